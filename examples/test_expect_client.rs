@@ -10,15 +10,15 @@ fn print_values(received: &[u8]) {
     }
 }
 
-fn main() -> Result<(), Box<dyn (::std::error::Error)>> {
-    let ip = std::env::args().skip(1).next().unwrap_or(format!("127.0.0.1"));
+fn main() -> Result<(), Box<dyn ::std::error::Error>> {
+    let ip = std::env::args().nth(1).unwrap_or("127.0.0.1".to_string());
     println!("Connecting to {}...", ip);
     let mut client = reliudp::RUdpSocket::connect((ip, 61243)).expect("Failed to create client");
 
     let mut received: Vec<u8> = vec!();
     let mut finished = false;
 
-    let message_seq_id = client.send_data(std::sync::Arc::new([0; 15]), MessageType::KeyMessage, Default::default());
+    let message_seq_id = client.send_data(std::sync::Arc::new([0; 15]), MessageType::KeyMessage, Default::default())?;
 
     for i in 0..5000 {
         client.next_tick()?;
@@ -28,7 +28,7 @@ fn main() -> Result<(), Box<dyn (::std::error::Error)>> {
         }
         for client_event in client.drain_events() {
             if let SocketEvent::Data(d) = client_event {
-                let v = d.as_ref().get(0).unwrap();
+                let v = d.as_ref().first().unwrap();
 
                 if received.contains(v) {
                     panic!("Value {} has already been received", v);
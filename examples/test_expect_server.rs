@@ -6,7 +6,7 @@ fn generate_really_big_message(i: u8) -> Arc<[u8]> {
     really_big_message
 }
 
-fn main() -> Result<(), Box<dyn (::std::error::Error)>> {
+fn main() -> Result<(), Box<dyn ::std::error::Error>> {
     let mut server = reliudp::RUdpServer::new("0.0.0.0:61243").expect("Failed to create server");
 
     let mut can_start = false;
@@ -16,12 +16,9 @@ fn main() -> Result<(), Box<dyn (::std::error::Error)>> {
         server.next_tick()?;
         for server_event in server.drain_events() {
             println!("Server: Incoming event {:?}", server_event);
-            match server_event.1 {
-                reliudp::SocketEvent::Connected => {
-                    println!("Client connected! Starting.");
-                    can_start = true
-                },
-                _ => {},
+            if let reliudp::SocketEvent::Connected = server_event.1 {
+                println!("Client connected! Starting.");
+                can_start = true
             }
         }
 
@@ -6,16 +6,13 @@ pub type Acks<D> = Vec<(u32, Ack<D>)>;
 #[derive(Debug, Clone)]
 pub struct Ack<D: AsRef<[u8]> + 'static>(D);
 
+/// `frag_total` is the id of the last fragment, so there is one bit for each of the `frag_total + 1` fragments.
 fn ack_size_from_frag_total(frag_total: u8) -> usize {
-    if frag_total % 8 == 0 {
-        (frag_total / 8) as usize
-    } else {
-        (frag_total / 8 + 1) as usize
-    }
+    frag_total as usize / 8 + 1
 }
 
 #[cfg(test)]
-pub (self) fn frag_ids_received_from_ack<I: Iterator<Item=u8>>(ack_bytes: I, frag_total: u8) -> impl Iterator<Item=u8> {
+fn frag_ids_received_from_ack<I: Iterator<Item=u8>>(ack_bytes: I, frag_total: u8) -> impl Iterator<Item=u8> {
     ack_bytes.enumerate().flat_map(move |(index, bits): (usize, u8)| {
         (0..8).filter_map(move |bit_index| {
             debug_assert!(index < 32); // 31 * 8 + 7 is max value at most in u8
@@ -34,7 +31,7 @@ pub (self) fn frag_ids_received_from_ack<I: Iterator<Item=u8>>(ack_bytes: I, fra
     })
 }
 
-pub (self) fn frag_ids_missing_from_ack<'a, I: Iterator<Item=u8> + 'a>(ack_bytes: I, frag_total: u8) -> impl Iterator<Item=u8> + 'a {
+fn frag_ids_missing_from_ack<'a, I: Iterator<Item=u8> + 'a>(ack_bytes: I, frag_total: u8) -> impl Iterator<Item=u8> + 'a {
     ack_bytes.enumerate().flat_map(move |(index, bits): (usize, u8)| {
         (0..8).filter_map(move |bit_index| {
             debug_assert!(index < 32); // 31 * 8 + 7 is max value at most in u8
@@ -94,6 +91,10 @@ impl<D: AsRef<[u8]> + 'static> Ack<D> {
         frag_ids_missing_from_ack(self.0.as_ref().iter().cloned(), frag_total)
     }
 
+    pub (crate) fn as_bytes(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+
     pub fn into_inner(self) -> D {
         self.0
     }
@@ -101,7 +102,7 @@ impl<D: AsRef<[u8]> + 'static> Ack<D> {
 
 #[test]
 fn ack_ser() {
-    let frag_ids = vec!(1u8, 2u8, 8u8, 9u8);
+    let frag_ids = [1u8, 2u8, 8u8, 9u8];
     let frag_total: u8 = 15;
     let ack = Ack::create_from_frag_ids(frag_ids.iter().cloned(), frag_total);
 
@@ -110,7 +111,7 @@ fn ack_ser() {
 
 #[test]
 fn ack_missing() {
-    let frag_ids = vec!(1u8, 2u8, 8u8, 9u8);
+    let frag_ids = [1u8, 2u8, 8u8, 9u8];
     let frag_total: u8 = 15;
     let ack = Ack::create_from_frag_ids(frag_ids.iter().cloned(), frag_total);
     let missing: Vec<u8> = ack.into_missing_iter(frag_total).collect();
@@ -132,10 +133,10 @@ fn ack_deser() {
 
 #[test]
 fn ack_ser_deser() {
-    let vec1: Vec<u8> = (0..255u8).into_iter().collect();
+    let vec1: Vec<u8> = (0..255u8).collect();
     let frag_total: u8 = 254;
-    let vec2: Vec<u8> = (0..255u8).into_iter().step_by(2).collect();
-    let vec3: Vec<u8> = (0..255u8).into_iter().step_by(3).collect();
+    let vec2: Vec<u8> = (0..255u8).step_by(2).collect();
+    let vec3: Vec<u8> = (0..255u8).step_by(3).collect();
     
     let ack1 = Ack::create_from_frag_ids(vec1.iter().cloned(), frag_total);
     let ack2 = Ack::create_from_frag_ids(vec2.iter().cloned(), frag_total);
@@ -148,4 +149,17 @@ fn ack_ser_deser() {
     assert_eq!(ack_vec1, vec1);
     assert_eq!(ack_vec2, vec2);
     assert_eq!(ack_vec3, vec3);
-}
\ No newline at end of file
+}
+
+#[test]
+fn ack_last_frag_id_multiple_of_8() {
+    for frag_total in [0u8, 8, 16, 32, 248] {
+        let ack = Ack::create_from_frag_ids(::std::iter::once(frag_total), frag_total);
+        assert_eq!(ack.as_bytes().len(), frag_total as usize / 8 + 1);
+        let received: Vec<u8> = ack.into_iter(frag_total).collect();
+        assert_eq!(received, vec![frag_total]);
+
+        let complete = Ack::create_complete(frag_total);
+        assert_eq!(complete.into_missing_iter(frag_total).count(), 0);
+    }
+}
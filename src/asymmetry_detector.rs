@@ -0,0 +1,95 @@
+use std::time::{Duration, Instant};
+
+pub (crate) const DEFAULT_ASYMMETRY_WINDOW: Duration = Duration::from_secs(5);
+pub (crate) const DEFAULT_ASYMMETRY_MIN_INBOUND: u32 = 20;
+
+/// Detects when we keep hearing the remote, but the remote doesn't seem to hear us.
+///
+/// If some of our key messages are waiting for an ack, and we received at least `min_inbound` packets
+/// from the remote during `window` without getting a single ack, our packets are most likely dropped
+/// on their way to the remote.
+#[derive(Debug)]
+pub (crate) struct AsymmetryDetector {
+    pub window: Duration,
+    pub min_inbound: u32,
+
+    /// Start of the current observation window: last time we received an ack,
+    /// or the time we started waiting for one.
+    window_start: Instant,
+    /// Packets received from the remote since `window_start`
+    inbound_count: u32,
+    /// Whether or not the asymmetry has already been reported for the current window
+    reported: bool,
+}
+
+impl AsymmetryDetector {
+    pub fn new(now: Instant) -> AsymmetryDetector {
+        AsymmetryDetector {
+            window: DEFAULT_ASYMMETRY_WINDOW,
+            min_inbound: DEFAULT_ASYMMETRY_MIN_INBOUND,
+            window_start: now,
+            inbound_count: 0,
+            reported: false,
+        }
+    }
+
+    fn reset(&mut self, now: Instant) {
+        self.window_start = now;
+        self.inbound_count = 0;
+        self.reported = false;
+    }
+
+    /// Should be called when we start waiting for acks, while we weren't waiting for any before.
+    pub (crate) fn start_waiting(&mut self, now: Instant) {
+        self.reset(now);
+    }
+
+    /// Should be called for every packet received from the remote.
+    pub (crate) fn received_packet(&mut self) {
+        self.inbound_count = self.inbound_count.saturating_add(1);
+    }
+
+    /// Should be called for every ack received from the remote.
+    pub (crate) fn received_ack(&mut self, now: Instant) {
+        self.reset(now);
+    }
+
+    /// Returns true once per observation window, if the connection looks one-way.
+    pub (crate) fn check(&mut self, now: Instant, waiting_for_acks: bool) -> bool {
+        if !waiting_for_acks || self.reported {
+            return false;
+        }
+        if now >= self.window_start + self.window && self.inbound_count >= self.min_inbound {
+            self.reported = true;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[test]
+fn asymmetry_detected_once() {
+    let start = Instant::now();
+    let mut detector = AsymmetryDetector::new(start);
+    detector.start_waiting(start);
+    for _ in 0..DEFAULT_ASYMMETRY_MIN_INBOUND {
+        detector.received_packet();
+    }
+    assert!(!detector.check(start + DEFAULT_ASYMMETRY_WINDOW / 2, true));
+    assert!(!detector.check(start + DEFAULT_ASYMMETRY_WINDOW, false));
+    assert!(detector.check(start + DEFAULT_ASYMMETRY_WINDOW, true));
+    assert!(!detector.check(start + DEFAULT_ASYMMETRY_WINDOW * 2, true));
+
+    detector.received_ack(start + DEFAULT_ASYMMETRY_WINDOW * 2);
+    assert!(!detector.check(start + DEFAULT_ASYMMETRY_WINDOW * 3, true));
+}
+
+#[test]
+fn asymmetry_needs_inbound_traffic() {
+    let start = Instant::now();
+    let mut detector = AsymmetryDetector::new(start);
+    detector.received_packet();
+    // the remote is silent: this is a timeout, not an asymmetry
+    assert!(!detector.check(start + DEFAULT_ASYMMETRY_WINDOW * 2, true));
+}
@@ -0,0 +1,58 @@
+use crate::fragment::FragmentSizing;
+use crate::fragment_combiner::AckMode;
+use std::time::Duration;
+
+/// The settings in effect for a connection, as set by the `set_*` methods of `RUdpSocket`
+/// (or of the `RUdpServer` it belongs to) or left to their defaults.
+///
+/// See `RUdpSocket::config`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SocketConfig {
+    /// See `RUdpSocket::set_timeout_delay`
+    pub timeout_delay: Duration,
+    /// See `RUdpSocket::set_connect_timeout`
+    pub connect_timeout: Duration,
+    /// See `RUdpSocket::set_heartbeat_delay`
+    pub heartbeat_delay: Duration,
+    /// See `RUdpSocket::set_heartbeat_enabled`
+    pub heartbeat_enabled: bool,
+    /// See `RUdpSocket::set_adaptive_heartbeat`
+    pub adaptive_heartbeat: bool,
+    /// See `RUdpSocket::set_receive_priority_order`
+    pub receive_priority_order: bool,
+    /// Window of the asymmetry detection, see `RUdpSocket::set_asymmetry_threshold`
+    pub asymmetry_window: Duration,
+    /// Packets to receive during `asymmetry_window`, see `RUdpSocket::set_asymmetry_threshold`
+    pub asymmetry_min_inbound: u32,
+    /// See `RUdpSocket::set_end_grace_period`
+    pub end_grace_period: Duration,
+    /// None means no limit, see `RUdpSocket::set_max_connection_lifetime`
+    pub max_connection_lifetime: Option<Duration>,
+    /// None means no limit, see `RUdpSocket::set_max_bytes_per_tick`
+    pub max_bytes_per_tick: Option<usize>,
+    /// As bytes per second, None means no limit, see `RUdpSocket::set_max_retransmit_rate`
+    pub max_retransmit_rate: Option<usize>,
+    /// Threshold of malformed packets when strict mode is enabled, None when it is disabled.
+    /// See `RUdpSocket::set_strict_mode`
+    pub malformed_packet_threshold: Option<u32>,
+    /// See `RUdpSocket::set_ack_piggybacking`
+    pub ack_piggybacking: bool,
+    /// See `RUdpSocket::set_ack_mode`
+    pub ack_mode: AckMode,
+    /// See `RUdpSocket::set_verify_crc`
+    pub verify_crc: bool,
+    /// See `RUdpSocket::set_fragment_sizing`
+    pub fragment_sizing: FragmentSizing,
+    /// See `RUdpSocket::set_min_payload_size`
+    pub min_payload_size: usize,
+    /// See `RUdpSocket::set_send_priority_info`
+    pub send_priority_info: bool,
+    /// See `RUdpSocket::set_cleanup_delay`
+    pub cleanup_delay: Duration,
+    /// See `RUdpSocket::set_max_payload_size`
+    pub max_payload_size: usize,
+    /// None means no limit, see `RUdpSocket::set_reassembly_limit`
+    pub reassembly_limit: Option<usize>,
+    /// Share of the server's send budget, see `RUdpServer::set_remote_weight`
+    pub send_weight: u32,
+}
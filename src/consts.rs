@@ -18,12 +18,70 @@ pub (crate) const FRAG_DATA_START_BYTE: usize = PACKET_DATA_START_BYTE + FRAG_AD
 // We need to take into account ipv4 headers (max of 60bytes) and udp headers (8 bytes)
 // 1152 + 60 + 8 = 1220 is not too bad, because the "common" MTU for ipv6 is 1280.
 // Although we arguably could do better. Needs tweaking & testing if changed to a higher value.
+// It can be lowered per socket with `RUdpSocket::set_max_payload_size`, or raised up to `MAX_RCV_UDP_DATA_SIZE`
+// with `RUdpSocket::set_fragment_payload_size`.
 pub (crate) const MAX_UDP_MESSAGE_SIZE: usize = 1024 + 128 + FRAG_DATA_START_BYTE;
 
+// Size of the buffers datagrams are received in: a jumbo frame (MTU of 9000), minus the ipv4 (20 bytes) and udp (8 bytes) headers.
+// Anything larger is truncated, and dropped since its CRC doesn't match anymore.
+pub (crate) const MAX_RCV_UDP_DATA_SIZE: usize = 9000 - 20 - 8;
+
 pub (crate) const SEQ_DATA_CLEANUP_DELAY: std::time::Duration = std::time::Duration::from_millis(5000);
 
 // Since the frag_id max is 255, we can have at most 256 frags in a message.
 pub (crate) const MAX_FRAGMENTS_IN_MESSAGE: usize = 256;
 
+/// Largest message that can be sent with `send_data`, in bytes: the payload of the largest fragment,
+/// once the headers of the fragment are accounted for, times the number of fragments a message can hold.
+///
+/// This is the limit with the default payload size. A connection negotiating a smaller payload size
+/// accepts smaller messages only, and one raising it with `RUdpSocket::set_fragment_payload_size`
+/// accepts larger ones, see `RUdpSocket::max_message_size`.
+pub const MAX_MESSAGE_SIZE: usize = MAX_FRAGMENTS_IN_MESSAGE * (MAX_UDP_MESSAGE_SIZE - FRAG_DATA_START_BYTE);
+
+/// Smallest fragment payload size that can be configured or negotiated. Since a message holds at most
+/// `MAX_FRAGMENTS_IN_MESSAGE` fragments, it keeps messages of up to 64KB possible.
+pub (crate) const MIN_PAYLOAD_SIZE: usize = 256;
+
 /// Number of iterations we must wait to send the next ack since the last one.
-pub (crate) const ACK_SEND_INTERVAL: Duration = Duration::from_millis(50);
\ No newline at end of file
+pub (crate) const ACK_SEND_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Delay after which we send a Migrate packet again, if the remote still hasn't answered on our new address.
+pub (crate) const MIGRATE_RESEND_DELAY: Duration = Duration::from_millis(200);
+
+/// Number of times in a row a message must be resent without progress, while the remote keeps answering,
+/// before we assume its fragments are too large to go through.
+pub (crate) const MTU_BLACK_HOLE_RESENDS: u32 = 4;
+
+/// Number of datagrams from unknown sources kept for `drain_unknown`. Past that, the oldest ones are dropped.
+pub (crate) const MAX_UNKNOWN_DATAGRAMS: usize = 64;
+
+/// Data packets kept per address while waiting for its Syn, see `RUdpServer::set_pre_handshake_data_policy`.
+pub (crate) const MAX_PRE_HANDSHAKE_PACKETS: usize = 32;
+/// Addresses for which the server keeps data packets while waiting for their Syn.
+pub (crate) const MAX_PRE_HANDSHAKE_REMOTES: usize = 64;
+/// Data packets received before the Syn are dropped if the Syn doesn't come within this delay.
+pub (crate) const PRE_HANDSHAKE_DATA_TTL: Duration = Duration::from_secs(3);
+
+/// Number of resends and acknowledged messages after which the congestion factor is updated.
+pub (crate) const CONGESTION_SAMPLE_SIZE: u32 = 8;
+/// Share of resends among the samples above which the link is considered congested.
+pub (crate) const CONGESTION_LOSS_THRESHOLD: f64 = 0.5;
+/// The resend delays are multiplied by this when the link is congested...
+pub (crate) const CONGESTION_BACKOFF: f64 = 2.0;
+/// ... and brought back towards the delay of their priority by this much when it is not.
+pub (crate) const CONGESTION_RECOVERY_STEP: f64 = 0.25;
+/// Resend delays are never stretched more than this.
+pub (crate) const MAX_CONGESTION_FACTOR: f64 = 8.0;
+
+/// Key messages whose round trip is being measured at the same time. When more are sent, the oldest is forgotten.
+pub (crate) const MAX_WAITING_PINGS: usize = 16;
+/// A key message not acknowledged after this long is not used to measure the ping anymore.
+pub (crate) const PING_SAMPLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time between two ticks while `drain_and_terminate` waits for the remote to acknowledge everything.
+pub (crate) const DRAIN_TICK_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Number of ticks we retry to send a fragment that couldn't be sent the first time because
+/// the local send buffer was full. After that, the usual resend mechanism takes over.
+pub (crate) const MAX_BLOCKED_SEND_RETRIES: u8 = 3;
@@ -0,0 +1,47 @@
+use crate::udp_packet::UdpPacketError;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+/// Why a received packet was discarded. See `RUdpSocket::set_discard_hook`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiscardReason {
+    /// The packet could not be read: bad CRC, too small, invalid fragment layout, ...
+    InvalidPacket(UdpPacketError),
+    /// The packet was a batch, but its content could not be split into packets
+    MalformedBatch(UdpPacketError),
+    /// The packet did not come from the remote this socket is connected to
+    UnknownSource,
+    /// The packet came from an address without a connection, and it was not a Syn: it could not start one
+    UnexpectedData,
+    /// A Syn was received on a connection which is already established
+    SynWhileConnected,
+    /// A Syn was received, but the server refused the connection. See `RUdpServer::set_connection_filter`
+    /// and `RUdpServer::set_max_remotes`.
+    ConnectionRefused,
+}
+
+type DiscardCallback = dyn FnMut(SocketAddr, DiscardReason, &[u8]) + Send;
+
+/// Function called for every packet discarded, shared between a server and all its remotes.
+#[derive(Clone, Default)]
+pub (crate) struct DiscardHook(Option<Arc<Mutex<DiscardCallback>>>);
+
+impl ::std::fmt::Debug for DiscardHook {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "DiscardHook({})", if self.0.is_some() { "set" } else { "unset" })
+    }
+}
+
+impl DiscardHook {
+    pub (crate) fn new<F: FnMut(SocketAddr, DiscardReason, &[u8]) + Send + 'static>(f: F) -> DiscardHook {
+        DiscardHook(Some(Arc::new(Mutex::new(f))))
+    }
+
+    #[inline]
+    pub (crate) fn call(&self, remote_addr: SocketAddr, reason: DiscardReason, bytes: &[u8]) {
+        if let Some(f) = &self.0 {
+            let mut f = f.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            f(remote_addr, reason, bytes);
+        }
+    }
+}
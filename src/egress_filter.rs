@@ -0,0 +1,39 @@
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+type EgressCallback = dyn FnMut(&mut Vec<u8>, &mut SocketAddr) -> bool + Send;
+
+/// Function called with every outgoing datagram right before it is sent, shared between a server and all its remotes.
+///
+/// Only available with the `egress_filter` feature, see `RUdpSocket::set_egress_filter`.
+#[derive(Clone, Default)]
+pub (crate) struct EgressFilter(Option<Arc<Mutex<EgressCallback>>>);
+
+impl ::std::fmt::Debug for EgressFilter {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "EgressFilter({})", if self.0.is_some() { "set" } else { "unset" })
+    }
+}
+
+impl EgressFilter {
+    pub (crate) fn new<F: FnMut(&mut Vec<u8>, &mut SocketAddr) -> bool + Send + 'static>(f: F) -> EgressFilter {
+        EgressFilter(Some(Arc::new(Mutex::new(f))))
+    }
+
+    #[inline]
+    pub (crate) fn is_set(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Returns false if the datagram must be dropped.
+    #[inline]
+    pub (crate) fn call(&self, bytes: &mut Vec<u8>, addr: &mut SocketAddr) -> bool {
+        match &self.0 {
+            Some(f) => {
+                let mut f = f.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                f(bytes, addr)
+            },
+            None => true,
+        }
+    }
+}
@@ -2,14 +2,60 @@ use crate::misc::ClonableIterator;
 use crate::consts::*;
 use crate::fragment_generator::FragmentGenerator;
 
-const MAX_FRAGMENT_MESSAGE_SIZE: usize = MAX_UDP_MESSAGE_SIZE - FRAG_DATA_START_BYTE;
+pub (crate) const MAX_FRAGMENT_MESSAGE_SIZE: usize = MAX_UDP_MESSAGE_SIZE - FRAG_DATA_START_BYTE;
 
+/// Largest fragment payload a datagram we can receive holds, see `RUdpSocket::set_fragment_payload_size`.
+pub (crate) const MAX_RCV_FRAGMENT_MESSAGE_SIZE: usize = MAX_RCV_UDP_DATA_SIZE - FRAG_DATA_START_BYTE;
+
+/// Kind of message a fragment belongs to, as seen on the wire.
+///
+/// The receiver only sends acks for non-Forgettable fragments.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum FragmentMeta {
     Forgettable = 0,
     KeyExpirable = 1,
     Key = 2,
+    /// Never re-sent, but the receiver still sends an ack
+    ForgettableAcked = 3,
+    /// Same as KeyExpirable, but the receiver doesn't report it when it never completes
+    TryReliable = 4,
+}
+
+/// How a message is cut into fragments.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FragmentSizing {
+    /// Every fragment is as large as possible, and the last one holds whatever is left,
+    /// which may be very small. This is the default.
+    #[default]
+    Packed,
+    /// Fragments all have the same size, give or take a byte.
+    ///
+    /// A message is cut into the same number of fragments as with `Packed`, so the bandwidth used is about
+    /// the same, but there is no tiny last fragment.
+    Balanced,
+}
+
+/// Cuts a slice into `remaining_chunks` chunks of the same size, give or take a byte.
+#[derive(Debug, Clone)]
+struct BalancedChunks<'a> {
+    data: &'a [u8],
+    remaining_chunks: usize,
+}
+
+impl<'a> Iterator for BalancedChunks<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        if self.remaining_chunks == 0 {
+            return None;
+        }
+        let chunk_size = (self.data.len() + self.remaining_chunks - 1) / self.remaining_chunks;
+        let (chunk, rest) = self.data.split_at(chunk_size);
+        self.data = rest;
+        self.remaining_chunks -= 1;
+        Some(chunk)
+    }
 }
 
 /// A fragment is a destructed UdpPacket that can hold at most
@@ -26,7 +72,7 @@ pub struct Fragment<T: AsRef<[u8]>> {
 }
 
 impl<T: AsRef<[u8]>> Fragment<T> {
-    pub fn as_borrowed_frag<'a>(&'a self) -> Fragment<&'a T> {
+    pub fn as_borrowed_frag(&self) -> Fragment<&T> {
         Fragment {
             seq_id: self.seq_id,
             frag_id: self.frag_id,
@@ -37,7 +83,7 @@ impl<T: AsRef<[u8]>> Fragment<T> {
     }
 }
 
-impl<'a> Clone for Fragment<&'a [u8]> {
+impl Clone for Fragment<&[u8]> {
     fn clone(&self) -> Self {
         Fragment {
             seq_id: self.seq_id,
@@ -49,7 +95,7 @@ impl<'a> Clone for Fragment<&'a [u8]> {
     }
 }
 
-impl<'a> Fragment<&'a [u8]> {
+impl Fragment<&[u8]> {
     #[cfg(test)]
     pub fn into_boxed(self) -> Fragment<Box<[u8]>> {
         Fragment {
@@ -62,6 +108,17 @@ impl<'a> Fragment<&'a [u8]> {
     }
 }
 
+/// Reason why a set of fragments could not be re-assembled into a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub (crate) enum FragmentSetError {
+    /// Two fragments of the same set have the same frag_id
+    DuplicateFragId(u8),
+    /// A fragment has a frag_id higher than the number of fragments in the set
+    FragIdOutOfRange(u8),
+    /// Fragments of the same set do not agree on the frag_total
+    FragTotalMismatch,
+}
+
 /// Restore the data from multiple fragments
 ///
 /// This method accepts an iterator, but the iterator doesn't have to be sorted,
@@ -71,7 +128,7 @@ impl<'a> Fragment<&'a [u8]> {
 ///
 /// returns an error if the message couldn't be restored properly: a frag_id is higher than frag_total,
 /// 2 frag_id are the same, ...
-pub (crate) fn build_data_from_fragments<I, B>(fragments: I) -> Result<Box<[u8]>, ()> 
+pub (crate) fn build_data_from_fragments<I, B>(fragments: I) -> Result<Box<[u8]>, FragmentSetError> 
 where   B: AsRef<[u8]> + 'static,
         I: Iterator<Item = Fragment<B>> + ExactSizeIterator {
     // start with vec!(None; n) and for every fragment, replace None by Some(...)
@@ -82,8 +139,11 @@ where   B: AsRef<[u8]> + 'static,
     let mut total_data_size: usize = 0;
     for fragment in fragments {
         let frag_id = fragment.frag_id as usize;
-        if frag_id >= fragments_vec.len() || fragments_vec[frag_id].is_some() {
-            return Err(())
+        if frag_id >= fragments_vec.len() {
+            return Err(FragmentSetError::FragIdOutOfRange(fragment.frag_id))
+        };
+        if fragments_vec[frag_id].is_some() {
+            return Err(FragmentSetError::DuplicateFragId(fragment.frag_id))
         };
         total_data_size += fragment.data.as_ref().len();
         fragments_vec[frag_id] = Some(fragment);
@@ -133,7 +193,7 @@ fn build_data_from_fragments_fail_wrong_frag_id() {
     ];
 
     let e = build_data_from_fragments(fragments.into_iter()).unwrap_err();
-    assert_eq!(e, ());
+    assert_eq!(e, FragmentSetError::FragIdOutOfRange(5));
 }
 
 #[test]
@@ -144,16 +204,20 @@ fn build_data_from_fragments_fail_duplicate_frag_id() {
     ];
 
     let e = build_data_from_fragments(fragments.into_iter()).unwrap_err();
-    assert_eq!(e, ());
+    assert_eq!(e, FragmentSetError::DuplicateFragId(0));
 }
 
-pub (crate) fn build_fragments_from_bytes<'a>(data: &'a [u8], seq_id: u32, frag_meta: FragmentMeta) -> Result<(Box<dyn 'a + ClonableIterator<Item = Fragment<&'a [u8]>>>, u8), ()> {
+pub (crate) type FragmentsIter<'a> = Box<dyn 'a + ClonableIterator<'a, Item = Fragment<&'a [u8]>>>;
+
+/// `max_fragment_size` is the most data a fragment may hold, at most `MAX_RCV_FRAGMENT_MESSAGE_SIZE`.
+pub (crate) fn build_fragments_from_bytes(data: &[u8], seq_id: u32, frag_meta: FragmentMeta, sizing: FragmentSizing, max_fragment_size: usize) -> Result<(FragmentsIter<'_>, u8), ()> {
     if data.is_empty() {
         panic!("build_fragments_from_data cannot build fragments if the message is empty");
     }
+    debug_assert!(max_fragment_size > 0 && max_fragment_size <= MAX_RCV_FRAGMENT_MESSAGE_SIZE);
 
-    let mut fragments_count = data.len() / MAX_FRAGMENT_MESSAGE_SIZE;
-    if data.len() % MAX_FRAGMENT_MESSAGE_SIZE != 0 {
+    let mut fragments_count = data.len() / max_fragment_size;
+    if data.len() % max_fragment_size != 0 {
         // if we can fix message into boxes exactly that's great! otherwise it means that there is a left-over,
         // and we should build the left over accordingly as well.
         fragments_count += 1;
@@ -163,15 +227,24 @@ pub (crate) fn build_fragments_from_bytes<'a>(data: &'a [u8], seq_id: u32, frag_
         return Err(())
     }
     let frag_total = (fragments_count - 1) as u8;
-    let iter = data.chunks(MAX_FRAGMENT_MESSAGE_SIZE);
-    Ok((Box::new(FragmentGenerator::new(iter, seq_id, frag_total, frag_meta)), frag_total))
+    let fragments: FragmentsIter<'_> = match sizing {
+        FragmentSizing::Packed => {
+            let iter = data.chunks(max_fragment_size);
+            Box::new(FragmentGenerator::new(iter, seq_id, frag_total, frag_meta))
+        },
+        FragmentSizing::Balanced => {
+            let iter = BalancedChunks { data, remaining_chunks: fragments_count };
+            Box::new(FragmentGenerator::new(iter, seq_id, frag_total, frag_meta))
+        },
+    };
+    Ok((fragments, frag_total))
 }
 
 #[test]
 fn build_rebuild_data() {
     let seq_id: u32 = 1;
     let data = vec!(0; 1024);
-    let (frags_iter_boxed, _frag_total) = build_fragments_from_bytes(data.as_ref(), seq_id, FragmentMeta::Key).unwrap();
+    let (frags_iter_boxed, _frag_total) = build_fragments_from_bytes(data.as_ref(), seq_id, FragmentMeta::Key, FragmentSizing::Packed, MAX_FRAGMENT_MESSAGE_SIZE).unwrap();
     let frags: Vec<Fragment<Box<[u8]>>> = frags_iter_boxed.map(|f| f.into_boxed()).collect();
     let new_data = build_data_from_fragments(frags.into_iter()).unwrap();
     assert_eq!(new_data.len(), data.len());
@@ -181,7 +254,7 @@ fn build_rebuild_data() {
 fn build_one_frag_from_data() {
     let seq_id: u32 = 1;
     let data = vec!(0; 1024);
-    let (mut frags_iter, frag_total) = build_fragments_from_bytes(data.as_ref(), seq_id, FragmentMeta::KeyExpirable).unwrap();
+    let (mut frags_iter, frag_total) = build_fragments_from_bytes(data.as_ref(), seq_id, FragmentMeta::KeyExpirable, FragmentSizing::Packed, MAX_FRAGMENT_MESSAGE_SIZE).unwrap();
     let frag = frags_iter.next().unwrap();
     assert!(frags_iter.next().is_none()); 
     assert_eq!(frag.data.len(), 1024);
@@ -196,7 +269,7 @@ fn build_one_frag_from_data() {
 fn build_multiple_frags_from_data() {
     let seq_id: u32 = 1;
     let data = vec!(0; 2048);
-    let (mut frags_iter, frag_total) = build_fragments_from_bytes(data.as_ref(), seq_id, FragmentMeta::KeyExpirable).unwrap();
+    let (mut frags_iter, frag_total) = build_fragments_from_bytes(data.as_ref(), seq_id, FragmentMeta::KeyExpirable, FragmentSizing::Packed, MAX_FRAGMENT_MESSAGE_SIZE).unwrap();
     let frag_1 = frags_iter.next().unwrap();
     let frag_2 = frags_iter.next().unwrap();
     assert!(frags_iter.next().is_none()); 
@@ -215,5 +288,18 @@ fn build_multiple_frags_from_data() {
 fn build_frags_from_data_fail() {
     let seq_id: u32 = 1;
     let data = vec!(0; MAX_FRAGMENTS_IN_MESSAGE * MAX_FRAGMENT_MESSAGE_SIZE + 1);
-    assert!(build_fragments_from_bytes(data.as_ref(), seq_id, FragmentMeta::KeyExpirable).is_err());
-}
\ No newline at end of file
+    assert!(build_fragments_from_bytes(data.as_ref(), seq_id, FragmentMeta::KeyExpirable, FragmentSizing::Packed, MAX_FRAGMENT_MESSAGE_SIZE).is_err());
+}
+
+#[test]
+fn build_balanced_fragments() {
+    let data: Vec<u8> = (0..(MAX_FRAGMENT_MESSAGE_SIZE * 2 + 10)).map(|i| i as u8).collect();
+    let (frags_iter, frag_total) = build_fragments_from_bytes(data.as_ref(), 1, FragmentMeta::Key, FragmentSizing::Balanced, MAX_FRAGMENT_MESSAGE_SIZE).unwrap();
+    let frags: Vec<Fragment<Box<[u8]>>> = frags_iter.map(|f| f.into_boxed()).collect();
+    assert_eq!(frag_total, 2);
+    let sizes: Vec<usize> = frags.iter().map(|f| f.data.len()).collect();
+    assert!(sizes.iter().max().unwrap() - sizes.iter().min().unwrap() <= 1);
+    assert!(sizes.iter().all(|size| *size <= MAX_FRAGMENT_MESSAGE_SIZE));
+    let new_data = build_data_from_fragments(frags.into_iter()).unwrap();
+    assert_eq!(&*new_data, &*data);
+}
@@ -2,9 +2,10 @@ use hashbrown::HashMap;
 use std::collections::VecDeque;
 use itertools::Itertools;
 use crate::ack::{Acks, Ack};
-use crate::fragment::{Fragment, build_data_from_fragments};
+use crate::fragment::{Fragment, FragmentSetError, build_data_from_fragments};
 use crate::fragment::FragmentMeta;
 use std::time::{Instant, Duration};
+use crate::rudp::{ReceivedData, MessagePriority};
 
 pub (crate) trait FragmentDataRef: ::std::fmt::Debug + AsRef<[u8]> + 'static {}
 
@@ -35,8 +36,15 @@ pub (crate) struct FragmentSet<B: FragmentDataRef> {
 
     pub (crate) last_received: Instant,
 
+    /// When the first fragment of this set was received
+    pub (crate) created_at: Instant,
+
     /// Acks sent since last update. Resets whenver new fragments are received.
     pub (crate) acks_sent_count: u32,
+
+    /// Largest difference seen between the order in which a fragment arrived and its frag_id.
+    /// 0 means every fragment arrived in order.
+    pub (crate) max_reorder_gap: u8,
 }
 
 impl<B: FragmentDataRef> FragmentSet<B> {
@@ -63,8 +71,10 @@ impl<B: FragmentDataRef> FragmentSet<B> {
             fragment_meta: frag_meta, 
             state: FragmentSetState::Incomplete { fragments: HashMap::with_capacity_and_hasher(frag_total, Default::default()) },
             last_sent_ack: None,
+            created_at: now,
             last_received: now,
             acks_sent_count: 0,
+            max_reorder_gap: 0,
         }
     }
 
@@ -75,7 +85,9 @@ impl<B: FragmentDataRef> FragmentSet<B> {
                 Ack::create_complete(*frag_total)
             },
             FragmentSetState::Incomplete { fragments } => {
-                let frag_total = fragments.values().next().unwrap().frag_total;
+                // fragments lying about their frag_total are only dropped once the set is complete,
+                // the largest one leaves room for every frag_id until then
+                let frag_total = fragments.values().map(|f| f.frag_total).max().unwrap_or(0);
                 let frag_ids_iter = fragments.keys().cloned();
                 // println!("Generating incomplete ack seq_id={:?} ({:?}/{:?})", self.seq_id, frag_ids_iter.size_hint().0, frag_total as usize + 1);
                 Ack::create_from_frag_ids(frag_ids_iter, frag_total)
@@ -83,6 +95,17 @@ impl<B: FragmentDataRef> FragmentSet<B> {
         }
     }
 
+    /// Returns how many fragments are still needed to complete this set, or None if it is already complete.
+    pub (crate) fn missing_fragment_count(&self) -> Option<usize> {
+        match &self.state {
+            FragmentSetState::Complete(..) => None,
+            FragmentSetState::Incomplete { fragments } => {
+                let frag_total = fragments.values().next().map(|f| f.frag_total as usize).unwrap_or(0);
+                Some((frag_total + 1).saturating_sub(fragments.len()))
+            },
+        }
+    }
+
     pub (crate) fn send_ack(&mut self, now: Instant) {
         self.last_sent_ack = Some(now);
         self.acks_sent_count += 1;
@@ -98,6 +121,16 @@ impl<B: FragmentDataRef> FragmentSet<B> {
         self.fragment_meta != FragmentMeta::Forgettable
     }
 
+    /// Number of acks we send every time we receive new fragments for this set.
+    #[inline]
+    pub (crate) fn max_acks_sent(&self) -> u32 {
+        match self.fragment_meta {
+            // the sender won't re-send anything anyway, a single ack is enough for best effort
+            FragmentMeta::ForgettableAcked => 1,
+            _ => 2,
+        }
+    }
+
     /// Should the set be removed because no more data will arrive and we can't send ack
     /// for it anymore
     #[inline]
@@ -109,7 +142,7 @@ impl<B: FragmentDataRef> FragmentSet<B> {
             FragmentSetState::Incomplete { .. } => {
                 match self.fragment_meta {
                     // a second expiry
-                    FragmentMeta::Forgettable => now >= self.last_received + Duration::from_secs(10),
+                    FragmentMeta::Forgettable | FragmentMeta::ForgettableAcked => now >= self.last_received + Duration::from_secs(10),
                     // 50 seconds expiry for key messages
                     _ => now >= self.last_received + Duration::from_secs(60),
                 }
@@ -118,14 +151,69 @@ impl<B: FragmentDataRef> FragmentSet<B> {
     }
 }
 
+/// When we acknowledge the fragments we receive.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AckMode {
+    /// Acks are sent as fragments arrive, even if the message is incomplete, so that the sender
+    /// only re-sends the missing fragments. This is the default.
+    #[default]
+    Incremental,
+    /// Acks are only sent once a message is complete.
+    ///
+    /// This drastically reduces what we send back on a link with a very small upload, but the sender
+    /// re-sends the whole message when its resend delay is up, instead of only the missing fragments.
+    CompleteOnly,
+}
+
+/// Number of incoming messages that were discarded because their fragments were inconsistent,
+/// sorted by reason.
+///
+/// A well-behaved remote should never trigger any of these: a non-zero value
+/// usually means the remote is buggy or malicious.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CorruptedSetsStats {
+    /// Two fragments of the same message had the same frag_id
+    pub duplicate_frag_id: u64,
+    /// A fragment had a frag_id higher than the number of fragments of its message
+    pub frag_id_out_of_range: u64,
+    /// Fragments of the same message did not agree on the total number of fragments
+    pub frag_total_mismatch: u64,
+}
+
+impl CorruptedSetsStats {
+    fn record(&mut self, error: FragmentSetError) {
+        match error {
+            FragmentSetError::DuplicateFragId(_) => self.duplicate_frag_id += 1,
+            FragmentSetError::FragIdOutOfRange(_) => self.frag_id_out_of_range += 1,
+            FragmentSetError::FragTotalMismatch => self.frag_total_mismatch += 1,
+        }
+    }
+
+    /// Total number of discarded messages, all reasons combined
+    pub fn total(&self) -> u64 {
+        self.duplicate_frag_id + self.frag_id_out_of_range + self.frag_total_mismatch
+    }
+}
+
 #[derive(Debug)]
 pub (crate) struct FragmentCombiner<B: FragmentDataRef> {
     // TODO: Against DOS attacks, we should make this a VecDeque of small size and get rid
     // of the old stuff automatically.
     pub (crate) pending_fragments: HashMap<u32, FragmentSet<B>>,
 
-    // (seq_id, data)
-    pub (crate) out_messages: VecDeque<(u32, Box<[u8]>)>,
+    pub (crate) out_messages: VecDeque<ReceivedData>,
+
+    pub (crate) corrupted_sets: CorruptedSetsStats,
+
+    /// Incomplete sets that were removed because they were stale: (seq_id, received, frag_total)
+    pub (crate) dropped_sets: VecDeque<(u32, usize, usize)>,
+
+    pub (crate) ack_mode: AckMode,
+
+    /// Bytes held by the fragments of incomplete sets
+    pub (crate) buffered_bytes: usize,
+    /// Once `buffered_bytes` goes above that, the oldest incomplete sets are dropped. None means no limit.
+    pub (crate) reassembly_limit: Option<usize>,
 }
 
 impl<B: FragmentDataRef> FragmentCombiner<B> {
@@ -133,6 +221,11 @@ impl<B: FragmentDataRef> FragmentCombiner<B> {
         FragmentCombiner {
             pending_fragments: HashMap::default(),
             out_messages: VecDeque::new(),
+            corrupted_sets: CorruptedSetsStats::default(),
+            dropped_sets: VecDeque::new(),
+            ack_mode: AckMode::default(),
+            buffered_bytes: 0,
+            reassembly_limit: None,
         }
     }
 
@@ -142,27 +235,57 @@ impl<B: FragmentDataRef> FragmentCombiner<B> {
     ///
     /// Returns an Error if all the fragments do not have the same frag_total,
     /// or if "build_message_from_fragments" encountered an error
-    fn transform_message(&mut self, seq_id: u32, now: Instant) -> Result<(), ()> {
+    fn transform_message(&mut self, seq_id: u32, now: Instant) -> Result<(), FragmentSetError> {
         if let Some(fragment_set) = self.pending_fragments.get_mut(&seq_id) {
 
             let fragments = fragment_set.complete(now);
+            self.buffered_bytes -= fragments.values().map(|f| f.data.as_ref().len()).sum::<usize>();
             if !fragments.values().map(|f| f.frag_total).all_equal() {
-                return Err(())
+                return Err(FragmentSetError::FragTotalMismatch)
             }
+            let frag_count = fragments.len() as u16;
+            let max_reorder_gap = fragment_set.max_reorder_gap;
+            let first_received_at = fragment_set.created_at;
             let message = build_data_from_fragments(fragments.into_iter().map(|(_k, v)| v))?;
 
             // build_data_from_fragments with an IntoIterator with just the values
-            self.out_messages.push_back((seq_id, message));
+            self.out_messages.push_back(ReceivedData {
+                data: message,
+                seq_id,
+                frag_count,
+                max_reorder_gap,
+                priority: MessagePriority::Normal,
+                first_received_at,
+                reassembly_time: now.saturating_duration_since(first_received_at),
+            });
             Ok(())
         } else {
             panic!("seq_id {} does not exist in fragment_combiner.fragments", seq_id);
         }
     }
 
-    pub fn next_out_message(&mut self) -> Option<(u32, Box<[u8]>)> {
+    /// Returns how many fragments are still needed to complete the message `seq_id`.
+    ///
+    /// Returns None if we haven't received any fragment for this message, or if it is already complete.
+    pub fn missing_fragment_count(&self, seq_id: u32) -> Option<usize> {
+        self.pending_fragments.get(&seq_id).and_then(FragmentSet::missing_fragment_count)
+    }
+
+    /// Returns true if we received some fragments of the message `seq_id`, but not all of them yet.
+    pub fn is_receiving(&self, seq_id: u32) -> bool {
+        self.pending_fragments.get(&seq_id).is_some_and(|set| matches!(set.state, FragmentSetState::Incomplete { .. }))
+    }
+
+    pub fn next_out_message(&mut self) -> Option<ReceivedData> {
         self.out_messages.pop_front()
     }
 
+    /// Returns the next incomplete message that was abandoned because it was stale,
+    /// as (seq_id, received fragments, total fragments)
+    pub fn next_dropped_set(&mut self) -> Option<(u32, usize, usize)> {
+        self.dropped_sets.pop_front()
+    }
+
     /// Push a fragment into the internal queue.
     ///
     /// If the fragment is the last to arrive
@@ -185,7 +308,16 @@ impl<B: FragmentDataRef> FragmentCombiner<B> {
             // because it didn't receive the ack on time.
             if let FragmentSetState::Incomplete { ref mut fragments } = fragment_set.state {
                 fragment_set.acks_sent_count = 0;
-                fragments.insert(fragment.frag_id, fragment);
+                if !fragments.contains_key(&fragment.frag_id) {
+                    // there can be at most 256 fragments, so the arrival index fits in a u8
+                    let arrival_index = fragments.len() as u8;
+                    let gap = arrival_index.abs_diff(fragment.frag_id);
+                    fragment_set.max_reorder_gap = fragment_set.max_reorder_gap.max(gap);
+                }
+                self.buffered_bytes += fragment.data.as_ref().len();
+                if let Some(old_fragment) = fragments.insert(fragment.frag_id, fragment) {
+                    self.buffered_bytes -= old_fragment.data.as_ref().len();
+                }
                 // try to transform fragments into a message, because we have enough of them here
                 // if len() > frag_total + 1, that means that there are too many messages!
                 // This can only happen when a packet "lied" about its frag_total.
@@ -200,11 +332,48 @@ impl<B: FragmentDataRef> FragmentCombiner<B> {
         };
 
         if try_transform {
-            if let Err(()) = self.transform_message(seq_id, now) {
+            if let Err(error) = self.transform_message(seq_id, now) {
                 // If we fail to transform a message (set is corrupted), we want to remove it.
-                log::warn!("set seq_id={} is corrupted", seq_id);
+                log::warn!("set seq_id={} is corrupted: {:?}", seq_id, error);
+                self.corrupted_sets.record(error);
                 self.pending_fragments.remove(&seq_id).expect("transform message failed because seq_id is corrupted, but seq_id is already removed. This is a bug.");
             }
+        } else {
+            self.enforce_reassembly_limit();
+        }
+    }
+
+    /// Drops the oldest incomplete sets until we hold no more than `reassembly_limit` bytes.
+    fn enforce_reassembly_limit(&mut self) {
+        let limit = match self.reassembly_limit {
+            Some(limit) => limit,
+            None => return,
+        };
+        while self.buffered_bytes > limit {
+            let oldest = self.pending_fragments.values()
+                .filter(|set| matches!(set.state, FragmentSetState::Incomplete { .. }))
+                .min_by_key(|set| set.created_at)
+                .map(|set| set.seq_id);
+            match oldest {
+                Some(seq_id) => {
+                    log::warn!("holding {} bytes for reassembly, above the limit of {}: dropping incomplete set seq_id={}", self.buffered_bytes, limit, seq_id);
+                    self.drop_incomplete_set(seq_id);
+                },
+                None => break,
+            }
+        }
+    }
+
+    /// Removes an incomplete set and reports it as dropped.
+    fn drop_incomplete_set(&mut self, seq_id: u32) {
+        if let Some(FragmentSet { state: FragmentSetState::Incomplete { fragments }, fragment_meta, .. }) = self.pending_fragments.remove(&seq_id) {
+            let frag_total = fragments.values().next().map(|f| f.frag_total as usize + 1).unwrap_or(0);
+            log::debug!("dropping incomplete set seq_id={} ({}/{} fragments received)", seq_id, fragments.len(), frag_total);
+            self.buffered_bytes -= fragments.values().map(|f| f.data.as_ref().len()).sum::<usize>();
+            // the sender told us it may give up on this one
+            if fragment_meta != FragmentMeta::TryReliable {
+                self.dropped_sets.push_back((seq_id, fragments.len(), frag_total));
+            }
         }
     }
 
@@ -216,7 +385,11 @@ impl<B: FragmentDataRef> FragmentCombiner<B> {
                 acks_to_remove.push(*seq_id);
                 continue;
             }
-            let should_send_ack: bool = if fragment_set.can_send_ack() && fragment_set.acks_sent_count < 2 {
+            let ack_allowed = match self.ack_mode {
+                AckMode::Incremental => true,
+                AckMode::CompleteOnly => matches!(fragment_set.state, FragmentSetState::Complete(..)),
+            };
+            let should_send_ack: bool = if ack_allowed && fragment_set.can_send_ack() && fragment_set.acks_sent_count < fragment_set.max_acks_sent() {
                 match fragment_set.last_sent_ack {
                     Some(last_iter) => {
                         debug_assert!(now > last_iter);
@@ -234,7 +407,12 @@ impl<B: FragmentDataRef> FragmentCombiner<B> {
             }
         }
         for seq_id in acks_to_remove {
-            self.pending_fragments.remove(&seq_id);
+            if matches!(self.pending_fragments.get(&seq_id), Some(FragmentSet { state: FragmentSetState::Incomplete { .. }, .. })) {
+                log::debug!("set seq_id={} is stale", seq_id);
+                self.drop_incomplete_set(seq_id);
+            } else {
+                self.pending_fragments.remove(&seq_id);
+            }
         }
         acks_to_send
     }
@@ -257,7 +435,142 @@ fn fragment_combiner_success() {
     }
 
     let out_message = fragment_combiner.next_out_message().unwrap();
-    assert_eq!(out_message.1.as_ref(), &[64, 64]);
+    assert_eq!(out_message.data.as_ref(), &[64, 64]);
+    assert_eq!(out_message.seq_id, 7);
+    assert_eq!(out_message.frag_count, 1);
     let out_message = fragment_combiner.next_out_message().unwrap();
-    assert_eq!(out_message.1.as_ref(), &[1, 2, 3, 4, 5, 6, 7, 8, 9]);
-}
\ No newline at end of file
+    assert_eq!(out_message.data.as_ref(), &[1, 2, 3, 4, 5, 6, 7, 8, 9]);
+    assert_eq!(out_message.seq_id, 5);
+    assert_eq!(out_message.frag_count, 3);
+}
+
+#[test]
+fn fragment_combiner_frag_total_mismatch() {
+    let fragments: Vec<Fragment<Box<[u8]>>> = vec![
+        Fragment { seq_id: 3, frag_id: 0, frag_total: 3, frag_meta: FragmentMeta::Key, data: Box::new([1, 2]) },
+        Fragment { seq_id: 3, frag_id: 1, frag_total: 2, frag_meta: FragmentMeta::Key, data: Box::new([3, 4]) },
+        Fragment { seq_id: 3, frag_id: 2, frag_total: 2, frag_meta: FragmentMeta::Key, data: Box::new([5, 6]) },
+    ];
+    let mut fragment_combiner = FragmentCombiner::new();
+    for fragment in fragments {
+        fragment_combiner.push(fragment, Instant::now());
+    }
+
+    assert!(fragment_combiner.next_out_message().is_none());
+    assert!(fragment_combiner.pending_fragments.is_empty());
+    assert_eq!(fragment_combiner.corrupted_sets.frag_total_mismatch, 1);
+    assert_eq!(fragment_combiner.corrupted_sets.total(), 1);
+}
+
+#[test]
+fn fragment_combiner_ack_with_frag_total_mismatch() {
+    let mut fragment_combiner: FragmentCombiner<Box<[u8]>> = FragmentCombiner::new();
+    fragment_combiner.push(Fragment { seq_id: 3, frag_id: 0, frag_total: 2, frag_meta: FragmentMeta::Key, data: Box::new([1, 2]) }, Instant::now());
+    fragment_combiner.push(Fragment { seq_id: 3, frag_id: 17, frag_total: 20, frag_meta: FragmentMeta::Key, data: Box::new([3, 4]) }, Instant::now());
+
+    let ack = fragment_combiner.pending_fragments[&3].generate_ack();
+    assert_eq!(ack.into_iter(20).collect::<Vec<u8>>(), vec![0, 17]);
+}
+
+#[test]
+fn fragment_combiner_missing_fragment_count() {
+    let mut fragment_combiner: FragmentCombiner<Box<[u8]>> = FragmentCombiner::new();
+    assert_eq!(fragment_combiner.missing_fragment_count(5), None);
+
+    fragment_combiner.push(Fragment { seq_id: 5, frag_id: 2, frag_total: 3, frag_meta: FragmentMeta::Key, data: Box::new([1, 2]) }, Instant::now());
+    assert_eq!(fragment_combiner.missing_fragment_count(5), Some(3));
+    fragment_combiner.push(Fragment { seq_id: 5, frag_id: 0, frag_total: 3, frag_meta: FragmentMeta::Key, data: Box::new([1, 2]) }, Instant::now());
+    fragment_combiner.push(Fragment { seq_id: 5, frag_id: 0, frag_total: 3, frag_meta: FragmentMeta::Key, data: Box::new([1, 2]) }, Instant::now());
+    assert_eq!(fragment_combiner.missing_fragment_count(5), Some(2));
+    fragment_combiner.push(Fragment { seq_id: 5, frag_id: 1, frag_total: 3, frag_meta: FragmentMeta::Key, data: Box::new([1, 2]) }, Instant::now());
+    assert!(fragment_combiner.is_receiving(5));
+    assert!(!fragment_combiner.is_receiving(6));
+    fragment_combiner.push(Fragment { seq_id: 5, frag_id: 3, frag_total: 3, frag_meta: FragmentMeta::Key, data: Box::new([1, 2]) }, Instant::now());
+    assert_eq!(fragment_combiner.missing_fragment_count(5), None);
+    assert!(!fragment_combiner.is_receiving(5));
+}
+
+#[test]
+fn fragment_combiner_reorder_gap() {
+    let mut fragment_combiner: FragmentCombiner<Box<[u8]>> = FragmentCombiner::new();
+    let now = Instant::now();
+    for (seq_id, frag_ids) in [(1u32, [0u8, 1, 2, 3]), (2, [3, 1, 2, 0])] {
+        for frag_id in frag_ids {
+            fragment_combiner.push(Fragment { seq_id, frag_id, frag_total: 3, frag_meta: FragmentMeta::Key, data: Box::new([frag_id]) }, now);
+        }
+    }
+    let in_order = fragment_combiner.next_out_message().unwrap();
+    assert_eq!(in_order.max_reorder_gap, 0);
+    let reordered = fragment_combiner.next_out_message().unwrap();
+    assert_eq!(&*reordered.data, &[0, 1, 2, 3]);
+    assert_eq!(reordered.max_reorder_gap, 3);
+}
+
+#[test]
+fn fragment_combiner_stale_set_dropped() {
+    let mut fragment_combiner: FragmentCombiner<Box<[u8]>> = FragmentCombiner::new();
+    let now = Instant::now();
+    fragment_combiner.push(Fragment { seq_id: 7, frag_id: 1, frag_total: 3, frag_meta: FragmentMeta::Key, data: Box::new([1, 2]) }, now);
+    fragment_combiner.tick(now + Duration::from_secs(1));
+    assert_eq!(fragment_combiner.next_dropped_set(), None);
+    fragment_combiner.tick(now + Duration::from_secs(61));
+    assert_eq!(fragment_combiner.next_dropped_set(), Some((7, 1, 4)));
+    assert_eq!(fragment_combiner.missing_fragment_count(7), None);
+}
+
+#[test]
+fn fragment_combiner_complete_only_acks() {
+    let mut fragment_combiner: FragmentCombiner<Box<[u8]>> = FragmentCombiner::new();
+    fragment_combiner.ack_mode = AckMode::CompleteOnly;
+    let now = Instant::now();
+    for frag_id in 0..3 {
+        fragment_combiner.push(Fragment { seq_id: 2, frag_id, frag_total: 3, frag_meta: FragmentMeta::Key, data: Box::new([frag_id]) }, now);
+    }
+    assert!(fragment_combiner.tick(now + Duration::from_millis(100)).is_empty());
+    fragment_combiner.push(Fragment { seq_id: 2, frag_id: 3, frag_total: 3, frag_meta: FragmentMeta::Key, data: Box::new([3]) }, now);
+    let acks = fragment_combiner.tick(now + Duration::from_millis(200));
+    assert_eq!(acks.len(), 1);
+    assert_eq!(acks[0].0, 2);
+}
+
+#[test]
+fn fragment_combiner_reassembly_limit() {
+    let mut fragment_combiner: FragmentCombiner<Box<[u8]>> = FragmentCombiner::new();
+    fragment_combiner.reassembly_limit = Some(250);
+    let now = Instant::now();
+    for seq_id in 0..3 {
+        fragment_combiner.push(Fragment { seq_id, frag_id: 0, frag_total: 1, frag_meta: FragmentMeta::Key, data: Box::new([0; 100]) }, now + Duration::from_millis(seq_id as u64));
+    }
+    assert_eq!(fragment_combiner.next_dropped_set(), Some((0, 1, 2)));
+    assert_eq!(fragment_combiner.next_dropped_set(), None);
+    assert_eq!(fragment_combiner.buffered_bytes, 200);
+
+    // completing a set releases its bytes
+    fragment_combiner.push(Fragment { seq_id: 1, frag_id: 1, frag_total: 1, frag_meta: FragmentMeta::Key, data: Box::new([0; 100]) }, now);
+    assert!(fragment_combiner.next_out_message().is_some());
+    assert_eq!(fragment_combiner.buffered_bytes, 100);
+}
+
+#[test]
+fn fragment_combiner_try_reliable_not_reported() {
+    let mut fragment_combiner: FragmentCombiner<Box<[u8]>> = FragmentCombiner::new();
+    let now = Instant::now();
+    fragment_combiner.push(Fragment { seq_id: 1, frag_id: 0, frag_total: 1, frag_meta: FragmentMeta::TryReliable, data: Box::new([1, 2]) }, now);
+    fragment_combiner.push(Fragment { seq_id: 2, frag_id: 0, frag_total: 1, frag_meta: FragmentMeta::KeyExpirable, data: Box::new([1, 2]) }, now);
+    fragment_combiner.tick(now + Duration::from_secs(61));
+    assert!(fragment_combiner.pending_fragments.is_empty());
+    assert_eq!(fragment_combiner.next_dropped_set(), Some((2, 1, 2)));
+    assert_eq!(fragment_combiner.next_dropped_set(), None);
+}
+
+#[test]
+fn fragment_combiner_reassembly_time() {
+    let mut fragment_combiner: FragmentCombiner<Box<[u8]>> = FragmentCombiner::new();
+    let start = Instant::now();
+    fragment_combiner.push(Fragment { seq_id: 1, frag_id: 0, frag_total: 1, frag_meta: FragmentMeta::Key, data: Box::new([0]) }, start);
+    assert!(fragment_combiner.next_out_message().is_none());
+    fragment_combiner.push(Fragment { seq_id: 1, frag_id: 1, frag_total: 1, frag_meta: FragmentMeta::Key, data: Box::new([1]) }, start + Duration::from_millis(300));
+    let message = fragment_combiner.next_out_message().unwrap();
+    assert_eq!(message.first_received_at, start);
+    assert_eq!(message.reassembly_time, Duration::from_millis(300));
+}
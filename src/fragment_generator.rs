@@ -26,7 +26,8 @@ impl<'a, I: Iterator<Item = &'a [u8]> + Clone> Iterator for FragmentGenerator<'a
         let data = self.iterator.next();
         data.map(|data| {
             let current_frag = self.next_frag;
-            self.next_frag += 1;
+            // wraps after the 256th fragment of the largest messages, it is never used again then
+            self.next_frag = self.next_frag.wrapping_add(1);
             Fragment {
                 seq_id: self.seq_id,
                 frag_total: self.frag_total,
@@ -0,0 +1,58 @@
+//! Entry points for fuzzers, such as cargo-fuzz. Only available with the `fuzz` feature.
+//!
+//! A fuzz target only has to call these functions with the bytes it was given: they never return
+//! anything, the only thing that matters is that they never panic, whatever the input.
+
+use crate::udp_packet::UdpPacket;
+use crate::udp_packet_handler::UdpPacketHandler;
+use std::time::Instant;
+
+/// Parses `data` as a datagram received from a remote, and feeds it to the reassembly of messages.
+///
+/// The CRC is checked while parsing, then the datagram is handed to the receiving side of a connection
+/// with CRC checks disabled, so that the fuzzer doesn't have to find valid checksums to reach the parsing
+/// of batches, acks and fragments and the reassembly of messages. A batch may hold several fragments
+/// of the same message, which is how the reassembly can be explored with a single datagram.
+pub fn parse_packet(data: &[u8]) {
+    let _r = UdpPacket::new(Box::from(data)).compute_packet();
+
+    let now = Instant::now();
+    let mut handler = UdpPacketHandler::new();
+    handler.set_verify_crc(false);
+    handler.add_received_packet(UdpPacket::new(Box::from(data)), now, &mut |_reason, _bytes| {});
+    let _acks = handler.tick(now);
+    while handler.next_received_message().is_some() {}
+}
+
+#[test]
+fn parse_mutated_packets() {
+    use crate::fragment::{build_fragments_from_bytes, FragmentMeta, FragmentSizing};
+    use crate::udp_packet::{Packet, pack_batches};
+
+    let mut datagrams: Vec<Box<[u8]>> = Vec::new();
+    // small fragments, so that the messages fit in a few batches. The first and last fragments on their own
+    // are messages whose other fragments are missing, with last frag_ids on both sides of a multiple of 8
+    for (seq_id, fragment_count) in [(3u32, 4usize), (4, 8), (5, 9), (6, 33), (7, 256)] {
+        let message: Vec<u8> = (0..fragment_count * 10).map(|i| i as u8).collect();
+        let (fragments, _frag_total) = build_fragments_from_bytes(&message, seq_id, FragmentMeta::Key, FragmentSizing::Packed, 10).unwrap();
+        let fragments: Vec<Box<[u8]>> = fragments.map(|f| Box::from(UdpPacket::from(&f).as_bytes())).collect();
+        datagrams.push(fragments[0].clone());
+        datagrams.push(fragments[fragments.len() - 1].clone());
+        datagrams.extend(pack_batches(fragments).into_iter().map(|p| Box::from(p.as_bytes())));
+    }
+    datagrams.push(Box::from(UdpPacket::from(&Packet::Ack(3, Box::from(&[0xF0u8][..]))).as_bytes()));
+
+    for datagram in &datagrams {
+        parse_packet(datagram);
+        for len in 0..datagram.len().min(64) {
+            parse_packet(&datagram[..len]);
+        }
+        for position in 0..datagram.len() {
+            for value in [0u8, 1, 2, 3, 7, 0x7F, 0x80, 0xFE, 0xFF] {
+                let mut mutated = datagram.to_vec();
+                mutated[position] = value;
+                parse_packet(&mutated);
+            }
+        }
+    }
+}
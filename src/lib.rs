@@ -65,9 +65,9 @@
 //! }
 //! ```
 
-/// TODO: reorganize stuff.
-/// Stuff is working, but it's really not well organized at all. A refactor will be needed
-/// (at least name-wise, but also to define precisely which module has which limits and which role)
+// TODO: reorganize stuff.
+// Stuff is working, but it's really not well organized at all. A refactor will be needed
+// (at least name-wise, but also to define precisely which module has which limits and which role)
 
 mod misc;
 mod consts;
@@ -81,6 +81,32 @@ mod rudp_server;
 mod ack;
 mod sent_data_tracker;
 mod ping_handler;
+mod asymmetry_detector;
+mod rng;
+mod stats;
+mod reassembler;
+mod discard;
+mod snapshot;
+mod config;
+mod transport;
+#[cfg(feature = "egress_filter")]
+mod egress_filter;
+mod send_stream;
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
 
 pub use rudp::*;
-pub use rudp_server::*;
\ No newline at end of file
+pub use rudp_server::*;
+pub use udp_packet::{UdpPacket, PacketMeta, UdpPacketError};
+pub use fragment::FragmentMeta;
+#[cfg(feature = "wire")]
+pub use udp_packet::Packet;
+#[cfg(feature = "wire")]
+pub use fragment::Fragment;
+pub use reassembler::{MessageReassembler, ReplayResult, read_trace, write_trace_datagram};
+pub use discard::DiscardReason;
+pub use snapshot::{RemoteSnapshot, ServerState, RemoteState};
+pub use config::SocketConfig;
+pub use ping_handler::PingStats;
+pub use send_stream::SendStream;
+pub use consts::MAX_MESSAGE_SIZE;
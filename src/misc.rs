@@ -10,6 +10,12 @@ impl<'a, T: Clone + Iterator + 'a> ClonableIterator<'a> for T {
     }
 }
 
+impl<'a, I: 'a> Clone for Box<dyn ClonableIterator<'a, Item = I> + 'a> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
 /// An Owned Slice
 pub (crate) struct OwnedSlice<T, D: AsRef<[T]> + 'static> {
     _d: ::std::marker::PhantomData<T>,
@@ -46,10 +52,62 @@ impl<T, D: AsRef<[T]> + 'static> OwnedSlice<T, D> {
     pub fn as_slice(&self) -> &[T] {
         &self.data.as_ref()[self.strip_begin..]
     }
+
+    /// Returns the whole data, including what was stripped.
+    pub fn as_unstripped_slice(&self) -> &[T] {
+        self.data.as_ref()
+    }
 }
 
 impl<T, D: AsRef<[T]>> AsRef<[T]> for OwnedSlice<T, D> {
     fn as_ref(&self) -> &[T] {
         self.as_slice()
     }
-}
\ No newline at end of file
+}
+
+/// Keeps track of which seq_ids have been received, without growing forever
+/// when they are received mostly in order.
+#[derive(Debug, Default)]
+pub (crate) struct ReceivedSeqIds {
+    /// Every seq_id strictly below this one has been received
+    next_missing: u32,
+    /// seq_ids received above `next_missing`
+    ahead: ::std::collections::BTreeSet<u32>,
+}
+
+impl ReceivedSeqIds {
+    pub fn insert(&mut self, seq_id: u32) {
+        if seq_id < self.next_missing {
+            return;
+        }
+        self.ahead.insert(seq_id);
+        while self.ahead.remove(&self.next_missing) {
+            self.next_missing += 1;
+        }
+    }
+
+    /// Returns true if every seq_id from 0 to `seq_id` (included) has been received
+    pub fn contains_all_up_to(&self, seq_id: u32) -> bool {
+        self.next_missing > seq_id
+    }
+
+    /// Returns true if no seq_id has been received at all
+    pub fn is_empty(&self) -> bool {
+        self.next_missing == 0 && self.ahead.is_empty()
+    }
+}
+
+#[test]
+fn received_seq_ids() {
+    let mut received = ReceivedSeqIds::default();
+    assert!(!received.contains_all_up_to(0));
+    received.insert(1);
+    received.insert(3);
+    assert!(!received.contains_all_up_to(1));
+    received.insert(0);
+    assert!(received.contains_all_up_to(1));
+    assert!(!received.contains_all_up_to(2));
+    received.insert(2);
+    assert!(received.contains_all_up_to(3));
+    assert!(received.ahead.is_empty());
+}
@@ -1,62 +1,109 @@
 use ::std::time::Instant;
+use ::std::collections::VecDeque;
+use crate::consts::{MAX_WAITING_PINGS, PING_SAMPLE_TIMEOUT};
+
+/// Statistics of the round trips to the remote, see `RUdpSocket::ping_stats`. Every value is in ms.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PingStats {
+    /// Shortest round trip seen
+    pub min_ms: u32,
+    /// Longest round trip seen
+    pub max_ms: u32,
+    /// Smoothed round trip: every new sample moves it by an eighth of the difference
+    pub avg_ms: f32,
+    /// Smoothed difference between two successive samples, as in RTP (RFC 3550).
+    /// Useful to size a jitter buffer.
+    pub jitter_ms: f32,
+    /// Last round trip measured, the same as `RUdpSocket::ping`
+    pub last_ms: u32,
+}
+
+impl PingStats {
+    fn new(ping_ms: u32) -> PingStats {
+        PingStats {
+            min_ms: ping_ms,
+            max_ms: ping_ms,
+            avg_ms: ping_ms as f32,
+            jitter_ms: 0.0,
+            last_ms: ping_ms,
+        }
+    }
+
+    fn add_sample(&mut self, ping_ms: u32) {
+        let difference = (ping_ms as f32 - self.last_ms as f32).abs();
+        self.jitter_ms += (difference - self.jitter_ms) / 16.0;
+        self.avg_ms += (ping_ms as f32 - self.avg_ms) / 8.0;
+        self.min_ms = self.min_ms.min(ping_ms);
+        self.max_ms = self.max_ms.max(ping_ms);
+        self.last_ms = ping_ms;
+    }
+}
 
 #[derive(Debug)]
 pub (crate) struct PingHandler {
-    pub waiting_ping: Option<(u32, Instant)>,
+    /// Key messages sent and not acknowledged yet, the oldest first
+    pub waiting_pings: VecDeque<(u32, Instant)>,
     // in ms
     pub current_ping: Option<u32>,
+    pub stats: Option<PingStats>,
 }
 
 impl PingHandler {
     pub fn new() -> PingHandler {
         PingHandler {
-            waiting_ping: None,
-            current_ping: None
+            waiting_pings: VecDeque::with_capacity(MAX_WAITING_PINGS),
+            current_ping: None,
+            stats: None,
         }
     }
 
     /// Should be called when we send the packet that will act as a ping
     ///
-    /// Does nothing if there is already another last_ping_sent recorded unanswered
+    /// Several pings may be in flight at once, so that every key message can give a sample. Pings that
+    /// are not answered after 5s are forgotten, and so is the oldest one when too many are in flight.
     pub (crate) fn ping(&mut self, seq_id: u32) {
         let now = Instant::now();
-        let delta_sec = self.waiting_ping.map(|(_, time)| {
-            (now - time).as_secs()
-        });
-        if let Some(delta_sec) = delta_sec {
-            if delta_sec >= 5 {
-                // if we haven't received an answer to our ping after 5s, we'll assume he never
-                // received it and we will send another one
-                self.waiting_ping = None;
-            } else {
-                // current ping is valid, we will skip storing given seq_id
-                return;
-            }
+        while self.waiting_pings.front().is_some_and(|(_, sent_at)| now - *sent_at >= PING_SAMPLE_TIMEOUT) {
+            // the remote most likely never received it
+            self.waiting_pings.pop_front();
+        }
+        if self.waiting_pings.len() >= MAX_WAITING_PINGS {
+            self.waiting_pings.pop_front();
         }
-        self.waiting_ping = Some((seq_id, now));
+        self.waiting_pings.push_back((seq_id, now));
     }
 
     /// Should be called when we receive the ping back
     ///
     /// Does nothing if the seq_id has not been recorded
     pub (crate) fn pong(&mut self, seq_id: u32) {
-        let clear_waiting_ping: bool = match self.waiting_ping {
-            Some((stored_seq_id, time)) if stored_seq_id == seq_id => {
-                let d = Instant::now() - time;
-                let ms = d.subsec_millis();
-                let secs = d.as_secs();
-                let ping_ms = if secs >= 5 {
-                    4999u32
-                } else {
-                    ms + (secs as u32) * 1000
-                };
-                self.current_ping = Some(ping_ms);
-                true
-            },
-            _ => false
+        if let Some(position) = self.waiting_pings.iter().position(|(stored_seq_id, _)| *stored_seq_id == seq_id) {
+            if let Some((_, sent_at)) = self.waiting_pings.remove(position) {
+                self.record_sample(sent_at);
+            }
+        }
+    }
+
+    /// Records a round trip that started at `sent_at` and ended now.
+    ///
+    /// Used for round trips that are not key messages, like the Syn/SynAck handshake.
+    pub (crate) fn record_sample(&mut self, sent_at: Instant) {
+        let d = Instant::now() - sent_at;
+        let ms = d.subsec_millis();
+        let secs = d.as_secs();
+        let ping_ms = if secs >= 5 {
+            4999u32
+        } else {
+            ms + (secs as u32) * 1000
         };
-        if clear_waiting_ping {
-            self.waiting_ping = None;
+        self.record_ping_ms(ping_ms);
+    }
+
+    pub (crate) fn record_ping_ms(&mut self, ping_ms: u32) {
+        self.current_ping = Some(ping_ms);
+        match self.stats.as_mut() {
+            Some(stats) => stats.add_sample(ping_ms),
+            None => self.stats = Some(PingStats::new(ping_ms)),
         }
     }
 
@@ -64,4 +111,43 @@ impl PingHandler {
     pub (crate) fn current_ping_ms(&self) -> Option<u32> {
         self.current_ping
     }
+
+    /// Returns None if no ping was computed yet
+    pub (crate) fn stats(&self) -> Option<PingStats> {
+        self.stats
+    }
+}
+
+#[test]
+fn several_pings_in_flight() {
+    let mut ping_handler = PingHandler::new();
+    for seq_id in 0..(MAX_WAITING_PINGS as u32 + 2) {
+        ping_handler.ping(seq_id);
+    }
+    assert_eq!(ping_handler.waiting_pings.len(), MAX_WAITING_PINGS);
+    // the oldest ones were forgotten
+    ping_handler.pong(0);
+    assert_eq!(ping_handler.current_ping_ms(), None);
+    ping_handler.pong(5);
+    ping_handler.pong(3);
+    assert!(ping_handler.current_ping_ms().is_some());
+    assert_eq!(ping_handler.waiting_pings.len(), MAX_WAITING_PINGS - 2);
+    // a pong only counts once
+    ping_handler.pong(5);
+    assert_eq!(ping_handler.waiting_pings.len(), MAX_WAITING_PINGS - 2);
+}
+
+#[test]
+fn ping_stats_jitter() {
+    let mut ping_handler = PingHandler::new();
+    assert_eq!(ping_handler.stats(), None);
+    for ping_ms in [40, 60, 40, 60].iter() {
+        ping_handler.record_ping_ms(*ping_ms);
+    }
+    let stats = ping_handler.stats().unwrap();
+    assert_eq!((stats.min_ms, stats.max_ms, stats.last_ms), (40, 60, 60));
+    assert!(stats.avg_ms > 40.0 && stats.avg_ms < 50.0, "{}", stats.avg_ms);
+    // 3 differences of 20ms
+    let expected_jitter = 20.0 * (1.0 - (15.0f32 / 16.0).powi(3));
+    assert!((stats.jitter_ms - expected_jitter).abs() < 1e-3, "{}", stats.jitter_ms);
 }
\ No newline at end of file
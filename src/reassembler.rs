@@ -0,0 +1,174 @@
+use crate::fragment_combiner::{FragmentCombiner, CorruptedSetsStats};
+use crate::misc::BoxedSlice;
+use crate::rudp::ReceivedData;
+use crate::udp_packet::{UdpPacket, Packet, PacketMeta, UdpPacketError, unpack_batch};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{ErrorKind as IoErrorKind, Read, Result as IoResult, Write};
+use std::time::Instant;
+
+/// Reads the datagrams of a trace, in order.
+///
+/// A trace is a sequence of datagrams, each one prefixed by its length as a big endian u32.
+/// `write_trace_datagram` writes such traces. Together with `MessageReassembler::replay`, this allows
+/// turning captured traffic (from a bug report, for instance) into a reproducible test.
+pub fn read_trace<R: Read>(mut reader: R) -> IoResult<Vec<Box<[u8]>>> {
+    let mut datagrams = Vec::new();
+    loop {
+        let len = match reader.read_u32::<BigEndian>() {
+            Ok(len) => len as usize,
+            Err(ref e) if e.kind() == IoErrorKind::UnexpectedEof => return Ok(datagrams),
+            Err(e) => return Err(e),
+        };
+        let mut datagram = vec![0u8; len];
+        reader.read_exact(&mut datagram)?;
+        datagrams.push(datagram.into_boxed_slice());
+    }
+}
+
+/// Appends a datagram to a trace. See `read_trace`.
+pub fn write_trace_datagram<W: Write>(mut writer: W, datagram: &[u8]) -> IoResult<()> {
+    writer.write_u32::<BigEndian>(datagram.len() as u32)?;
+    writer.write_all(datagram)
+}
+
+/// What came out of a sequence of datagrams, see `MessageReassembler::replay`.
+#[derive(Debug, Default)]
+pub struct ReplayResult {
+    /// Messages completely re-assembled, in the order they were completed
+    pub messages: Vec<ReceivedData>,
+    /// Datagrams which are not valid packets, as (index of the datagram, error)
+    pub errors: Vec<(usize, UdpPacketError)>,
+    /// Messages discarded because their fragments were inconsistent
+    pub corrupted_sets: CorruptedSetsStats,
+}
+
+/// Re-assembles messages out of raw datagrams, without any connection.
+///
+/// This is the same logic a `RUdpSocket` uses to re-assemble the messages of its remote,
+/// for relays, loggers or analysis tools which only see the datagrams go by.
+///
+/// seq_ids are only unique per sender: use one `MessageReassembler` per sender.
+///
+/// Only fragments (possibly in batches) are used, every other kind of packet is ignored.
+/// Nothing is ever sent, so the sender will not receive any ack from this.
+#[derive(Debug)]
+pub struct MessageReassembler {
+    fragment_combiner: FragmentCombiner<BoxedSlice<u8>>,
+}
+
+impl Default for MessageReassembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MessageReassembler {
+    pub fn new() -> MessageReassembler {
+        MessageReassembler {
+            fragment_combiner: FragmentCombiner::new(),
+        }
+    }
+
+    /// Feed a datagram, exactly as it was received.
+    ///
+    /// Returns an error if the datagram is not a valid packet. Completed messages
+    /// can be retrieved with `next_message`.
+    pub fn push_bytes(&mut self, bytes: &[u8]) -> Result<(), UdpPacketError> {
+        self.push_packet(UdpPacket::new(Box::from(bytes)), Instant::now())
+    }
+
+    fn push_packet(&mut self, udp_packet: UdpPacket<Box<[u8]>>, now: Instant) -> Result<(), UdpPacketError> {
+        match udp_packet.compute_packet()? {
+            Packet::Fragment(fragment) => self.fragment_combiner.push(fragment, now),
+            Packet::Batch(payload) => {
+                for packet in unpack_batch(payload.as_ref())? {
+                    if let Ok(PacketMeta::Batch) = packet.peek_meta() {
+                        continue;
+                    }
+                    self.push_packet(packet, now)?;
+                }
+            },
+            _ => {},
+        };
+        Ok(())
+    }
+
+    /// Feeds every datagram to a new `MessageReassembler`, and returns everything that came out of it.
+    pub fn replay<I: IntoIterator<Item=B>, B: AsRef<[u8]>>(datagrams: I) -> ReplayResult {
+        let mut reassembler = MessageReassembler::new();
+        let mut result = ReplayResult::default();
+        for (index, datagram) in datagrams.into_iter().enumerate() {
+            if let Err(e) = reassembler.push_bytes(datagram.as_ref()) {
+                result.errors.push((index, e));
+            }
+        }
+        while let Some(message) = reassembler.next_message() {
+            result.messages.push(message);
+        }
+        result.corrupted_sets = reassembler.corrupted_sets();
+        result
+    }
+
+    /// Returns how many messages were discarded because their fragments were inconsistent, sorted by reason.
+    pub fn corrupted_sets(&self) -> CorruptedSetsStats {
+        self.fragment_combiner.corrupted_sets
+    }
+
+    /// Returns the next message which has been completely re-assembled, if any.
+    pub fn next_message(&mut self) -> Option<ReceivedData> {
+        self.fragment_combiner.next_out_message()
+    }
+
+    /// Forget about the incomplete messages which haven't received any fragment for a while.
+    ///
+    /// Should be called regularly, otherwise messages that will never be completed are kept forever.
+    pub fn tick(&mut self) {
+        let _acks = self.fragment_combiner.tick(Instant::now());
+        while self.fragment_combiner.next_dropped_set().is_some() {}
+    }
+}
+
+#[test]
+fn reassembler_out_of_order() {
+    use crate::fragment::{build_fragments_from_bytes, FragmentMeta, FragmentSizing, MAX_FRAGMENT_MESSAGE_SIZE};
+
+    let data: Vec<u8> = (0..3000u32).map(|i| i as u8).collect();
+    let (fragments, _frag_total) = build_fragments_from_bytes(&data, 12, FragmentMeta::Key, FragmentSizing::Packed, MAX_FRAGMENT_MESSAGE_SIZE).unwrap();
+    let datagrams: Vec<UdpPacket<Box<[u8]>>> = fragments.map(|f| UdpPacket::from(&f)).collect();
+
+    let mut reassembler = MessageReassembler::new();
+    assert_eq!(reassembler.push_bytes(&[1, 2, 3]), Err(UdpPacketError::NotBigEnough));
+    for datagram in datagrams.iter().rev() {
+        assert!(reassembler.next_message().is_none());
+        reassembler.push_bytes(datagram.as_bytes()).unwrap();
+    }
+    let message = reassembler.next_message().unwrap();
+    assert_eq!(message.seq_id, 12);
+    assert_eq!(&*message.data, &*data);
+}
+
+#[test]
+fn replay_trace() {
+    use crate::fragment::{build_fragments_from_bytes, FragmentMeta, FragmentSizing, MAX_FRAGMENT_MESSAGE_SIZE};
+
+    let data = [9u8; 2000];
+    let (fragments, _frag_total) = build_fragments_from_bytes(&data[..], 4, FragmentMeta::Key, FragmentSizing::Packed, MAX_FRAGMENT_MESSAGE_SIZE).unwrap();
+    let mut datagrams: Vec<UdpPacket<Box<[u8]>>> = fragments.map(|f| UdpPacket::from(&f)).collect();
+    datagrams.reverse();
+
+    let mut trace: Vec<u8> = Vec::new();
+    write_trace_datagram(&mut trace, datagrams[0].as_bytes()).unwrap();
+    write_trace_datagram(&mut trace, &[0u8; 20]).unwrap();
+    for datagram in &datagrams[1..] {
+        write_trace_datagram(&mut trace, datagram.as_bytes()).unwrap();
+    }
+
+    let result = MessageReassembler::replay(read_trace(&trace[..]).unwrap());
+    assert_eq!(result.errors, vec![(1, UdpPacketError::InvalidCrc)]);
+    assert_eq!(result.messages.len(), 1);
+    assert_eq!(&*result.messages[0].data, &data[..]);
+    assert_eq!(result.corrupted_sets.total(), 0);
+
+    // a truncated trace is an error
+    assert!(read_trace(&trace[..trace.len() - 1]).is_err());
+}
@@ -0,0 +1,32 @@
+use rand::RngCore;
+use rand::rngs::OsRng;
+use std::sync::{Arc, Mutex};
+
+/// Source of randomness used by the protocol, shared between a server and all its remotes.
+///
+/// By default, this is the OS's random number generator. Tests may replace it with a seeded generator
+/// to make every randomized behavior deterministic.
+#[derive(Clone)]
+pub (crate) struct SharedRng(Arc<Mutex<dyn RngCore + Send>>);
+
+impl ::std::fmt::Debug for SharedRng {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "SharedRng")
+    }
+}
+
+impl Default for SharedRng {
+    fn default() -> SharedRng {
+        SharedRng::new(OsRng)
+    }
+}
+
+impl SharedRng {
+    pub (crate) fn new<R: RngCore + Send + 'static>(rng: R) -> SharedRng {
+        SharedRng(Arc::new(Mutex::new(rng)))
+    }
+
+    pub (crate) fn next_u64(&self) -> u64 {
+        self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).next_u64()
+    }
+}
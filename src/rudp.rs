@@ -1,29 +1,181 @@
 use std::net::UdpSocket;
-use crate::udp_packet_handler::{UdpPacketHandler, ReceivedMessage};
-use crate::udp_packet::{UdpPacket, Packet};
+use crate::udp_packet_handler::{UdpPacketHandler, ReceivedMessage, read_token};
+use crate::consts::{MAX_FRAGMENTS_IN_MESSAGE, DRAIN_TICK_INTERVAL, MIGRATE_RESEND_DELAY, MTU_BLACK_HOLE_RESENDS, MIN_PAYLOAD_SIZE, MAX_UNKNOWN_DATAGRAMS, MAX_UDP_MESSAGE_SIZE, PACKET_DATA_START_BYTE};
+use crate::udp_packet::{UdpPacket, Packet, PacketMeta, pack_batches, PADDING_HEADER_SIZE};
+use std::cell::{Cell, RefCell};
+use std::borrow::Cow;
 use std::net::{SocketAddr, ToSocketAddrs};
 use std::io::{Error as IoError, ErrorKind as IoErrorKind, Result as IoResult};
 use std::sync::Arc;
-use crate::ack::Ack;
+use crate::ack::{Ack, Acks};
 use crate::sent_data_tracker::SentDataTracker;
 use std::collections::VecDeque;
 use crate::ping_handler::*;
+use crate::asymmetry_detector::AsymmetryDetector;
+use crate::misc::ReceivedSeqIds;
+use crate::rng::SharedRng;
+use crate::discard::{DiscardHook, DiscardReason};
+use crate::transport::Transport;
+use rand::RngCore;
 use std::time::{Duration, Instant};
 
+pub use crate::fragment_combiner::{CorruptedSetsStats, AckMode};
+pub use crate::fragment::FragmentSizing;
+use crate::fragment::{MAX_FRAGMENT_MESSAGE_SIZE, MAX_RCV_FRAGMENT_MESSAGE_SIZE};
+pub use crate::sent_data_tracker::{PendingInfo, DeliveryResult, DeliveryStatus};
+use crate::sent_data_tracker::DeliveryCallback;
+pub use crate::stats::ConnectionStats;
+use crate::stats::RateMeter;
+use crate::snapshot::{RemoteSnapshot, RemoteState};
+use crate::config::SocketConfig;
+#[cfg(feature = "egress_filter")]
+use crate::egress_filter::EgressFilter;
+use crate::send_stream::SendStream;
+
+/// A message sent by the remote, re-assembled.
+///
+/// Derefs to the content of the message, but also tells how it was received.
+#[derive(Debug)]
+pub struct ReceivedData {
+    /// Content of the message
+    pub data: Box<[u8]>,
+    /// seq_id given to this message by the remote
+    pub seq_id: u32,
+    /// Number of fragments this message was split into. A message that arrived in a single fragment
+    /// cannot have been delayed by the loss of another fragment.
+    pub frag_count: u16,
+    /// How much the fragments of this message were reordered on their way: the largest difference between
+    /// the position in which a fragment arrived and its position in the message.
+    ///
+    /// A bit of reordering is normal, but a large value compared to `frag_count` usually means
+    /// the packets are taking very different paths.
+    pub max_reorder_gap: u8,
+    /// Priority the remote sent this message with. A `Custom` priority is seen as `Normal` by the receiver.
+    pub priority: MessagePriority,
+    /// When the first fragment of this message was received. `first_received_at.elapsed()` tells how old
+    /// the oldest part of the message is, to discard data that is too stale to be of any use.
+    pub first_received_at: Instant,
+    /// Time between the first and the last fragment of this message being received: 0 for a message
+    /// in a single fragment, and long when some fragments had to be sent again.
+    pub reassembly_time: Duration,
+}
+
+impl ReceivedData {
+    /// Returns the content of the message, dropping the rest.
+    pub fn into_boxed_slice(self) -> Box<[u8]> {
+        self.data
+    }
+}
+
+impl ::std::ops::Deref for ReceivedData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl AsRef<[u8]> for ReceivedData {
+    fn as_ref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
 /// Represents an event of the Socket.
 ///
 /// They fall in mostly 2 categories: meta events, and data events.
 pub enum SocketEvent {
     /// Data sent by the remote, re-assembled
-    Data(Box<[u8]>),
+    Data(ReceivedData),
     /// Represents when the handshake with the other side was done successfully
     Connected,
     /// Connection was aborted unexpectedly by the other end (not the same as Timeout or Ended)
-    Aborted,
-    /// Connection was ended peacefully by the other end
-    Ended,
+    Aborted {
+        /// Status of the connection right before, `SynReceived` if the handshake wasn't even done for instance
+        last_status: SocketStatus,
+    },
+    /// Connection was ended peacefully, by the other end or by us
+    Ended {
+        /// Status of the connection right before
+        last_status: SocketStatus,
+    },
     /// We haven't got any packet coming from the other for a certain amount of time
-    Timeout,
+    Timeout {
+        /// Status of the connection right before
+        last_status: SocketStatus,
+        /// Time since the last packet received from the remote, at least the timeout delay.
+        ///
+        /// Much more than the timeout delay means the socket was not ticked for a while:
+        /// the remote may have been fine all along.
+        since: Duration,
+    },
+    /// The remote fully acknowledged the message with this seq_id, as returned by `send_data`.
+    ///
+    /// Sent once per message, for every message type which is acknowledged: not for `Forgettable` messages.
+    /// This is the same moment `is_seq_id_received` starts returning `Ok(true)`.
+    Delivered(u32),
+    /// The remote never answered our connection request, see `RUdpSocket::set_connect_timeout`.
+    ///
+    /// Unlike `Timeout`, we were never connected: the remote is most likely unreachable, or not listening.
+    ConnectFailed,
+    /// We keep receiving packets from the remote, but none of our key messages are acknowledged:
+    /// our packets are most likely lost on their way to the remote.
+    ///
+    /// This is only a warning, the connection is kept alive.
+    AsymmetricConnectivity,
+    /// The remote changed its address and continues the connection from there.
+    ///
+    /// Only sent by a `RUdpServer`: the event comes with the new address of the remote, `from` is the old one.
+    Migrated {
+        from: SocketAddr,
+    },
+    /// In strict mode, the remote sent too many malformed packets: the connection has been aborted.
+    TooManyMalformedPackets,
+    /// The remote connected with `connect_with_payload`, this is the payload it sent.
+    ///
+    /// It comes before `Connected` and any `Data` from this remote.
+    IncomingConnection(Box<[u8]>),
+    /// A message from the remote was partially received, but the missing fragments never arrived
+    /// and the message has been given up on. It will never be received as `Data`.
+    InboundMessageDropped {
+        seq_id: u32,
+        /// Number of fragments we received
+        received: usize,
+        /// Total number of fragments of the message
+        frag_total: usize,
+    },
+    /// The remote answered the probe started by `RUdpSocket::probe` in time.
+    ProbeSucceeded {
+        probe_id: u32,
+        /// Time between the last probe packet we sent and its answer
+        rtt: Duration,
+    },
+    /// The remote did not answer the probe started by `RUdpSocket::probe` before its deadline.
+    ///
+    /// The connection is kept alive, it is up to you to decide what to do with it.
+    ProbeFailed {
+        probe_id: u32,
+    },
+    /// The remote connected again from another address with `RUdpSocket::reconnect`, and this connection
+    /// has been replaced by the new one.
+    ///
+    /// Only sent by a `RUdpServer`: `by` is the address of the new connection. This connection is aborted.
+    Replaced {
+        by: SocketAddr,
+    },
+    /// Some messages kept being resent in vain while the remote was still answering: their fragments were
+    /// most likely too large to go through anymore, a tunnel may have appeared on the way for instance.
+    ///
+    /// `payload_size` is the new payload size of our fragments, see `RUdpSocket::negotiated_payload_size`.
+    /// The pending messages were sent again with smaller fragments and new seq_ids: `renumbered` holds
+    /// the (old, new) seq_id of each of them. Their callbacks follow them, but `delivery_status` and the like,
+    /// as well as `Delivered`, only know the new seq_ids. The remote may report the old seq_ids
+    /// as `InboundMessageDropped`. A message too large to be cut into that many smaller fragments
+    /// keeps its seq_id and its fragments.
+    PathMtuShrank {
+        payload_size: usize,
+        renumbered: Vec<(u32, u32)>,
+    },
 }
 
 impl ::std::fmt::Debug for SocketEvent {
@@ -31,9 +183,22 @@ impl ::std::fmt::Debug for SocketEvent {
         match self {
             SocketEvent::Data(d) => write!(f, "Data({:?} bytes)", d.len()),
             SocketEvent::Connected => write!(f, "Connected"),
-            SocketEvent::Aborted => write!(f, "Aborted"),
-            SocketEvent::Ended => write!(f, "Ended"),
-            SocketEvent::Timeout => write!(f, "Timeout"),
+            SocketEvent::Aborted { last_status } => write!(f, "Aborted {{ last_status: {} }}", last_status.name()),
+            SocketEvent::Ended { last_status } => write!(f, "Ended {{ last_status: {} }}", last_status.name()),
+            SocketEvent::Timeout { last_status, since } => write!(f, "Timeout {{ last_status: {}, since: {:?} }}", last_status.name(), since),
+            SocketEvent::ConnectFailed => write!(f, "ConnectFailed"),
+            SocketEvent::Delivered(seq_id) => write!(f, "Delivered({})", seq_id),
+            SocketEvent::AsymmetricConnectivity => write!(f, "AsymmetricConnectivity"),
+            SocketEvent::Migrated { from } => write!(f, "Migrated {{ from: {} }}", from),
+            SocketEvent::TooManyMalformedPackets => write!(f, "TooManyMalformedPackets"),
+            SocketEvent::IncomingConnection(payload) => write!(f, "IncomingConnection({:?} bytes)", payload.len()),
+            SocketEvent::InboundMessageDropped { seq_id, received, frag_total } =>
+                write!(f, "InboundMessageDropped {{ seq_id: {}, received: {}, frag_total: {} }}", seq_id, received, frag_total),
+            SocketEvent::ProbeSucceeded { probe_id, rtt } => write!(f, "ProbeSucceeded {{ probe_id: {}, rtt: {:?} }}", probe_id, rtt),
+            SocketEvent::ProbeFailed { probe_id } => write!(f, "ProbeFailed {{ probe_id: {} }}", probe_id),
+            SocketEvent::Replaced { by } => write!(f, "Replaced {{ by: {} }}", by),
+            SocketEvent::PathMtuShrank { payload_size, renumbered } =>
+                write!(f, "PathMtuShrank {{ payload_size: {}, renumbered: {:?} }}", payload_size, renumbered),
         }
     }
 }
@@ -42,11 +207,12 @@ impl ::std::fmt::Debug for SocketEvent {
 ///
 /// A high priority message will be sent very often until we get a successful ack,
 /// while a low priority will often wait for the other party to send an ack to send the appropriate data.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Default)]
 pub enum MessagePriority {
     Lowest,
     VeryLow,
     Low,
+    #[default]
     Normal,
     High,
     VeryHigh,
@@ -54,12 +220,6 @@ pub enum MessagePriority {
     Custom { resend_delay: Duration }
 }
 
-impl Default for MessagePriority {
-    fn default() -> Self {
-        MessagePriority::Normal
-    }
-}
-
 impl MessagePriority {
     pub fn resend_delay(&self) -> Duration {
         match self {
@@ -73,8 +233,72 @@ impl MessagePriority {
             MessagePriority::Custom { resend_delay } => *resend_delay,
         }
     }
+
+    /// Value carried by the fragments of the message, so that the receiver knows its priority.
+    ///
+    /// Normal (and Custom, whose resend delay is never sent) is 0, so that fragments of a normal
+    /// message look exactly like they did before priorities were sent along.
+    pub (crate) fn wire_value(&self) -> u8 {
+        match self {
+            MessagePriority::Normal | MessagePriority::Custom { .. } => 0,
+            MessagePriority::High => 1,
+            MessagePriority::VeryHigh => 2,
+            MessagePriority::Highest => 3,
+            MessagePriority::Low => 4,
+            MessagePriority::VeryLow => 5,
+            MessagePriority::Lowest => 6,
+        }
+    }
+
+    /// Inverse of `wire_value`. Unknown values are read as Normal.
+    pub (crate) fn from_wire_value(value: u8) -> MessagePriority {
+        match value {
+            1 => MessagePriority::High,
+            2 => MessagePriority::VeryHigh,
+            3 => MessagePriority::Highest,
+            4 => MessagePriority::Low,
+            5 => MessagePriority::VeryLow,
+            6 => MessagePriority::Lowest,
+            _ => MessagePriority::Normal,
+        }
+    }
+
+    /// Higher means the message should be processed first. Custom priorities rank as Normal.
+    pub fn rank(&self) -> i8 {
+        match self {
+            MessagePriority::Lowest => -3,
+            MessagePriority::VeryLow => -2,
+            MessagePriority::Low => -1,
+            MessagePriority::Normal | MessagePriority::Custom { .. } => 0,
+            MessagePriority::High => 1,
+            MessagePriority::VeryHigh => 2,
+            MessagePriority::Highest => 3,
+        }
+    }
+}
+
+/// Why a message could not be sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendError {
+    /// The message is `len` bytes long, but at most `max` bytes can be sent in a single message
+    /// with the current payload size. See `RUdpSocket::max_message_size`.
+    TooLarge { len: usize, max: usize },
+    /// Every seq_id below the range reserved with `RUdpSocket::reserve_seq_ids` has been given to a message already.
+    /// Only messages in the reserved range can be sent anymore, with `RUdpSocket::send_data_in_reserved_range`.
+    SeqIdsExhausted,
+}
+
+impl ::std::fmt::Display for SendError {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            SendError::TooLarge { len, max } => write!(f, "message of {} bytes is too large to be sent, the maximum is {} bytes", len, max),
+            SendError::SeqIdsExhausted => write!(f, "every seq_id below the reserved range has been used"),
+        }
+    }
 }
 
+impl ::std::error::Error for SendError {}
+
 /// Represents the type of message you are able to send (key, forgettable, ...)
 #[derive(Debug, Copy, Clone)]
 pub enum MessageType {
@@ -97,19 +321,98 @@ pub enum MessageType {
     /// A long at the socket doesn't receive the correct ack for this message,
     /// this message will be re-sent.
     KeyMessage,
+    /// Sent only once like a Forgettable message, but the remote acks it if it arrives.
+    ///
+    /// Nothing is ever re-sent, but `is_seq_id_received` tells whether the message made it
+    /// for a few seconds after it was sent.
+    ForgettableWithAck,
+    /// Sent reliably like a `KeyExpirableMessage`, but giving up once the time is over is a normal outcome.
+    ///
+    /// Use it for data that matters only for a while. Once it expires, the callback gets `DeliveryResult::GaveUp`
+    /// instead of `Expired`, and the remote doesn't report the incomplete message as `InboundMessageDropped`.
+    TryReliable(Duration),
 }
 
 impl MessageType {
     pub fn has_ack(self) -> bool {
-        use MessageType::{KeyExpirableMessage, KeyMessage};
-        match self {
-            KeyExpirableMessage(_) | KeyMessage => true,
-            _ => false
-        }
+        use MessageType::{KeyExpirableMessage, KeyMessage, ForgettableWithAck, TryReliable};
+        matches!(self, KeyExpirableMessage(_) | KeyMessage | ForgettableWithAck | TryReliable(_))
     } 
 }
 
 
+/// Function called when a socket can send again, see `RUdpSocket::set_on_writable`.
+#[derive(Default)]
+pub (crate) struct WritableHook(Option<Box<dyn FnMut() + Send>>);
+
+impl ::std::fmt::Debug for WritableHook {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "WritableHook({})", if self.0.is_some() { "set" } else { "unset" })
+    }
+}
+
+type DeliverySorter = dyn Fn(&mut [ReceivedData], bool) + Send + Sync;
+
+/// Order in which the messages received during a tick are handed out, see `RUdpSocket::set_delivery_order`.
+#[derive(Clone, Default)]
+pub (crate) struct DeliveryOrder(Option<Arc<DeliverySorter>>);
+
+impl ::std::fmt::Debug for DeliveryOrder {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "DeliveryOrder({})", if self.0.is_some() { "set" } else { "unset" })
+    }
+}
+
+/// Sort key of the priority, higher priorities first; all equal if we don't sort by priority.
+#[inline]
+fn priority_key(data: &ReceivedData, by_priority: bool) -> ::std::cmp::Reverse<i8> {
+    ::std::cmp::Reverse(if by_priority { data.priority.rank() } else { 0 })
+}
+
+impl DeliveryOrder {
+    /// The key type is only known here, so the whole sort is kept rather than `f` alone.
+    pub (crate) fn new<K: Ord + 'static, F: Fn(u32) -> K + Send + Sync + 'static>(f: F) -> DeliveryOrder {
+        DeliveryOrder(Some(Arc::new(move |data: &mut [ReceivedData], by_priority: bool| {
+            data.sort_by_cached_key(|d| (priority_key(d, by_priority), f(d.seq_id)))
+        })))
+    }
+
+    /// Stable sort, so that messages with the same key keep the order they arrived in.
+    fn sort(&self, data: &mut [ReceivedData], by_priority: bool) {
+        match &self.0 {
+            Some(sort) => sort(data, by_priority),
+            None => data.sort_by_key(|d| priority_key(d, by_priority)),
+        }
+    }
+
+    fn is_set(&self) -> bool {
+        self.0.is_some()
+    }
+}
+
+/// First address `remote_addr` resolves to.
+fn resolve<A: ToSocketAddrs>(remote_addr: A) -> IoResult<SocketAddr> {
+    remote_addr.to_socket_addrs()?.next()
+        .ok_or_else(|| IoError::new(IoErrorKind::InvalidInput, "the remote address did not resolve to any address"))
+}
+
+/// Binds a random local port of the same family as `remote_addr`: an IPv4 socket can't send to an IPv6 address.
+fn bind_for(remote_addr: SocketAddr) -> IoResult<UdpSocket> {
+    if remote_addr.is_ipv6() {
+        UdpSocket::bind("[::]:0")
+    } else {
+        UdpSocket::bind("0.0.0.0:0")
+    }
+}
+
+/// Checks a payload size given to `set_fragment_payload_size`.
+pub (crate) fn check_fragment_payload_size(size: usize) -> IoResult<()> {
+    if !(MIN_PAYLOAD_SIZE..=MAX_RCV_FRAGMENT_MESSAGE_SIZE).contains(&size) {
+        return Err(IoError::new(IoErrorKind::InvalidInput, "the fragment payload size does not fit in a datagram we can receive"));
+    }
+    Ok(())
+}
+
 /// Represents the internal connection status of the Socket
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SocketStatus {
@@ -117,6 +420,8 @@ pub enum SocketStatus {
     SynReceived,
 
     TimeoutError(Instant),
+    /// The remote never answered our Syn
+    ConnectTimeout(Instant),
 
     Connected,
 
@@ -129,14 +434,29 @@ impl SocketStatus {
         self == SocketStatus::Connected
     }
 
-    pub (crate) fn event(self) -> Option<SocketEvent> {
+    /// Name of the status, without the associated instant
+    pub fn name(self) -> &'static str {
+        match self {
+            SocketStatus::SynSent(_) => "SynSent",
+            SocketStatus::SynReceived => "SynReceived",
+            SocketStatus::TimeoutError(_) => "TimeoutError",
+            SocketStatus::ConnectTimeout(_) => "ConnectTimeout",
+            SocketStatus::Connected => "Connected",
+            SocketStatus::TerminateSent(_) => "TerminateSent",
+            SocketStatus::TerminateReceived(_) => "TerminateReceived",
+        }
+    }
+
+    /// Event telling the connection went from `last_status` to this status, if any.
+    pub (crate) fn event(self, last_status: SocketStatus, since_last_received: Duration) -> Option<SocketEvent> {
         match self {
-            SocketStatus::TimeoutError(_) => Some(SocketEvent::Timeout),
-            SocketStatus::TerminateSent(_) => Some(SocketEvent::Ended),
+            SocketStatus::TimeoutError(_) => Some(SocketEvent::Timeout { last_status, since: since_last_received }),
+            SocketStatus::ConnectTimeout(_) => Some(SocketEvent::ConnectFailed),
+            SocketStatus::TerminateSent(_) => Some(SocketEvent::Ended { last_status }),
             // // this is actually commented to tell you that you should NOT uncomment this,
             // // when we receive a packet, we automatically send the right event (ended or aborted)
             // // so there is no need to have a similar event sent here as well
-            // SocketStatus::TerminateReceived => Some(SocketEvent::Ended),
+            // SocketStatus::TerminateReceived => Some(SocketEvent::Ended { .. }),
             SocketStatus::TerminateReceived(_) => None,
             SocketStatus::Connected => Some(SocketEvent::Connected),
             _ => None
@@ -145,17 +465,14 @@ impl SocketStatus {
 
     pub fn is_finished(self) -> bool {
         use SocketStatus::*;
-        match self {
-            TimeoutError(_) | TerminateSent(_) | TerminateReceived(_) => true,
-            _ => false
-        }
+        matches!(self, TimeoutError(_) | ConnectTimeout(_) | TerminateSent(_) | TerminateReceived(_))
     }
 
     /// Returns true if the connection is finished and old enough to be deleted permanently.
     pub fn is_finished_and_old(self, now: Instant) -> bool {
         use SocketStatus::*;
         match self {
-            TimeoutError(t) | TerminateSent(t) | TerminateReceived(t) => (now - t).as_secs() >= 10,
+            TimeoutError(t) | ConnectTimeout(t) | TerminateSent(t) | TerminateReceived(t) => (now - t).as_secs() >= 10,
             _ => false
         }
     }
@@ -184,8 +501,12 @@ pub struct RUdpSocket {
 
     pub (crate) ping_handler: PingHandler,
 
+    pub (self) asymmetry_detector: AsymmetryDetector,
+
     // pub (self) last_remote_seq_id: u32,
     pub (self) next_local_seq_id: u32,
+    /// seq_ids from this one and above are only used by `send_data_in_reserved_range`
+    pub (self) reserved_seq_ids_start: Option<u32>,
 
     pub (self) cached_now: Instant,
     pub (self) last_received_message: Instant,
@@ -193,9 +514,101 @@ pub struct RUdpSocket {
 
     /// required before the socket is set as timeout. Default is 10s
     pub (self) timeout_delay: Duration,
+    /// How long we wait for an answer to our Syn, counted from the creation of the socket
+    pub (self) connect_timeout: Duration,
 
     /// required before we send a sample "heartbeat" message to avoid timeouts.
     pub (self) heartbeat_delay: Duration,
+    pub (self) heartbeat_enabled: bool,
+    /// Stretch the heartbeat delay up to what the timeout of the remote allows, see `set_adaptive_heartbeat`
+    pub (self) adaptive_heartbeat: bool,
+    /// Hold acks until the next message we send, see `set_ack_piggybacking`
+    pub (self) ack_piggybacking: bool,
+    /// Acks held until the next message we send, or the next tick at the latest
+    pub (self) held_acks: Acks<Box<[u8]>>,
+    /// Reorder the messages received during a tick by priority, see `set_receive_priority_order`
+    pub (self) receive_priority_order: bool,
+    /// Whether the last message given to `send_data` was held back instead of being sent right away
+    pub (self) last_send_queued: bool,
+    /// Whether some data was held back last tick, because of the send buffer or the send budget
+    pub (self) congested: bool,
+    pub (self) on_writable: WritableHook,
+
+    /// Number of bytes this socket may still send this tick, when a `RUdpServer` shares a send budget
+    /// between its remotes. None means unlimited.
+    pub (crate) send_allowance: Option<usize>,
+    /// Whether some resends were deferred during the last tick because the allowance was exhausted
+    pub (crate) send_deferred: bool,
+    /// Share of the server's send budget this socket gets, relative to the other remotes
+    pub (crate) send_weight: u32,
+    /// See `set_max_bytes_per_tick`. None means unlimited.
+    pub (self) max_bytes_per_tick: Option<usize>,
+    /// What is left of `max_bytes_per_tick` until the end of the next tick
+    pub (self) tick_bytes_left: usize,
+    /// See `set_max_retransmit_rate`, as bytes per second. None means unlimited.
+    pub (self) max_retransmit_rate: Option<usize>,
+    /// Bytes we may still resend, refilled at `max_retransmit_rate` up to a second's worth
+    pub (self) retransmit_budget: f64,
+    pub (self) retransmit_budget_refilled_at: Instant,
+
+    /// When this connection was created
+    pub (self) created_at: Instant,
+    /// Once the connection is that old, it is ended. None means no limit.
+    pub (self) max_connection_lifetime: Option<Duration>,
+
+    /// How long we keep receiving data after the remote sent End. Default is 0.
+    pub (self) end_grace_period: Duration,
+    /// (time End was received, last_seq_id sent by the remote) while we are in the grace period
+    pub (self) pending_end: Option<(Instant, u32)>,
+    pub (self) received_seq_ids: ReceivedSeqIds,
+
+    pub (crate) rng: SharedRng,
+    pub (crate) discard_hook: DiscardHook,
+    pub (crate) delivery_order: DeliveryOrder,
+
+    /// Token we gave to the remote when it connected to us, so it can prove it owns this connection
+    /// when it changes address.
+    pub (crate) connection_token: Option<u64>,
+    /// Token the remote gave us when we connected to it.
+    pub (self) remote_token: Option<u64>,
+    /// Sent along with every Syn
+    pub (self) syn_payload: Box<[u8]>,
+    /// Token of the connection this one replaces, sent along with every Syn. See `reconnect`.
+    pub (self) previous_token: Option<u64>,
+    /// Largest fragment payload we accept to send and receive, advertised during the handshake.
+    pub (self) max_payload_size: usize,
+    /// Largest fragment payload the remote advertised, if it did.
+    pub (self) remote_max_payload_size: Option<usize>,
+    /// Largest fragment payload that seems to go through, if larger ones were lost. See `SocketEvent::PathMtuShrank`.
+    pub (self) path_max_payload_size: Option<usize>,
+    /// Last time we told the remote we changed address, if it hasn't answered yet.
+    pub (self) migrate_sent: Option<Instant>,
+
+    pub (self) next_probe_id: u32,
+    /// The probe we are waiting an answer for, if any
+    pub (self) pending_probe: Option<PendingProbe>,
+    /// Packets were received since the last tick, see `has_work`
+    pub (self) received_since_tick: bool,
+    /// Traffic of the last second, see `current_send_rate`
+    pub (self) rate_meter: RateMeter,
+    /// Datagrams received by `next_tick` from another address than the remote's, see `drain_unknown`
+    pub (self) unknown_datagrams: VecDeque<(SocketAddr, Box<[u8]>)>,
+
+    /// In strict mode, the connection is aborted once the remote sent that many malformed packets
+    pub (self) malformed_packet_threshold: Option<u32>,
+    pub (self) malformed_packets: u32,
+}
+
+/// How many times a probe is sent at most before its deadline
+const PROBE_SENDS: u32 = 3;
+
+#[derive(Debug, Clone, Copy)]
+struct PendingProbe {
+    probe_id: u32,
+    deadline: Instant,
+    /// Delay after which we send the probe again if it hasn't been answered
+    resend_delay: Duration,
+    last_sent: Instant,
 }
 
 #[derive(Debug)]
@@ -215,6 +628,12 @@ pub (crate) struct UdpSocketWrapper {
     pub (self) udp_socket: Arc<UdpSocket>,
     pub (self) remote_addr: SocketAddr,
     pub (self) status: SocketStatus,
+    /// Packets waiting to be sent while the socket is corked. None if the socket is not corked.
+    pub (self) cork_buffer: RefCell<Option<Vec<Box<[u8]>>>>,
+    pub (self) stats: Cell<ConnectionStats>,
+    pub (crate) transport: Transport,
+    #[cfg(feature = "egress_filter")]
+    pub (crate) egress_filter: EgressFilter,
 }
 
 impl UdpSocketWrapper {
@@ -223,20 +642,66 @@ impl UdpSocketWrapper {
             udp_socket,
             remote_addr,
             status,
+            cork_buffer: RefCell::new(None),
+            stats: Cell::new(ConnectionStats::default()),
+            transport: Transport::default(),
+            #[cfg(feature = "egress_filter")]
+            egress_filter: EgressFilter::default(),
         }
-    } 
+    }
 
     /// Send some bytes without splitting in any way
+    ///
+    /// If the OS only sent part of the datagram, an error is returned: the remote will discard what it
+    /// receives, so it must be handled as any other failed send.
     #[inline]
     pub (self) fn send_raw_bytes(&self, bytes: &[u8]) -> IoResult<()> {
-        let sent_size = self.udp_socket.send_to(bytes, self.remote_addr)?;
-        debug_assert_eq!(sent_size, bytes.len(), "udp packet did not contain whole packet");
+        let (bytes_to_send, remote_addr) = match self.filter_egress(bytes) {
+            Some(filtered) => filtered,
+            None => {
+                // dropped on purpose, as if it was lost on its way
+                self.update_stats(|stats| stats.record_sent(bytes.len()));
+                return Ok(());
+            },
+        };
+        let sent_size = self.transport.send_to(&self.udp_socket, &bytes_to_send, remote_addr)?;
+        if sent_size != bytes_to_send.len() {
+            log::warn!("only {} bytes out of {} were sent to {}", sent_size, bytes_to_send.len(), remote_addr);
+            return Err(IoError::new(IoErrorKind::WriteZero, "the datagram was only partially sent"));
+        }
+        self.update_stats(|stats| stats.record_sent(bytes.len()));
         Ok(())
     }
 
+    /// Runs the egress filter on a datagram about to be sent. Returns the bytes to send and where to,
+    /// or None if the filter dropped the datagram.
+    #[cfg(feature = "egress_filter")]
+    fn filter_egress<'a>(&self, bytes: &'a [u8]) -> Option<(Cow<'a, [u8]>, SocketAddr)> {
+        let mut remote_addr = self.remote_addr;
+        if !self.egress_filter.is_set() {
+            return Some((Cow::Borrowed(bytes), remote_addr));
+        }
+        let mut rewritten = bytes.to_vec();
+        if !self.egress_filter.call(&mut rewritten, &mut remote_addr) {
+            return None;
+        }
+        Some((Cow::Owned(rewritten), remote_addr))
+    }
+
+    /// Without the `egress_filter` feature, datagrams are sent as they are.
+    #[cfg(not(feature = "egress_filter"))]
+    #[inline]
+    fn filter_egress<'a>(&self, bytes: &'a [u8]) -> Option<(Cow<'a, [u8]>, SocketAddr)> {
+        Some((Cow::Borrowed(bytes), self.remote_addr))
+    }
+
     #[inline]
     pub (crate) fn send_udp_packet<P: AsRef<[u8]>>(&self, udp_packet: &UdpPacket<P>) -> ::std::io::Result<()> {
         if ! self.status.is_finished() {
+            if let Some(cork_buffer) = self.cork_buffer.borrow_mut().as_mut() {
+                cork_buffer.push(Box::from(udp_packet.as_bytes()));
+                return Ok(());
+            }
             self.send_raw_bytes(udp_packet.as_bytes())
         } else {
             // useless to send more data is the connection is terminated
@@ -249,16 +714,108 @@ impl UdpSocketWrapper {
         self.status
     }
 
+    pub (self) fn is_corked(&self) -> bool {
+        self.cork_buffer.borrow().is_some()
+    }
+
+    pub (self) fn cork(&self) {
+        let mut cork_buffer = self.cork_buffer.borrow_mut();
+        if cork_buffer.is_none() {
+            *cork_buffer = Some(Vec::new());
+        }
+    }
+
+    /// Sends everything that was held while corked, packed in as few datagrams as possible.
+    pub (self) fn uncork(&self) -> IoResult<()> {
+        let packets = self.cork_buffer.borrow_mut().take();
+        if let Some(packets) = packets {
+            for udp_packet in pack_batches(packets) {
+                self.send_udp_packet(&udp_packet)?;
+            }
+        }
+        Ok(())
+    }
+
     #[inline]
     pub fn set_status(&mut self, new_status: SocketStatus) {
         self.status = new_status;
     }
+
+    #[inline]
+    pub (crate) fn update_stats<F: FnOnce(&mut ConnectionStats)>(&self, f: F) {
+        let mut stats = self.stats.get();
+        f(&mut stats);
+        self.stats.set(stats);
+    }
 }
 
 const DEFAULT_TIMEOUT_DELAY: Duration = Duration::from_secs(10);
 const DEFAULT_HEARTBEAT_DELAY: Duration = Duration::from_secs(1);
+/// With adaptive heartbeats, the remote may miss this many heartbeats minus one before timing out.
+const ADAPTIVE_HEARTBEAT_ATTEMPTS: u32 = 3;
 
 impl RUdpSocket {
+    /// Common part of `connect` and `new_incoming`: every setting is at its default value.
+    fn with_socket(socket: UdpSocketWrapper, local_addr: SocketAddr, now: Instant) -> RUdpSocket {
+        RUdpSocket {
+            socket,
+            local_addr,
+            sent_data_tracker: SentDataTracker::new(),
+            packet_handler: UdpPacketHandler::new(),
+            // last_remote_seq_id: 0,
+            events: Default::default(),
+            ping_handler: PingHandler::new(),
+            asymmetry_detector: AsymmetryDetector::new(now),
+            next_local_seq_id: 0,
+            reserved_seq_ids_start: None,
+            cached_now: now,
+            last_received_message: now,
+            last_sent_message: now,
+            timeout_delay: DEFAULT_TIMEOUT_DELAY,
+            connect_timeout: DEFAULT_TIMEOUT_DELAY,
+            heartbeat_delay: DEFAULT_HEARTBEAT_DELAY,
+            heartbeat_enabled: true,
+            adaptive_heartbeat: false,
+            ack_piggybacking: false,
+            held_acks: Vec::new(),
+            receive_priority_order: false,
+            last_send_queued: false,
+            congested: false,
+            on_writable: WritableHook::default(),
+            created_at: now,
+            max_connection_lifetime: None,
+            end_grace_period: Duration::from_secs(0),
+            send_allowance: None,
+            send_deferred: false,
+            send_weight: 1,
+            max_bytes_per_tick: None,
+            tick_bytes_left: 0,
+            max_retransmit_rate: None,
+            retransmit_budget: 0.0,
+            retransmit_budget_refilled_at: now,
+            pending_end: None,
+            received_seq_ids: ReceivedSeqIds::default(),
+            rng: SharedRng::default(),
+            discard_hook: DiscardHook::default(),
+            delivery_order: DeliveryOrder::default(),
+            connection_token: None,
+            remote_token: None,
+            previous_token: None,
+            max_payload_size: MAX_FRAGMENT_MESSAGE_SIZE,
+            remote_max_payload_size: None,
+            path_max_payload_size: None,
+            migrate_sent: None,
+            next_probe_id: 0,
+            pending_probe: None,
+            received_since_tick: false,
+            unknown_datagrams: VecDeque::new(),
+            rate_meter: RateMeter::default(),
+            syn_payload: Box::new([]),
+            malformed_packet_threshold: None,
+            malformed_packets: 0,
+        }
+    }
+
     /// Creates a Socket and connects to the remote instantly.
     ///
     /// This will fail ONLY if there is something wrong with the network,
@@ -271,54 +828,87 @@ impl RUdpSocket {
     /// * The remote did not answer, and we will get a timeout
     // If you want to accept a new connection, use `new_incoming` instead.
     pub fn connect<A: ToSocketAddrs>(remote_addr: A) -> IoResult<RUdpSocket> {
-        let remote_addr = remote_addr.to_socket_addrs()?.next().unwrap();
+        let remote_addr = resolve(remote_addr)?;
+        Self::connect_with_socket(bind_for(remote_addr)?, remote_addr)
+    }
+
+    /// Same as `connect`, but uses an already bound UDP socket instead of a random local port.
+    ///
+    /// Knowing the local port in advance allows peer-to-peer connections: if both ends
+    /// connect to each other at the same time, both of them will end up `Connected`.
+    pub fn connect_with_socket<A: ToSocketAddrs>(udp_socket: UdpSocket, remote_addr: A) -> IoResult<RUdpSocket> {
+        Self::connect_inner(udp_socket, remote_addr, Box::new([]), None)
+    }
+
+    /// Same as `connect`, but `payload` is sent along with the connection request.
+    ///
+    /// The server receives it as a `SocketEvent::IncomingConnection` before anything else from this remote,
+    /// which saves a round trip compared to sending it once connected: useful to authenticate, for instance.
+    ///
+    /// The payload must fit in a single datagram (a bit more than 1KB), otherwise an error of kind `InvalidInput`
+    /// is returned. It is sent unreliably like the connection request itself: it is sent again along with it
+    /// if the remote doesn't answer, but a remote which isn't a `RUdpServer` may ignore it.
+    pub fn connect_with_payload<A: ToSocketAddrs>(remote_addr: A, payload: &[u8]) -> IoResult<RUdpSocket> {
+        if payload.len() > MAX_UDP_MESSAGE_SIZE - PACKET_DATA_START_BYTE {
+            return Err(IoError::new(IoErrorKind::InvalidInput, "the connection payload does not fit in a single datagram"));
+        }
+        let remote_addr = resolve(remote_addr)?;
+        Self::connect_inner(bind_for(remote_addr)?, remote_addr, Box::from(payload), None)
+    }
+
+    /// Connects again to the remote from a new local port, for instance when this connection looks stuck.
+    ///
+    /// The new connection carries the token of this one, so a `RUdpServer` replaces this connection
+    /// with the new one right away, instead of seeing the same client twice until this one times out.
+    /// The server gets a `SocketEvent::Replaced` for this connection.
+    ///
+    /// The payload given to `connect_with_payload`, if any, is sent again. This socket is left untouched:
+    /// you will most likely want to drop it.
+    pub fn reconnect(&self) -> IoResult<RUdpSocket> {
+        Self::connect_inner(bind_for(self.remote_addr())?, self.remote_addr(), self.syn_payload.clone(), self.remote_token)
+    }
+
+    fn connect_inner<A: ToSocketAddrs>(udp_socket: UdpSocket, remote_addr: A, syn_payload: Box<[u8]>, previous_token: Option<u64>) -> IoResult<RUdpSocket> {
+        let remote_addr = resolve(remote_addr)?;
 
-        let udp_socket = Arc::new(UdpSocket::bind("0.0.0.0:0")?);
+        let udp_socket = Arc::new(udp_socket);
         udp_socket.set_nonblocking(true)?;
         let local_addr = udp_socket.local_addr()?;
 
         let now = Instant::now();
-        let mut rudp_socket = RUdpSocket {
-            socket: UdpSocketWrapper::new(udp_socket, SocketStatus::SynSent(now), remote_addr),
-            local_addr,
-            sent_data_tracker: SentDataTracker::new(),
-            packet_handler: UdpPacketHandler::new(),
-            // last_remote_seq_id: 0,
-            events: Default::default(),
-            ping_handler: PingHandler::new(),
-            next_local_seq_id: 0,
-            cached_now: now,
-            last_received_message: now,
-            last_sent_message: now,
-            timeout_delay: DEFAULT_TIMEOUT_DELAY,
-            heartbeat_delay: DEFAULT_HEARTBEAT_DELAY,
-        };
+        let socket = UdpSocketWrapper::new(udp_socket, SocketStatus::SynSent(now), remote_addr);
+        let mut rudp_socket = RUdpSocket::with_socket(socket, local_addr, now);
+        rudp_socket.syn_payload = syn_payload;
+        rudp_socket.previous_token = previous_token;
         log::info!("trying to connect to remote {}...", rudp_socket.remote_addr());
         rudp_socket.send_syn()?;
 
         Ok(rudp_socket)
     }
 
-    pub (crate) fn new_incoming(udp_socket: Arc<UdpSocket>, incoming_packet: UdpPacket<Box<[u8]>>, incoming_address: SocketAddr) -> Result<RUdpSocket, RUdpCreateError> {
-        if let Ok(Packet::Syn) = incoming_packet.compute_packet() {
+    /// The SynAck is not sent yet, so the caller can configure the socket before answering with `send_synack`.
+    pub (crate) fn new_incoming(udp_socket: Arc<UdpSocket>, incoming_packet: UdpPacket<Box<[u8]>>, incoming_address: SocketAddr, rng: &SharedRng) -> Result<RUdpSocket, RUdpCreateError> {
+        let advertised_payload_size = incoming_packet.seq_id_field();
+        let payload = match incoming_packet.compute_packet() {
+            Ok(Packet::Syn(payload)) => Some(payload),
+            Ok(Packet::Rejoin(payload)) => {
+                // the server already took care of the token
+                let token_len = ::std::cmp::min(8, payload.as_ref().len());
+                Some(payload.with_added_strip(token_len))
+            },
+            _ => None,
+        };
+        if let Some(payload) = payload {
             let local_addr = udp_socket.local_addr()?;
             let now = Instant::now();
-            let mut rudp_socket = RUdpSocket {
-                socket: UdpSocketWrapper::new(udp_socket, SocketStatus::SynReceived, incoming_address),
-                local_addr,
-                packet_handler: UdpPacketHandler::new(),
-                sent_data_tracker: SentDataTracker::new(),
-                // last_remote_seq_id: 0,
-                events: Default::default(),
-                next_local_seq_id: 0,
-                ping_handler: PingHandler::new(),
-                cached_now: now,
-                last_received_message: now,
-                last_sent_message: now,
-                timeout_delay: DEFAULT_TIMEOUT_DELAY,
-                heartbeat_delay: DEFAULT_HEARTBEAT_DELAY,
-            };
-            rudp_socket.send_synack()?;
+            let socket = UdpSocketWrapper::new(udp_socket, SocketStatus::SynReceived, incoming_address);
+            let mut rudp_socket = RUdpSocket::with_socket(socket, local_addr, now);
+            rudp_socket.rng = rng.clone();
+            rudp_socket.connection_token = Some(rudp_socket.rng.next_u64());
+            rudp_socket.set_remote_max_payload_size(advertised_payload_size);
+            if !payload.as_ref().is_empty() {
+                rudp_socket.events.push_back(SocketEvent::IncomingConnection(Box::from(payload.as_ref())));
+            }
             log::info!("received incoming connection from {}", rudp_socket.remote_addr());
 
             Ok(rudp_socket)
@@ -336,155 +926,1201 @@ impl RUdpSocket {
         self.timeout_delay = timeout_delay;
     }
 
+    /// Set how long we keep sending our connection request to the remote before giving up. Default is 10s.
+    ///
+    /// Past this delay without an answer, the status becomes `ConnectTimeout` and a `SocketEvent::ConnectFailed`
+    /// is sent instead of `Timeout`, which is kept for connections that were established. While connecting,
+    /// only this delay applies, not `set_timeout_delay`. It is counted from the call to `connect`.
+    pub fn set_connect_timeout(&mut self, connect_timeout: Duration) {
+        self.connect_timeout = connect_timeout;
+    }
+
     /// Set the number of iterations required before we send a "heartbeat" message to the remote,
     /// to make sure they don't consider us as timed out.
     pub fn set_heartbeat_delay(&mut self, heartbeat_delay: Duration) {
         self.heartbeat_delay = heartbeat_delay;
     }
 
-    #[inline]
-    /// Drains socket events for this Socket.
+    /// Enable or disable heartbeats. They are enabled by default.
     ///
-    /// This is one of the 2 ways to loop over all incoming events. See the examples
-    /// for how to use it.
-    pub fn drain_events<'a>(&'a mut self) -> impl Iterator<Item=SocketEvent> + 'a {
-        self.events.drain(..)
+    /// With heartbeats disabled, nothing is sent to the remote when we have nothing to send:
+    /// if the application stays silent for longer than the timeout delay of the remote,
+    /// the remote will time out the connection. This is useful when the application sends data
+    /// frequently anyway, or when the connection *should* die when the application is silent.
+    pub fn set_heartbeat_enabled(&mut self, enabled: bool) {
+        self.heartbeat_enabled = enabled;
     }
 
-    #[inline]
-    /// Gets the next socket event for this socket.
-    pub fn next_event(&mut self) -> Option<SocketEvent> {
-        self.events.pop_front()
+    /// Set how long a message is remembered once the remote acknowledged it. Default is 5 seconds.
+    ///
+    /// Acks still in flight for a forgotten message are only counted in `ConnectionStats::late_acks`. Raise it if that
+    /// counter keeps growing, the round trip of the path must be very long. It is also how long we wait for the ack
+    /// of a `ForgettableWithAck` message sent from now on.
+    pub fn set_cleanup_delay(&mut self, cleanup_delay: Duration) {
+        self.sent_data_tracker.cleanup_delay = cleanup_delay;
     }
 
-    #[inline]
-    pub (self) fn set_status(&mut self, status: SocketStatus) {
-        log::debug!("socket {}: new status {:?}", self.remote_addr(), status);
-        self.socket.set_status(status);
-        if let Some(event) = status.event() {
-            // We should notify this event
-            self.events.push_back(event);
-        }
+    /// Send heartbeats only as often as needed to keep the remote from timing out. Disabled by default.
+    ///
+    /// Instead of a heartbeat every `heartbeat_delay`, the delay is stretched so that the remote still gets
+    /// a heartbeat in time even if the previous ones were lost, taking the ping into account. `heartbeat_delay`
+    /// is then the shortest delay between two heartbeats. The remote is assumed to use the same timeout delay as ours.
+    /// See `next_heartbeat`.
+    pub fn set_adaptive_heartbeat(&mut self, adaptive: bool) {
+        self.adaptive_heartbeat = adaptive;
     }
-    
-    #[inline]
-    /// Send data to the remote.
+
+    /// Hand out the messages received during a tick by priority, highest first. Disabled by default.
     ///
-    /// Returns the sequence_id of the message sent. This may be useful to track whether or not the message has been received.
-    pub fn send_data(&mut self, data: Arc<[u8]>, message_type: MessageType, message_priority: MessagePriority) -> u32 {
-        if message_type.has_ack() {
-            self.ping_handler.ping(self.next_local_seq_id);
-        }
-        let seq_id = self.next_local_seq_id;
-        self.next_local_seq_id += 1;
-        self.sent_data_tracker.send_data(seq_id, data, self.cached_now, message_type, message_priority, &self.socket);
-        seq_id
+    /// Among the `Data` events of a single tick, a message sent with a higher `MessagePriority` comes before
+    /// the others, even if it arrived last; messages of the same priority keep the order they arrived in.
+    /// The other events stay where they are, so an `Ended` event still comes after the messages of its tick.
+    /// Events of a previous tick are never reordered, drain them before the next tick to get the most out of this.
+    ///
+    /// The remote only tells us the priority of its messages if it enabled `set_send_priority_info`,
+    /// they are all `Normal` otherwise.
+    pub fn set_receive_priority_order(&mut self, enabled: bool) {
+        self.receive_priority_order = enabled;
     }
 
-    /// Returns whether or not the seq_id has been received by the remote.
+    /// Hand out the messages received during a tick by ascending key, `f` giving the key of a message from its seq_id.
     ///
-    /// Ok(true) = has been received
-    /// Ok(false) = has not been received yet
-    /// Err(()) = invalid u32 OR message was sent a long time ago
-    pub fn is_seq_id_received(&self, seq_id: u32) -> Result<bool, ()> {
-        self.sent_data_tracker.is_seq_id_received(seq_id)
+    /// Like `set_receive_priority_order`, only the `Data` events of a single tick are sorted, in the slots they
+    /// already occupy; messages with the same key keep the order they arrived in. If both are set, messages are
+    /// sorted by priority first, then by key. For instance, `|seq_id| std::cmp::Reverse(seq_id)` hands out
+    /// the newest messages first.
+    ///
+    /// This has nothing to do with reliability: every message is acknowledged as soon as it is received, whatever
+    /// its place in the order, and nothing is held back for a later tick to wait for a message that would come before.
+    /// To drop older messages instead of only handing them out later, send them as `KeyExpirableMessage`
+    /// or with `send_latest`.
+    pub fn set_delivery_order<K: Ord + 'static, F: Fn(u32) -> K + Send + Sync + 'static>(&mut self, f: F) {
+        self.delivery_order = DeliveryOrder::new(f);
     }
 
-    fn send_udp_packet<P: AsRef<[u8]>>(&mut self, udp_packet: &UdpPacket<P>) -> std::io::Result<()> {
-        self.last_sent_message = self.cached_now;
-        self.socket.send_udp_packet(&udp_packet)
+    /// Hand out the messages in the order they were received again, see `set_delivery_order`.
+    pub fn clear_delivery_order(&mut self) {
+        self.delivery_order = DeliveryOrder::default();
     }
 
-    /// Should only be used by connect
-    fn send_syn(&mut self) -> ::std::io::Result<()> {
-        let p: Packet<Box<[u8]>> = Packet::Syn;
-        let udp_packet = UdpPacket::from(&p);
-        self.send_udp_packet(&udp_packet)
+    /// Returns the delay after which we send a heartbeat, if we haven't sent anything else in the meantime.
+    pub (self) fn effective_heartbeat_delay(&self) -> Duration {
+        if !self.adaptive_heartbeat {
+            return self.heartbeat_delay;
+        }
+        let ping = Duration::from_millis(self.ping().unwrap_or(0) as u64);
+        let stretched = self.timeout_delay.saturating_sub(ping) / ADAPTIVE_HEARTBEAT_ATTEMPTS;
+        ::std::cmp::max(self.heartbeat_delay, stretched)
     }
 
-    /// Should only be used by new_incoming
-    pub (self) fn send_synack(&mut self) -> ::std::io::Result<()> {
-        let p: Packet<Box<[u8]>> = Packet::SynAck;
-        let udp_packet = UdpPacket::from(&p);
-        self.set_status(SocketStatus::Connected);
-        self.send_udp_packet(&udp_packet)
+    /// Returns when the next heartbeat will be sent if nothing else is sent to the remote before,
+    /// or None if no heartbeat will be sent: heartbeats are disabled, or we are not connected.
+    pub fn next_heartbeat(&self) -> Option<Instant> {
+        if !self.heartbeat_enabled || !self.status().is_connected() {
+            return None;
+        }
+        Some(self.last_sent_message + self.effective_heartbeat_delay())
     }
 
-    pub (self) fn send_ack<D: AsRef<[u8]> + 'static>(&mut self, seq_id: u32, ack: Ack<D>) -> ::std::io::Result<()> {
-        let p: Packet<D> = Packet::Ack(seq_id, ack.into_inner());
-        let udp_packet = UdpPacket::from(&p);
-        self.send_udp_packet(&udp_packet)
+    /// Replace the source of randomness used by this socket. The OS's random number generator is used by default.
+    ///
+    /// This is mostly useful for tests, to make randomized behavior deterministic with a seeded generator.
+    ///
+    /// **Warning**: some features rely on this randomness being unpredictable for their security.
+    /// Never use a weak or seeded generator outside of tests.
+    pub fn set_rng<R: RngCore + Send + 'static>(&mut self, rng: R) {
+        self.rng = SharedRng::new(rng);
     }
 
-    /// Same as `terminate`, but leave the Socket alive.
+    /// Set a function called with every packet this socket receives but discards: corrupted packets,
+    /// packets coming from another address than the remote's, unexpected Syns, ...
     ///
-    /// This is mostly useful if you want to still receive the data the other remote is currently
-    /// sending at this time. However, note that no acks will be sent, so its usefulness
-    /// is still limited.
-    pub fn send_end(&mut self) -> ::std::io::Result<()> {
-        let p: Packet<Box<[u8]>> = Packet::End(self.next_local_seq_id.saturating_sub(1));
-        let udp_packet = UdpPacket::from(&p);
-        self.send_udp_packet(&udp_packet)
+    /// The arguments are the address the packet came from, why it was discarded and its bytes.
+    /// Mostly useful for security monitoring. Nothing is done when no function is set.
+    pub fn set_discard_hook<F: FnMut(SocketAddr, DiscardReason, &[u8]) + Send + 'static>(&mut self, f: F) {
+        self.discard_hook = DiscardHook::new(f);
     }
 
-    /// Terminates the socket, by sending a "Ended" event to the remote.
-    pub fn terminate(mut self) -> IoResult<()> {
-        self.send_end()
+    /// Set a function called with every datagram this socket sends, right before it is sent.
+    ///
+    /// The function may rewrite the datagram and the address it is sent to, or return false to drop it.
+    /// This is meant to test how connections behave through NATs and misbehaving networks, for instance
+    /// by corrupting some packets on purpose. It is only available with the `egress_filter` feature,
+    /// which should not be enabled in production builds.
+    ///
+    /// Dropped or rewritten datagrams still count as sent in the stats.
+    #[cfg(feature = "egress_filter")]
+    pub fn set_egress_filter<F: FnMut(&mut Vec<u8>, &mut SocketAddr) -> bool + Send + 'static>(&mut self, f: F) {
+        self.socket.egress_filter = EgressFilter::new(f);
     }
 
-    fn send_heartbeat(&mut self) -> ::std::io::Result<()> {
-        let p: Packet<Box<[u8]>> = Packet::Heartbeat;
-        let udp_packet = UdpPacket::from(&p);
-        self.send_udp_packet(&udp_packet)
+    /// Enable or disable strict mode.
+    ///
+    /// Malformed packets (bad CRC, invalid layout, ...) are always discarded, but a well-behaved remote
+    /// should never send any. In strict mode, once the remote has sent `malformed_packet_threshold` of them,
+    /// the connection is aborted and `SocketEvent::TooManyMalformedPackets` is emitted.
+    ///
+    /// Disabled by default.
+    pub fn set_strict_mode(&mut self, strict: bool, malformed_packet_threshold: u32) {
+        self.malformed_packet_threshold = if strict { Some(malformed_packet_threshold) } else { None };
     }
 
-    pub (self) fn send_abort(&mut self) -> ::std::io::Result<()> {
-        let p: Packet<Box<[u8]>> = Packet::Abort(self.next_local_seq_id.saturating_sub(1));
-        let udp_packet = UdpPacket::from(&p);
-        self.send_udp_packet(&udp_packet)
+    /// Set how long this connection may last, regardless of activity.
+    ///
+    /// Once the connection is that old, it is ended on the next tick: End is sent to the remote
+    /// and a `SocketEvent::Ended` is emitted, as if you had called `terminate`.
+    /// The remote will have to connect again.
+    pub fn set_max_connection_lifetime(&mut self, max_connection_lifetime: Duration) {
+        self.max_connection_lifetime = Some(max_connection_lifetime);
     }
 
-    /// Add a packet to a queue, to be processed later.
-    pub (crate) fn add_received_packet(&mut self, udp_packet: UdpPacket<Box<[u8]>>) {
-        self.last_received_message = self.cached_now;
-        log::trace!("received packet {:?} from remote {}", udp_packet, self.socket.remote_addr);
-        self.packet_handler.add_received_packet(udp_packet, self.cached_now);
+    /// Set how many bytes of messages this socket may send from one tick to the next. None (the default) means unlimited.
+    ///
+    /// New messages are always sent right away, but they use up the budget: once it is exhausted, resends
+    /// wait for the next tick, when the budget is full again. A resend larger than the whole budget is sent once
+    /// enough budget was left unused over several ticks. This trades latency for not congesting a narrow uplink
+    /// ourselves, when many messages of high priority are resent at the same time.
+    ///
+    /// On a `RUdpServer` with a send budget (see `RUdpServer::set_send_budget`), the smallest of both applies.
+    pub fn set_max_bytes_per_tick(&mut self, max_bytes_per_tick: Option<usize>) {
+        self.max_bytes_per_tick = max_bytes_per_tick;
+        self.tick_bytes_left = max_bytes_per_tick.unwrap_or(0);
     }
 
-    /// Process the next paquet received in the queue.
-    fn next_packet_event(&mut self) -> Option<SocketEvent> {
-        loop {
-            let r = self.packet_handler.next_received_message();
-            match r {
+    /// Set a hard ceiling on the bytes this socket resends per second, whatever the priority of the messages.
+    /// None (the default) means unlimited.
+    ///
+    /// Unlike `set_max_bytes_per_tick`, new messages are not limited: only resends are. When many messages are
+    /// resent at the same time, which happens when most packets are lost, this keeps the resends from saturating
+    /// the link. Resends which don't fit wait for a later tick, messages of higher priority going first.
+    /// Up to a second's worth of resends may go at once after a quiet period.
+    ///
+    /// Set it from the capacity of the link, not too low: a message whose resend is larger than a second's worth
+    /// is never resent.
+    pub fn set_max_retransmit_rate(&mut self, bytes_per_sec: Option<usize>) {
+        self.max_retransmit_rate = bytes_per_sec;
+        self.retransmit_budget = bytes_per_sec.unwrap_or(0) as f64;
+        self.retransmit_budget_refilled_at = Instant::now();
+    }
+
+    /// Bytes we may resend this tick according to `max_retransmit_rate`.
+    fn refill_retransmit_budget(&mut self) -> usize {
+        match self.max_retransmit_rate {
+            Some(rate) => {
+                let elapsed = self.cached_now.saturating_duration_since(self.retransmit_budget_refilled_at);
+                self.retransmit_budget = (self.retransmit_budget + rate as f64 * elapsed.as_secs_f64()).min(rate as f64);
+                self.retransmit_budget_refilled_at = self.cached_now;
+                self.retransmit_budget as usize
+            },
+            None => usize::MAX,
+        }
+    }
+
+    /// Set how long we keep receiving data after the remote ended the connection.
+    ///
+    /// When the remote sends End, some of the messages it sent before may still be incomplete on our side.
+    /// During this grace period, fragments still arriving are re-assembled and delivered as `Data` events
+    /// (and acked) before the `Ended` event. The connection is finalized as soon as all messages up to
+    /// the last one the remote sent have been received, or when the grace period is over. If the remote
+    /// sent nothing and nothing was received from it, it is finalized right away.
+    ///
+    /// Default is 0: the connection is finalized as soon as End is received.
+    pub fn set_end_grace_period(&mut self, end_grace_period: Duration) {
+        self.end_grace_period = end_grace_period;
+    }
+
+    /// Set how the messages we send from now on are cut into fragments. Default is `FragmentSizing::Packed`.
+    ///
+    /// The remote does not need to know about it, any sizing can be re-assembled.
+    pub fn set_fragment_sizing(&mut self, fragment_sizing: FragmentSizing) {
+        self.sent_data_tracker.layout.sizing = fragment_sizing;
+    }
+
+    /// Pad the fragments we send from now on, so that they all hold at least `min_payload_size` bytes.
+    /// Default is 0, no padding.
+    ///
+    /// Small messages then all look the same on the wire, which makes it harder to guess what they are
+    /// from their size alone. The remote strips the padding, but it must run a version of this crate
+    /// which knows about padding. The value is capped to what fits in a single datagram.
+    pub fn set_min_payload_size(&mut self, min_payload_size: usize) {
+        self.sent_data_tracker.layout.min_payload_size = min_payload_size.min(MAX_FRAGMENT_MESSAGE_SIZE - PADDING_HEADER_SIZE);
+    }
+
+    /// Tell the remote the priority of the messages we send from now on, for its `set_receive_priority_order`.
+    /// Disabled by default.
+    ///
+    /// Without it, the remote sees every message as `Normal`. The remote must run a version of this crate
+    /// which knows about it: older versions drop the fragments of messages with any other priority.
+    pub fn set_send_priority_info(&mut self, enabled: bool) {
+        self.sent_data_tracker.layout.send_priority = enabled;
+    }
+
+    /// Set the largest fragment payload we are willing to send and receive, clamped between 256 bytes
+    /// and what fits in a single datagram (the default).
+    ///
+    /// Both ends advertise this value in their Syn and SynAck, and each side then sends fragments no larger
+    /// than the smaller of the two, see `negotiated_payload_size`. Lower it if something on the path drops
+    /// large datagrams. Since a message holds at most 256 fragments, it also caps the size of a message.
+    ///
+    /// It should be set before connecting: once connected, it only changes what we send, the remote
+    /// keeps using the value we advertised during the handshake.
+    ///
+    /// Use `set_fragment_payload_size` to raise it above the default, on links with jumbo frames.
+    /// A size too large for the path makes the datagrams fragmented at the IP level, and a single lost IP fragment
+    /// loses the whole datagram; the socket lowers the payload size by itself when this seems to happen, but it is
+    /// best to set it right away on links with a small MTU, such as tunnels.
+    pub fn set_max_payload_size(&mut self, max_payload_size: usize) {
+        self.apply_max_payload_size(max_payload_size.clamp(MIN_PAYLOAD_SIZE, MAX_FRAGMENT_MESSAGE_SIZE));
+    }
+
+    /// Same as `set_max_payload_size`, but the payload size may go above the default, up to what fits in the
+    /// datagrams we receive (8972 bytes, fragment headers included): for instance on a LAN with jumbo frames.
+    ///
+    /// Fragments that large are only sent if the remote raised its payload size as well, since both ends advertise
+    /// this value during the handshake. A datagram larger than the MTU of the path is fragmented at the IP level,
+    /// and many networks drop IP fragments altogether: only raise it when every link in between carries jumbo frames.
+    ///
+    /// Returns an error of kind `InvalidInput` and leaves the setting untouched if `size` is below 256 bytes,
+    /// or leaves no room for the fragment headers in a datagram we can receive.
+    pub fn set_fragment_payload_size(&mut self, size: usize) -> IoResult<()> {
+        check_fragment_payload_size(size)?;
+        self.apply_max_payload_size(size);
+        Ok(())
+    }
+
+    /// Sets the payload size we advertise, which must have been checked already.
+    pub (crate) fn apply_max_payload_size(&mut self, max_payload_size: usize) {
+        self.max_payload_size = max_payload_size;
+        self.update_negotiated_payload_size();
+        if let SocketStatus::SynSent(_) = self.socket.status() {
+            // the Syn we already sent advertised the previous value
+            let _r = self.send_syn();
+        }
+    }
+
+    /// Largest fragment payload we send to the remote: the smallest of what both sides advertised
+    /// during the handshake.
+    pub fn negotiated_payload_size(&self) -> usize {
+        self.sent_data_tracker.layout.max_fragment_size
+    }
+
+    /// A remote advertising 0 predates the negotiation and accepts anything.
+    pub (self) fn set_remote_max_payload_size(&mut self, advertised_payload_size: u32) {
+        if advertised_payload_size != 0 {
+            self.remote_max_payload_size = Some((advertised_payload_size as usize).clamp(MIN_PAYLOAD_SIZE, MAX_RCV_FRAGMENT_MESSAGE_SIZE));
+            self.update_negotiated_payload_size();
+        }
+    }
+
+    pub (self) fn update_negotiated_payload_size(&mut self) {
+        let remote_max_payload_size = self.remote_max_payload_size.unwrap_or(MAX_FRAGMENT_MESSAGE_SIZE);
+        let path_max_payload_size = self.path_max_payload_size.unwrap_or(self.max_payload_size);
+        self.sent_data_tracker.layout.max_fragment_size = self.max_payload_size.min(remote_max_payload_size).min(path_max_payload_size);
+    }
+
+    /// Shrinks the payload size if a message keeps being resent in vain while the remote still answers:
+    /// its fragments are most likely too large for the path, and would be dropped forever.
+    pub (self) fn check_path_mtu(&mut self) {
+        let current_size = self.negotiated_payload_size();
+        let smaller_size = ::std::cmp::max(current_size / 2, MIN_PAYLOAD_SIZE);
+        if smaller_size >= current_size {
+            return;
+        }
+        let stuck_since = match self.sent_data_tracker.stuck_since(MTU_BLACK_HOLE_RESENDS, smaller_size) {
+            Some(stuck_since) => stuck_since,
+            None => return,
+        };
+        if self.last_received_message <= stuck_since {
+            // we don't hear from the remote either, this is not about the size of our packets
+            return;
+        }
+        log::warn!("socket {}: large fragments don't seem to reach the remote, lowering the payload size from {} to {}", self.remote_addr(), current_size, smaller_size);
+        self.path_max_payload_size = Some(smaller_size);
+        self.update_negotiated_payload_size();
+        let mut renumbered = Vec::new();
+        for seq_id in self.sent_data_tracker.oversized_pending() {
+            let new_seq_id = match self.next_regular_seq_id() {
+                Ok(new_seq_id) => new_seq_id,
+                Err(_) => {
+                    log::warn!("socket {}: no seq_id left to send seq_id={} again with smaller fragments", self.remote_addr(), seq_id);
+                    break;
+                },
+            };
+            log::debug!("socket {}: sending seq_id={} again as seq_id={} with smaller fragments", self.remote_addr(), seq_id, new_seq_id);
+            match self.sent_data_tracker.refragment(seq_id, new_seq_id, self.cached_now, &self.socket) {
+                Ok(()) => renumbered.push((seq_id, new_seq_id)),
+                Err(e) => log::warn!("socket {}: seq_id={} can't be sent again with smaller fragments: {}", self.remote_addr(), seq_id, e),
+            }
+        }
+        self.events.push_back(SocketEvent::PathMtuShrank { payload_size: smaller_size, renumbered });
+    }
+
+    /// Set whether the CRC of the packets we receive is checked. Default is true.
+    ///
+    /// Packets failing the check are discarded as `UdpPacketError::InvalidCrc`. Only disable it for a remote
+    /// you trust on a link that doesn't corrupt data (a local sidecar for instance), to save the cost of
+    /// hashing every packet: a corrupted packet would then be read as if it was valid.
+    pub fn set_verify_crc(&mut self, verify_crc: bool) {
+        self.packet_handler.set_verify_crc(verify_crc);
+    }
+
+    /// Send our acks along with the next message we send, in the same datagram. Disabled by default.
+    ///
+    /// The acks of a tick are held until the next call to `send_data` (or the like), and are then packed in a batch
+    /// with its first fragments, when they fit. On connections sending and receiving at the same time, this saves
+    /// a datagram per ack. Acks are never held for longer than a tick: if nothing was sent in the meantime, they
+    /// are sent on their own during the next tick, which delays them by a tick compared to the default behavior.
+    pub fn set_ack_piggybacking(&mut self, enabled: bool) {
+        self.ack_piggybacking = enabled;
+    }
+
+    /// Set when we acknowledge the fragments we receive. Default is `AckMode::Incremental`.
+    ///
+    /// See `AckMode::CompleteOnly` for links where the way back to the remote is scarce.
+    pub fn set_ack_mode(&mut self, ack_mode: AckMode) {
+        self.packet_handler.set_ack_mode(ack_mode);
+    }
+
+    /// Set how many bytes of incomplete messages from the remote we keep for reassembly. Default is no limit.
+    ///
+    /// Once we hold more than that, the oldest incomplete messages are given up on, and
+    /// `SocketEvent::InboundMessageDropped` is emitted for each of them.
+    pub fn set_reassembly_limit(&mut self, bytes: usize) {
+        self.packet_handler.set_reassembly_limit(Some(bytes));
+    }
+
+    /// Set when a `SocketEvent::AsymmetricConnectivity` should be emitted: if some key messages are waiting
+    /// for an ack, and we have received at least `min_inbound` packets from the remote during `window`
+    /// without receiving a single ack.
+    ///
+    /// Default is 20 packets in 5s.
+    pub fn set_asymmetry_threshold(&mut self, window: Duration, min_inbound: u32) {
+        self.asymmetry_detector.window = window;
+        self.asymmetry_detector.min_inbound = min_inbound;
+    }
+
+    /// Returns the settings currently in effect for this connection.
+    pub fn config(&self) -> SocketConfig {
+        SocketConfig {
+            timeout_delay: self.timeout_delay,
+            connect_timeout: self.connect_timeout,
+            heartbeat_delay: self.heartbeat_delay,
+            heartbeat_enabled: self.heartbeat_enabled,
+            adaptive_heartbeat: self.adaptive_heartbeat,
+            receive_priority_order: self.receive_priority_order,
+            ack_piggybacking: self.ack_piggybacking,
+            asymmetry_window: self.asymmetry_detector.window,
+            asymmetry_min_inbound: self.asymmetry_detector.min_inbound,
+            end_grace_period: self.end_grace_period,
+            max_connection_lifetime: self.max_connection_lifetime,
+            max_bytes_per_tick: self.max_bytes_per_tick,
+            max_retransmit_rate: self.max_retransmit_rate,
+            malformed_packet_threshold: self.malformed_packet_threshold,
+            ack_mode: self.packet_handler.ack_mode(),
+            verify_crc: self.packet_handler.verify_crc(),
+            fragment_sizing: self.sent_data_tracker.layout.sizing,
+            min_payload_size: self.sent_data_tracker.layout.min_payload_size,
+            send_priority_info: self.sent_data_tracker.layout.send_priority,
+            cleanup_delay: self.sent_data_tracker.cleanup_delay,
+            max_payload_size: self.max_payload_size,
+            reassembly_limit: self.packet_handler.reassembly_limit(),
+            send_weight: self.send_weight,
+        }
+    }
+
+    #[inline]
+    /// Drains socket events for this Socket.
+    ///
+    /// This is one of the 2 ways to loop over all incoming events. See the examples
+    /// for how to use it.
+    pub fn drain_events<'a>(&'a mut self) -> impl Iterator<Item=SocketEvent> + 'a {
+        self.events.drain(..)
+    }
+
+    /// Drains only the `Data` events, leaving every other event queued.
+    ///
+    /// Messages come in the order they were received. However, you lose the order between data and the other events:
+    /// for instance you can't know anymore whether a message was received before or after the `Ended` event.
+    /// If this matters to you, use `drain_events` instead.
+    pub fn drain_data(&mut self) -> impl Iterator<Item=ReceivedData> {
+        let (data, meta): (VecDeque<SocketEvent>, VecDeque<SocketEvent>) = self.events.drain(..)
+            .partition(|event| matches!(event, SocketEvent::Data(_)));
+        self.events = meta;
+        data.into_iter().filter_map(|event| match event {
+            SocketEvent::Data(data) => Some(data),
+            _ => None,
+        })
+    }
+
+    /// Removes up to `max` `Data` events, and returns the content of their messages in a single `Vec`.
+    ///
+    /// The other events, and the messages past `max`, are left queued. This is meant for applications
+    /// processing messages in bulk; the same ordering caveats as `drain_data` apply.
+    pub fn drain_data_batch(&mut self, max: usize) -> Vec<Box<[u8]>> {
+        let mut batch = Vec::with_capacity(max.min(self.events.len()));
+        let mut rest = VecDeque::with_capacity(self.events.len());
+        for event in self.events.drain(..) {
+            match event {
+                SocketEvent::Data(data) if batch.len() < max => batch.push(data.data),
+                event => rest.push_back(event),
+            }
+        }
+        self.events = rest;
+        batch
+    }
+
+    /// Drains every event but the `Data` events, which are left queued. See `drain_data`.
+    pub fn drain_meta(&mut self) -> impl Iterator<Item=SocketEvent> {
+        let (data, meta): (VecDeque<SocketEvent>, VecDeque<SocketEvent>) = self.events.drain(..)
+            .partition(|event| matches!(event, SocketEvent::Data(_)));
+        self.events = data;
+        meta.into_iter()
+    }
+
+    /// Drains the datagrams `next_tick` received from other addresses than the remote's, as they were received.
+    ///
+    /// They are not read in any way, this is mostly useful during NAT hole punching, when a peer may answer
+    /// from another port than the one we know of. Only the last 64 datagrams are kept, in case someone floods us.
+    pub fn drain_unknown(&mut self) -> impl Iterator<Item=(SocketAddr, Box<[u8]>)> + '_ {
+        self.unknown_datagrams.drain(..)
+    }
+
+    #[inline]
+    /// Gets the next socket event for this socket.
+    pub fn next_event(&mut self) -> Option<SocketEvent> {
+        self.events.pop_front()
+    }
+
+    #[inline]
+    pub (self) fn set_status(&mut self, status: SocketStatus) {
+        log::debug!("socket {}: new status {:?}", self.remote_addr(), status);
+        let last_status = self.status();
+        self.socket.set_status(status);
+        if let Some(event) = status.event(last_status, self.cached_now.saturating_duration_since(self.last_received_message)) {
+            // We should notify this event
+            self.events.push_back(event);
+        }
+    }
+    
+    #[inline]
+    /// Send data to the remote.
+    ///
+    /// Returns the sequence_id of the message sent. This may be useful to track whether or not the message has been received.
+    ///
+    /// Returns `SendError::TooLarge` and sends nothing if the message is larger than `max_message_size`.
+    pub fn send_data(&mut self, data: Arc<[u8]>, message_type: MessageType, message_priority: MessagePriority) -> Result<u32, SendError> {
+        self.send_data_inner(data, message_type, message_priority, None)
+    }
+
+    /// Same as `send_data`, but panics if the message is too large, like `send_data` used to.
+    #[deprecated(note = "use `send_data`, which returns an error instead of panicking")]
+    pub fn send_data_unchecked(&mut self, data: Arc<[u8]>, message_type: MessageType, message_priority: MessagePriority) -> u32 {
+        match self.send_data(data, message_type, message_priority) {
+            Ok(seq_id) => seq_id,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Largest message `send_data` accepts right now, in bytes. At most `MAX_MESSAGE_SIZE`, unless the payload size
+    /// was raised with `set_fragment_payload_size`.
+    ///
+    /// It depends on the payload size negotiated with the remote (see `negotiated_payload_size`), so it may shrink
+    /// during the handshake or when the path turns out not to carry large packets.
+    pub fn max_message_size(&self) -> usize {
+        MAX_FRAGMENTS_IN_MESSAGE * self.negotiated_payload_size()
+    }
+
+    fn check_message_size(&self, len: usize) -> Result<(), SendError> {
+        let max = self.max_message_size();
+        if len > max {
+            return Err(SendError::TooLarge { len, max });
+        }
+        Ok(())
+    }
+
+    /// Same as `send_data`, but `callback` is called once, during a later `next_tick`, with the outcome of the message.
+    ///
+    /// * `Delivered` once the remote has acknowledged every fragment,
+    /// * `Expired` if the message expired first. `Forgettable` messages are never acknowledged:
+    ///   their callback is always called with `Expired` on the next tick, use `ForgettableWithAck` instead.
+    /// * `Failed` if the connection ended first.
+    /// * `Cancelled` if `cancel_all_pending` was called first.
+    ///
+    /// If the socket is dropped before any of this happens, the callback is dropped without being called.
+    ///
+    /// If the message is too large, `SendError::TooLarge` is returned right away and the callback is dropped without being called.
+    pub fn send_data_with_callback<F: FnOnce(DeliveryResult) + Send + 'static>(&mut self, data: Arc<[u8]>, message_type: MessageType, message_priority: MessagePriority, callback: F) -> Result<u32, SendError> {
+        self.send_data_inner(data, message_type, message_priority, Some(DeliveryCallback::new(callback)))
+    }
+
+    /// Same as `send_data` with `MessageType::Forgettable`, but the message is sent straight from `data`.
+    ///
+    /// Forgettable messages are never kept once sent, so there is no need to allocate an `Arc` for them:
+    /// this is meant for streams of fire-and-forget messages, such as telemetry. Like `send_data`,
+    /// returns an error if the message is too big to be sent.
+    pub fn send_data_ref(&mut self, data: &[u8], message_priority: MessagePriority) -> Result<u32, SendError> {
+        self.check_message_size(data.len())?;
+        let seq_id = self.next_regular_seq_id()?;
+        let piggybacked = self.cork_held_acks();
+        let sent = self.sent_data_tracker.send_forgettable(seq_id, data, message_priority, &self.socket);
+        if piggybacked {
+            let _r = self.socket.uncork();
+        }
+        let all_sent = sent?;
+        self.use_send_allowance(data.len());
+        self.last_send_queued = !all_sent || self.socket.is_corked();
+        self.congested |= !all_sent;
+        Ok(seq_id)
+    }
+
+    fn send_data_inner(&mut self, data: Arc<[u8]>, message_type: MessageType, message_priority: MessagePriority, callback: Option<DeliveryCallback>) -> Result<u32, SendError> {
+        self.check_message_size(data.len())?;
+        let seq_id = self.next_regular_seq_id()?;
+        self.send_data_with_seq_id(seq_id, data, message_type, message_priority, callback)?;
+        Ok(seq_id)
+    }
+
+    /// If some acks are held, corks the socket and queues them, so that they are packed with what is sent next.
+    ///
+    /// Returns true if the socket must be uncorked once the message is sent. A socket already corked by the application
+    /// is left corked, the acks are packed along with everything else when it is uncorked.
+    fn cork_held_acks(&mut self) -> bool {
+        if self.held_acks.is_empty() {
+            return false;
+        }
+        let corked_here = !self.socket.is_corked();
+        self.socket.cork();
+        for (seq_id, ack) in ::std::mem::take(&mut self.held_acks) {
+            let _r = self.send_ack(seq_id, ack);
+        }
+        corked_here
+    }
+
+    fn next_regular_seq_id(&mut self) -> Result<u32, SendError> {
+        let seq_id = self.next_local_seq_id;
+        if !self.reserved_seq_ids_start.map_or(true, |start| seq_id < start) {
+            return Err(SendError::SeqIdsExhausted);
+        }
+        self.next_local_seq_id += 1;
+        Ok(seq_id)
+    }
+
+    /// First sends are never delayed, but they use up the share left for resends.
+    fn use_send_allowance(&mut self, len: usize) {
+        if let Some(allowance) = self.send_allowance.as_mut() {
+            *allowance = allowance.saturating_sub(len);
+        }
+        self.tick_bytes_left = self.tick_bytes_left.saturating_sub(len);
+    }
+
+    fn send_data_with_seq_id(&mut self, seq_id: u32, data: Arc<[u8]>, message_type: MessageType, message_priority: MessagePriority, callback: Option<DeliveryCallback>) -> Result<(), SendError> {
+        let len = data.len();
+        let had_pending = self.sent_data_tracker.has_pending();
+        let piggybacked = self.cork_held_acks();
+        let sent = self.sent_data_tracker.send_data(seq_id, data, self.cached_now, message_type, message_priority, &self.socket);
+        if piggybacked {
+            let _r = self.socket.uncork();
+        }
+        let all_sent = sent?;
+        self.use_send_allowance(len);
+        if message_type.has_ack() {
+            self.ping_handler.ping(seq_id);
+        }
+        if message_type.has_ack() && !had_pending {
+            self.asymmetry_detector.start_waiting(self.cached_now);
+        }
+        self.last_send_queued = !all_sent || self.socket.is_corked();
+        self.congested |= !all_sent;
+        if let Some(callback) = callback {
+            self.sent_data_tracker.set_callback(seq_id, callback);
+        }
+        Ok(())
+    }
+
+    /// Keeps the seq_ids from `start` to `u32::MAX` (included) out of the ones given by `send_data` and the like,
+    /// so that they can be picked by the application with `send_data_in_reserved_range`.
+    ///
+    /// This lets an application build its own control messages on top of the reliability of this crate, and tell them
+    /// apart on the remote with `ReceivedData::seq_id`: both ends must agree on `start`, since it is never sent.
+    ///
+    /// seq_ids never wrap around: a connection can send at most `start` messages outside of the reserved range,
+    /// sending more returns `SendError::SeqIdsExhausted`. Returns false and leaves the range untouched if `start` was already given to a message.
+    pub fn reserve_seq_ids(&mut self, start: u32) -> bool {
+        if start < self.next_local_seq_id {
+            return false;
+        }
+        self.reserved_seq_ids_start = Some(start);
+        true
+    }
+
+    /// Same as `send_data`, but with a `seq_id` picked in the range reserved with `reserve_seq_ids`.
+    ///
+    /// Returns false and sends nothing if `seq_id` is outside of the reserved range, if the message is too large,
+    /// or if the message previously sent with that seq_id is still being sent. Even then, the remote may still take a message re-using a seq_id
+    /// for a duplicate of the previous one if they are too close together: use every seq_id of the range once if possible.
+    pub fn send_data_in_reserved_range(&mut self, seq_id: u32, data: Arc<[u8]>, message_type: MessageType, message_priority: MessagePriority) -> bool {
+        let is_reserved = self.reserved_seq_ids_start.is_some_and(|start| seq_id >= start);
+        if !is_reserved || self.check_message_size(data.len()).is_err() || self.sent_data_tracker.is_seq_id_received(seq_id).is_ok() {
+            return false;
+        }
+        self.send_data_with_seq_id(seq_id, data, message_type, message_priority, None).is_ok()
+    }
+
+    /// Sends the latest version of some state, like the position of the entities of a game, on `channel`.
+    ///
+    /// The message is re-sent until the remote acknowledges it like a `KeyMessage`, unless a newer message is sent
+    /// on the same channel first: the older one is then never re-sent again, only the newest matters.
+    /// Channels are only known by the sender, use as many as you have independent pieces of state.
+    ///
+    /// An older message may still arrive after a newer one, if it was sent before it was superseded.
+    /// The remote should only keep the message with the highest seq_id for every piece of state.
+    ///
+    /// Returns the seq_id of the message sent, or an error like `send_data`. A message too large to be sent
+    /// doesn't supersede the previous one.
+    pub fn send_latest(&mut self, channel: u16, data: Arc<[u8]>, message_priority: MessagePriority) -> Result<u32, SendError> {
+        let seq_id = self.send_data_inner(data, MessageType::KeyMessage, message_priority, None)?;
+        if let Some(superseded) = self.sent_data_tracker.supersede(channel, seq_id) {
+            log::trace!("socket {}: seq_id={} superseded by seq_id={} on channel {}", self.remote_addr(), superseded, seq_id, channel);
+        }
+        Ok(seq_id)
+    }
+
+    /// Stops sending every message that the remote has not fully acknowledged yet, and returns how many there were.
+    ///
+    /// Nothing already sent is re-sent anymore: use this when the application discards everything
+    /// it was sending, for instance when changing levels. What we receive and the connection itself are left untouched.
+    /// The seq_ids of the cancelled messages are forgotten, like the ones of expired messages.
+    pub fn cancel_all_pending(&mut self) -> usize {
+        self.sent_data_tracker.cancel_pending()
+    }
+
+    /// Opens a stream to send data as it is produced, instead of building the whole message first.
+    ///
+    /// The data is sent as a sequence of messages of type `message_type`, see `SendStream`.
+    pub fn open_stream(&mut self, message_type: MessageType, message_priority: MessagePriority) -> SendStream<'_> {
+        SendStream::new(self, message_type, message_priority)
+    }
+
+    /// Sends data of any size, split into as many messages as needed. Returns their seq_ids, in order.
+    ///
+    /// Each message is at most `max_message_size`, and they are all given to `send_data` right away: like
+    /// with `open_stream`, the remote receives them as separate `Data` events with consecutive seq_ids, and has
+    /// to put them back together itself. Use `large_send_complete` to know when all of them were received.
+    ///
+    /// Empty data is not sent at all, and no seq_id is returned. Nothing is sent either if there are not
+    /// enough seq_ids left below the range reserved with `reserve_seq_ids` for every part.
+    pub fn send_large(&mut self, data: Arc<[u8]>, message_type: MessageType, message_priority: MessagePriority) -> Result<Vec<u32>, SendError> {
+        if data.is_empty() {
+            return Ok(Vec::new());
+        }
+        if data.len() <= self.max_message_size() {
+            return self.send_data(data, message_type, message_priority).map(|seq_id| vec![seq_id]);
+        }
+        let max_message_size = self.max_message_size();
+        let parts = (data.len() + max_message_size - 1) / max_message_size;
+        if let Some(start) = self.reserved_seq_ids_start {
+            if ((start - self.next_local_seq_id.min(start)) as usize) < parts {
+                return Err(SendError::SeqIdsExhausted);
+            }
+        }
+        data.chunks(max_message_size)
+            .map(|part| self.send_data(Arc::from(part), message_type, message_priority))
+            .collect()
+    }
+
+    /// Returns true once every message of `seq_ids` has been acknowledged by the remote, see `send_large`.
+    ///
+    /// Like `is_seq_id_received`, this only works for messages that are still tracked: it stays false forever for
+    /// `Forgettable` messages, and once the messages are forgotten after `set_cleanup_delay`.
+    pub fn large_send_complete(&self, seq_ids: &[u32]) -> bool {
+        seq_ids.iter().all(|seq_id| self.delivery_status(*seq_id) == DeliveryStatus::Acknowledged)
+    }
+
+    /// Returns true if the last message given to `send_data` did not entirely hit the wire right away.
+    ///
+    /// This happens when the socket is corked, or when the send buffer of the OS was full: the fragments
+    /// that could not be sent are tried again next tick. A message that was queued is not lost,
+    /// but it is a sign that we are sending more than the link can take right now.
+    pub fn was_last_send_queued(&self) -> bool {
+        self.last_send_queued
+    }
+
+    /// Set a function called when we can send freely again, after some data had to be held back.
+    ///
+    /// Data is held back when the send buffer of the OS is full, or when the send budget of the `RUdpServer`
+    /// is used up. `f` is called once, during `next_tick`, when everything held back has been sent:
+    /// it is not called again before something else is held back.
+    pub fn set_on_writable<F: FnMut() + Send + 'static>(&mut self, f: F) {
+        self.on_writable = WritableHook(Some(Box::new(f)));
+    }
+
+    /// Holds every outgoing packet until `uncork` is called, instead of sending them right away.
+    ///
+    /// On `uncork`, the held packets are packed in as few datagrams as possible. This is useful when
+    /// you are about to send several small messages in a row: they will share datagrams
+    /// instead of being sent one by one.
+    ///
+    /// Everything is held while corked, including acks and heartbeats: don't stay corked across
+    /// several ticks, or the remote might time out.
+    pub fn cork(&mut self) {
+        self.socket.cork();
+    }
+
+    /// Sends everything held since `cork`, packed in as few datagrams as possible.
+    ///
+    /// Does nothing if the socket is not corked.
+    pub fn uncork(&mut self) -> IoResult<()> {
+        self.socket.uncork()
+    }
+
+    /// Returns whether or not the seq_id has been received by the remote.
+    ///
+    /// Ok(true) = has been received
+    /// Ok(false) = has not been received yet
+    /// Err(()) = the message is not tracked: the seq_id was never given by `send_data`, the message is `Forgettable`,
+    /// or it was forgotten `set_cleanup_delay` after being acknowledged or after expiring.
+    ///
+    /// A message acknowledged a while ago returns Err, not Ok(true): poll while the message is still tracked,
+    /// or use `send_data_with_callback`. See also `delivery_status`, which tells the same in an enum.
+    #[allow(clippy::result_unit_err)]
+    pub fn is_seq_id_received(&self, seq_id: u32) -> Result<bool, ()> {
+        self.sent_data_tracker.is_seq_id_received(seq_id)
+    }
+
+    /// Same as `is_seq_id_received`, but the answer is spelled out: `Unknown` stands for `Err(())`.
+    pub fn delivery_status(&self, seq_id: u32) -> DeliveryStatus {
+        self.sent_data_tracker.delivery_status(seq_id)
+    }
+
+    /// Returns the delay after which the message `seq_id` will be sent again, counted from the last time it was sent.
+    ///
+    /// This starts as the resend delay of the priority of the message, but partial acks
+    /// from the remote can make us resend sooner, and congestion later (see `congestion_factor`).
+    /// Returns None if the message is not waiting to be acknowledged: it is forgettable, complete, expired or unknown.
+    pub fn effective_resend_delay(&self, seq_id: u32) -> Option<Duration> {
+        self.sent_data_tracker.effective_resend_delay(seq_id)
+    }
+
+    /// Returns how much the resend delays are stretched because most of what we send has to be resent:
+    /// 1.0 when the link is fine, up to 8.0.
+    ///
+    /// Every few resends or acknowledged messages, the factor doubles if more than half of them were resends,
+    /// and goes back towards 1.0 by a small step otherwise. This keeps the resends from making a congested
+    /// link worse, at the cost of latency while it lasts.
+    pub fn congestion_factor(&self) -> f64 {
+        self.sent_data_tracker.congestion_factor()
+    }
+
+    /// Returns the last ack the remote sent us for the message `seq_id`, as it was received.
+    ///
+    /// Bit `n % 8` of byte `n / 8` is set if the remote claims to have received the fragment `n`.
+    /// Returns None if no ack was received for this message yet, or if the message is not tracked
+    /// anymore (it is forgettable, expired, or was acknowledged a while ago).
+    ///
+    /// This is mostly useful to debug a transfer which is stuck.
+    pub fn last_ack_bitmap(&self, seq_id: u32) -> Option<Box<[u8]>> {
+        self.sent_data_tracker.last_ack_bitmap(seq_id)
+    }
+
+    /// Returns the messages that have not been fully acknowledged by the remote yet.
+    ///
+    /// They are sorted by urgency: the first one is the next that will be re-sent.
+    /// Forgettable messages are never re-sent, so they never appear here, even with `ForgettableWithAck`.
+    pub fn pending_schedule(&self) -> Vec<PendingInfo> {
+        self.sent_data_tracker.pending_schedule()
+    }
+
+    /// Returns how many messages have not been fully acknowledged by the remote yet.
+    ///
+    /// Same as the length of `pending_schedule`, without building it.
+    pub fn pending_sends(&self) -> usize {
+        self.sent_data_tracker.pending_count()
+    }
+
+    /// Returns the size of the messages not fully acknowledged by the remote yet, in bytes.
+    ///
+    /// Useful to apply backpressure: if it keeps growing, the remote can't keep up with what we send,
+    /// and sending less, or less often, may be a good idea.
+    pub fn pending_bytes(&self) -> usize {
+        self.sent_data_tracker.pending_bytes()
+    }
+
+    fn send_udp_packet<P: AsRef<[u8]>>(&mut self, udp_packet: &UdpPacket<P>) -> std::io::Result<()> {
+        self.last_sent_message = self.cached_now;
+        self.socket.send_udp_packet(udp_packet)
+    }
+
+    /// Should only be used by connect
+    fn send_syn(&mut self) -> ::std::io::Result<()> {
+        let udp_packet = match self.previous_token {
+            Some(token) => {
+                let payload: Vec<u8> = token.to_be_bytes().iter().chain(self.syn_payload.iter()).copied().collect();
+                UdpPacket::from(&Packet::Rejoin(payload))
+            },
+            None => UdpPacket::from(&Packet::Syn(&self.syn_payload)),
+        };
+        let udp_packet = udp_packet.with_seq_id_field(self.max_payload_size as u32);
+        self.send_udp_packet(&udp_packet)
+    }
+
+    pub (crate) fn send_synack(&mut self) -> ::std::io::Result<()> {
+        let payload: Box<[u8]> = match self.connection_token {
+            Some(token) => Box::new(token.to_be_bytes()),
+            None => Box::new([]),
+        };
+        let p: Packet<Box<[u8]>> = Packet::SynAck(payload);
+        let udp_packet = UdpPacket::from(&p).with_seq_id_field(self.max_payload_size as u32);
+        self.send_udp_packet(&udp_packet)
+    }
+
+    pub (self) fn send_ack<D: AsRef<[u8]> + 'static>(&mut self, seq_id: u32, ack: Ack<D>) -> ::std::io::Result<()> {
+        let p: Packet<D> = Packet::Ack(seq_id, ack.into_inner());
+        let udp_packet = UdpPacket::from(&p);
+        self.send_udp_packet(&udp_packet)
+    }
+
+    /// Same as `terminate`, but leave the Socket alive.
+    ///
+    /// This is mostly useful if you want to still receive the data the other remote is currently
+    /// sending at this time. However, note that no acks will be sent, so its usefulness
+    /// is still limited.
+    pub fn send_end(&mut self) -> ::std::io::Result<()> {
+        let p: Packet<Box<[u8]>> = Packet::End(self.next_local_seq_id.saturating_sub(1));
+        let udp_packet = UdpPacket::from(&p);
+        self.send_udp_packet(&udp_packet)
+    }
+
+    /// Terminates the socket, by sending a "Ended" event to the remote.
+    ///
+    /// Messages the remote has not acknowledged yet are not sent anymore, and may never arrive.
+    /// Use `drain_and_terminate` to wait for them first, for instance for a last key message that must get through.
+    pub fn terminate(mut self) -> IoResult<()> {
+        self.send_end()
+    }
+
+    /// Ticks the socket until the remote has acknowledged every message we sent, then terminates it.
+    ///
+    /// Returns true if everything was acknowledged, false if `deadline` passed first or if the connection
+    /// ended in the meantime. The socket is terminated either way. This blocks the current thread, and the
+    /// events received while draining are lost: use `pending_sends` with your own loop if you need them.
+    pub fn drain_and_terminate(mut self, deadline: Duration) -> IoResult<bool> {
+        let started_at = Instant::now();
+        let drained = loop {
+            self.next_tick()?;
+            if self.pending_sends() == 0 {
+                break true;
+            }
+            if self.status().is_finished() || started_at.elapsed() >= deadline {
+                break false;
+            }
+            ::std::thread::sleep(DRAIN_TICK_INTERVAL);
+        };
+        if !drained {
+            log::debug!("socket {}: {} messages were still pending when terminating", self.remote_addr(), self.pending_sends());
+        }
+        self.terminate()?;
+        Ok(drained)
+    }
+
+    /// Same as `drain_and_terminate`, but reports messages left unacknowledged as an error.
+    ///
+    /// Returns an error of kind `TimedOut` if `timeout` passed or the connection ended before the remote
+    /// acknowledged everything. The socket is terminated either way.
+    pub fn terminate_graceful(self, timeout: Duration) -> IoResult<()> {
+        if self.drain_and_terminate(timeout)? {
+            Ok(())
+        } else {
+            Err(IoError::new(IoErrorKind::TimedOut, "some messages were not acknowledged before terminating"))
+        }
+    }
+
+    fn send_heartbeat(&mut self) -> ::std::io::Result<()> {
+        let p: Packet<Box<[u8]>> = Packet::Heartbeat;
+        let udp_packet = UdpPacket::from(&p);
+        self.send_udp_packet(&udp_packet)
+    }
+
+    fn send_migrate(&mut self, token: u64) -> ::std::io::Result<()> {
+        let p: Packet<Box<[u8]>> = Packet::Migrate(Box::new(token.to_be_bytes()));
+        let udp_packet = UdpPacket::from(&p);
+        self.migrate_sent = Some(self.cached_now);
+        self.send_udp_packet(&udp_packet)
+    }
+
+    fn send_probe(&mut self, probe_id: u32) -> ::std::io::Result<()> {
+        let p: Packet<Box<[u8]>> = Packet::Probe(probe_id);
+        let udp_packet = UdpPacket::from(&p);
+        self.send_udp_packet(&udp_packet)
+    }
+
+    fn send_probe_ack(&mut self, probe_id: u32) -> ::std::io::Result<()> {
+        let p: Packet<Box<[u8]>> = Packet::ProbeAck(probe_id);
+        let udp_packet = UdpPacket::from(&p);
+        self.send_udp_packet(&udp_packet)
+    }
+
+    /// Actively checks that the remote can still be reached and can still reach us.
+    ///
+    /// A probe is sent right away, and re-sent a few times until the remote answers. If it answers
+    /// within `deadline`, `SocketEvent::ProbeSucceeded` is emitted, otherwise `SocketEvent::ProbeFailed`
+    /// is, without having to wait for the whole timeout delay. The round trip of the probe is used as
+    /// a ping sample as well.
+    ///
+    /// Only one probe can be pending at a time: starting a new one abandons the previous one,
+    /// which will not emit any event. Returns the id of the probe, which comes along with its event.
+    ///
+    /// The remote must run a version of this crate which answers probes, otherwise probes always fail.
+    pub fn probe(&mut self, deadline: Duration) -> IoResult<u32> {
+        let probe_id = self.next_probe_id;
+        self.next_probe_id = self.next_probe_id.wrapping_add(1);
+        self.pending_probe = Some(PendingProbe {
+            probe_id,
+            deadline: self.cached_now + deadline,
+            resend_delay: deadline / PROBE_SENDS,
+            last_sent: self.cached_now,
+        });
+        self.send_probe(probe_id)?;
+        Ok(probe_id)
+    }
+
+    /// Continue this connection from a new local address, for instance after switching networks.
+    ///
+    /// A new UDP socket is bound to `new_local_addr`, and the remote is told to send everything there from now on.
+    /// Reliable messages which were in flight during the switch are re-sent as usual,
+    /// so nothing is lost, unlike when connecting again.
+    ///
+    /// This only works with a `RUdpServer` as the remote, which gives us a token when we connect
+    /// to prove the connection is ours. Returns an error if we haven't received any such token.
+    pub fn rebind<A: ToSocketAddrs>(&mut self, new_local_addr: A) -> IoResult<()> {
+        let token = self.remote_token.ok_or_else(|| IoError::new(IoErrorKind::Other, "the remote has not given us a connection token, the connection can't be migrated"))?;
+        let udp_socket = UdpSocket::bind(new_local_addr)?;
+        udp_socket.set_nonblocking(true)?;
+        self.local_addr = udp_socket.local_addr()?;
+        self.socket.udp_socket = Arc::new(udp_socket);
+        log::info!("socket {}: migrating to local address {}", self.remote_addr(), self.local_addr);
+        self.send_migrate(token)
+    }
+
+    /// Continue this connection from a dedicated `udp_socket`, bound to `local_addr`, and tell the remote to send there.
+    ///
+    /// Used when a remote is detached from a `RUdpServer`, see `RUdpServer::detach`.
+    pub (crate) fn move_to_own_socket(&mut self, udp_socket: UdpSocket, local_addr: SocketAddr) {
+        self.local_addr = local_addr;
+        self.socket.udp_socket = Arc::new(udp_socket);
+        log::info!("socket {}: moving to dedicated local address {}", self.remote_addr(), self.local_addr);
+        if let Some(token) = self.migration_token() {
+            // re-sent during the next ticks if this one is lost
+            let _r = self.send_migrate(token);
+        }
+    }
+
+    /// Token proving to the remote that a Migrate comes from us: the one it gave us if we connected to it,
+    /// the one we gave it otherwise.
+    fn migration_token(&self) -> Option<u64> {
+        self.remote_token.or(self.connection_token)
+    }
+
+    /// The remote proved it owns this connection from `new_remote_addr`: send everything there from now on.
+    ///
+    /// `udp_socket` is the socket the remote now sends to, which may differ if the server has several listeners.
+    pub (crate) fn migrate_to(&mut self, new_remote_addr: SocketAddr, udp_socket: Arc<UdpSocket>) -> IoResult<()> {
+        let from = self.socket.remote_addr;
+        log::info!("remote {} migrated to {}", from, new_remote_addr);
+        self.socket.remote_addr = new_remote_addr;
+        self.socket.udp_socket = udp_socket;
+        self.last_received_message = self.cached_now;
+        self.events.push_back(SocketEvent::Migrated { from });
+        // let the remote know right away that we received the migration
+        self.send_heartbeat()
+    }
+
+    /// Returns true if `udp_packet` is a Migrate holding the token the remote gave us when we connected to it.
+    fn is_remote_migration(&self, udp_packet: &UdpPacket<Box<[u8]>>) -> bool {
+        if self.remote_token.is_none() || self.status().is_finished() {
+            return false;
+        }
+        match udp_packet.compute_packet_meta() {
+            Ok(PacketMeta::Migrate) => read_token(&udp_packet.as_bytes()[PACKET_DATA_START_BYTE..]) == self.remote_token,
+            _ => false,
+        }
+    }
+
+    pub (self) fn send_abort(&mut self) -> ::std::io::Result<()> {
+        let p: Packet<Box<[u8]>> = Packet::Abort(self.next_local_seq_id.saturating_sub(1));
+        let udp_packet = UdpPacket::from(&p);
+        self.send_udp_packet(&udp_packet)
+    }
+
+    /// Add a packet to a queue, to be processed later.
+    pub (crate) fn add_received_packet(&mut self, udp_packet: UdpPacket<Box<[u8]>>) {
+        self.last_received_message = self.cached_now;
+        self.received_since_tick = true;
+        // the remote sends to our new address, so it has received our Migrate
+        self.migrate_sent = None;
+        self.asymmetry_detector.received_packet();
+        let size = udp_packet.as_bytes().len();
+        self.socket.update_stats(|stats| stats.record_received(size));
+        log::trace!("received packet {:?} from remote {}", udp_packet, self.socket.remote_addr);
+        let (discard_hook, remote_addr) = (&self.discard_hook, self.socket.remote_addr);
+        let mut malformed_packets = 0;
+        self.packet_handler.add_received_packet(udp_packet, self.cached_now, &mut |reason, bytes| {
+            log::trace!("discarding packet from {}: {:?}", remote_addr, reason);
+            malformed_packets += 1;
+            discard_hook.call(remote_addr, reason, bytes)
+        });
+        if malformed_packets > 0 {
+            self.received_malformed_packets(malformed_packets);
+        }
+    }
+
+    /// Aborts this connection because the remote connected again from `new_addr`.
+    pub (crate) fn replaced_by(&mut self, new_addr: SocketAddr) {
+        log::info!("socket {}: the remote connected again from {}, aborting this connection", self.remote_addr(), new_addr);
+        let _r = self.send_abort();
+        // not `set_status`: this is not a regular end, the event is our own
+        self.socket.set_status(SocketStatus::TerminateSent(self.cached_now));
+        self.events.push_back(SocketEvent::Replaced { by: new_addr });
+    }
+
+    fn received_malformed_packets(&mut self, count: u32) {
+        self.socket.update_stats(|stats| stats.malformed_packets += count as u64);
+        self.malformed_packets = self.malformed_packets.saturating_add(count);
+        if let Some(threshold) = self.malformed_packet_threshold {
+            if self.malformed_packets >= threshold && !self.status().is_finished() {
+                log::warn!("socket {}: received {} malformed packets, aborting the connection", self.remote_addr(), self.malformed_packets);
+                let _r = self.send_abort();
+                // not `set_status`: this is not a regular end, the event is our own
+                self.socket.set_status(SocketStatus::TerminateSent(self.cached_now));
+                self.events.push_back(SocketEvent::TooManyMalformedPackets);
+            }
+        }
+    }
+
+    /// Sorts the `Data` events from index `from` by priority and by `delivery_order`, in the slots they already occupy.
+    fn order_received_data(&mut self, from: usize) {
+        let mut data: Vec<ReceivedData> = Vec::new();
+        // None marks the slot of a `Data` event
+        let slots: Vec<Option<SocketEvent>> = self.events.split_off(from).into_iter().map(|event| match event {
+            SocketEvent::Data(d) => {
+                data.push(d);
+                None
+            },
+            event => Some(event),
+        }).collect();
+        self.delivery_order.sort(&mut data, self.receive_priority_order);
+        let mut data = data.into_iter();
+        self.events.extend(slots.into_iter().filter_map(|slot| slot.or_else(|| data.next().map(SocketEvent::Data))));
+    }
+
+    /// Process the next paquet received in the queue.
+    fn next_packet_event(&mut self) -> Option<SocketEvent> {
+        loop {
+            let r = self.packet_handler.next_received_message();
+            if self.socket.status() == SocketStatus::SynReceived {
+                if let Some(ReceivedMessage::Ack(..)) | Some(ReceivedMessage::Data(_)) | Some(ReceivedMessage::Heartbeat) = r {
+                    // the remote received our SynAck (or in a simultaneous open, our SynAck may have been lost,
+                    // but the remote obviously considers itself connected to us)
+                    log::info!("connected to remote {}", self.remote_addr());
+                    self.set_status(SocketStatus::Connected);
+                }
+            }
+            match r {
                 None => return None,
                 Some(ReceivedMessage::Abort(_id)) => {
+                    let last_status = self.status();
                     self.set_status(SocketStatus::TerminateReceived(self.cached_now));
-                    return Some(SocketEvent::Aborted)
+                    return Some(SocketEvent::Aborted { last_status })
                 },
                 Some(ReceivedMessage::Ack(seq_id, data)) => {
                     self.ping_handler.pong(seq_id);
-                    self.sent_data_tracker.receive_ack(seq_id, data, self.cached_now);
+                    self.asymmetry_detector.received_ack(self.cached_now);
+                    let tracked = self.sent_data_tracker.receive_ack(seq_id, data, self.cached_now);
+                    let was_sent = seq_id < self.next_local_seq_id || self.reserved_seq_ids_start.is_some_and(|start| seq_id >= start);
+                    if !tracked && was_sent {
+                        log::debug!("socket {}: received a late ack for seq_id={}", self.remote_addr(), seq_id);
+                        self.socket.update_stats(|stats| stats.late_acks += 1);
+                    }
                 },
-                Some(ReceivedMessage::Data(_id, data)) => {
+                Some(ReceivedMessage::Data(data)) => {
                     log::trace!("received data {:?} from remote {}", data, self.socket.remote_addr);
+                    self.received_seq_ids.insert(data.seq_id);
                     return Some(SocketEvent::Data(data))
                 },
-                Some(ReceivedMessage::End(_id)) => {
-                    self.set_status(SocketStatus::TerminateReceived(self.cached_now));
-                    return Some(SocketEvent::Ended)
+                Some(ReceivedMessage::End(last_seq_id)) => {
+                    // End(0) is also what a remote which sent nothing at all sends: if nothing came from it either,
+                    // there is nothing to wait for
+                    let nothing_sent = last_seq_id == 0 && self.received_seq_ids.is_empty() && self.packet_handler.reassembly_buffered_bytes() == 0;
+                    if self.end_grace_period > Duration::from_secs(0) && !self.status().is_finished() && !nothing_sent {
+                        if self.pending_end.is_none() {
+                            log::debug!("socket {}: received End, waiting up to {:?} for the remaining data", self.remote_addr(), self.end_grace_period);
+                            self.pending_end = Some((self.cached_now, last_seq_id));
+                        }
+                    } else {
+                        let last_status = self.status();
+                        self.set_status(SocketStatus::TerminateReceived(self.cached_now));
+                        return Some(SocketEvent::Ended { last_status })
+                    }
                 },
                 Some(ReceivedMessage::Heartbeat) => {},
-                Some(ReceivedMessage::SynAck) => {
-                    if let SocketStatus::SynSent(_) = self.socket.status() {
+                Some(ReceivedMessage::Probe(probe_id)) => {
+                    let _r = self.send_probe_ack(probe_id);
+                },
+                Some(ReceivedMessage::ProbeAck(probe_id)) => {
+                    if let Some(pending_probe) = self.pending_probe.filter(|p| p.probe_id == probe_id) {
+                        self.pending_probe = None;
+                        self.ping_handler.record_sample(pending_probe.last_sent);
+                        let rtt = self.cached_now - pending_probe.last_sent;
+                        return Some(SocketEvent::ProbeSucceeded { probe_id, rtt })
+                    } else {
+                        log::trace!("socket {}: ignoring ProbeAck({}) for a probe we don't wait for", self.remote_addr(), probe_id);
+                    }
+                },
+                Some(ReceivedMessage::Dropped(seq_id, received, frag_total)) => {
+                    self.socket.update_stats(|stats| stats.inbound_messages_dropped += 1);
+                    log::warn!("socket {}: gave up on incomplete message seq_id={} ({}/{} fragments)", self.remote_addr(), seq_id, received, frag_total);
+                    return Some(SocketEvent::InboundMessageDropped { seq_id, received, frag_total })
+                },
+                Some(ReceivedMessage::SynAck(token, advertised_payload_size)) => {
+                    if matches!(self.socket.status(), SocketStatus::SynSent(_) | SocketStatus::SynReceived) {
+                        self.set_remote_max_payload_size(advertised_payload_size);
+                    }
+                    if let SocketStatus::SynSent(syn_sent_at) = self.socket.status() {
                         log::info!("connected to remote {}", self.remote_addr());
+                        self.remote_token = token;
+                        // the handshake is a round trip as well, use it as our first ping sample
+                        self.ping_handler.record_sample(syn_sent_at);
                         self.set_status(SocketStatus::Connected);
+                        // let the remote know we received its SynAck, it won't consider itself connected before that
+                        let _r = self.send_heartbeat();
+                    } else if self.socket.status() == SocketStatus::SynReceived {
+                        log::info!("connected to remote {}", self.remote_addr());
+                        self.set_status(SocketStatus::Connected);
+                    } else if self.socket.status() == SocketStatus::Connected {
+                        // our answer to the previous SynAck was lost, send it again
+                        log::debug!("received a duplicate synack from {}", self.remote_addr());
+                        let _r = self.send_heartbeat();
                     } else {
                         log::warn!("received synack while the status isn't synsent for {}", self.remote_addr());
                         /* received synack when the status isn't even SynSent? Mmmh... */
                     }
                 },
-                Some(ReceivedMessage::Syn) => {
+                Some(ReceivedMessage::Syn(_, advertised_payload_size)) if matches!(self.socket.status(), SocketStatus::SynSent(_)) => {
+                    // simultaneous open: both ends are trying to connect to each other
+                    log::info!("received a syn from {} while connecting, answering with a synack", self.remote_addr());
+                    self.set_remote_max_payload_size(advertised_payload_size);
+                    self.set_status(SocketStatus::SynReceived);
+                    let _r = self.send_synack();
+                },
+                Some(ReceivedMessage::Syn(_, advertised_payload_size)) if self.socket.status() == SocketStatus::SynReceived => {
+                    self.set_remote_max_payload_size(advertised_payload_size);
+                    // the remote is still waiting for our SynAck, it must have been lost
+                    log::debug!("received a syn from {} again, resending a synack", self.remote_addr());
+                    let _r = self.send_synack();
+                },
+                Some(ReceivedMessage::Syn(payload, _)) => {
                     log::warn!("received a syn message while already connected {}, resending a synack", self.remote_addr());
+                    // the payload still holds the whole datagram it came in, no need to build it again
+                    self.discard_hook.call(self.remote_addr(), DiscardReason::SynWhileConnected, payload.as_unstripped_slice());
                     let _r = self.send_synack();
                     /* do nothing for special now, but we may want to handle "syn" later to
                     have a 'reconnect' feature or something? */
@@ -493,32 +2129,252 @@ impl RUdpSocket {
         };
     }
 
-    /// Returns the ping to the remote as ms
+    /// Returns how many fragments we still need to receive to complete the incoming message `seq_id`.
+    ///
+    /// Returns None if we haven't received any fragment for this message yet,
+    /// or if the message is already complete.
+    pub fn missing_fragment_count(&self, seq_id: u32) -> Option<usize> {
+        self.packet_handler.missing_fragment_count(seq_id)
+    }
+
+    /// Returns how many bytes of incomplete incoming messages we are holding, waiting for their other fragments.
+    ///
+    /// See `set_reassembly_limit` to cap it.
+    pub fn reassembly_buffered_bytes(&self) -> usize {
+        self.packet_handler.reassembly_buffered_bytes()
+    }
+
+    /// Returns true if the incoming message `seq_id` has started arriving, but is not complete yet.
+    ///
+    /// Incomplete messages are given up on after a while, see `SocketEvent::InboundMessageDropped`:
+    /// this returns false again from then on.
+    pub fn is_receiving(&self, seq_id: u32) -> bool {
+        self.packet_handler.is_receiving(seq_id)
+    }
+
+    /// Returns how many incoming messages were discarded because their fragments were inconsistent,
+    /// sorted by reason.
+    pub fn corrupted_sets(&self) -> CorruptedSetsStats {
+        self.packet_handler.corrupted_sets()
+    }
+
+    /// Returns the traffic counters of this connection.
+    pub fn stats(&self) -> ConnectionStats {
+        self.socket.stats.get()
+    }
+
+    /// Sets all the counters of `stats` and `corrupted_sets` back to 0.
+    ///
+    /// Useful to sample them periodically and get per-interval values instead of cumulative ones.
+    /// The state of the connection itself is left untouched.
+    pub fn reset_stats(&mut self) {
+        self.socket.stats.set(ConnectionStats::default());
+        self.rate_meter.reset_counters();
+        self.packet_handler.reset_corrupted_sets();
+    }
+
+    /// Returns how many bytes were sent to the remote during the last second, in bytes per second.
+    ///
+    /// Updated every tick, this is meant for live bandwidth graphs. Bytes are counted like in `stats`.
+    pub fn current_send_rate(&self) -> u64 {
+        self.rate_meter.send_rate()
+    }
+
+    /// Returns how many bytes were received from the remote during the last second, in bytes per second.
+    ///
+    /// See `current_send_rate`.
+    pub fn current_recv_rate(&self) -> u64 {
+        self.rate_meter.recv_rate()
+    }
+
+    /// Returns the ping to the remote as ms
+    ///
+    /// Returns None if the ping has not been computed yet
+    pub fn ping(&self) -> Option<u32> {
+        self.ping_handler.current_ping_ms()
+    }
+
+    /// Returns statistics of the pings to the remote since the connection started: min, max, average and jitter.
+    ///
+    /// Returns None if the ping has not been computed yet
+    pub fn ping_stats(&self) -> Option<PingStats> {
+        self.ping_handler.stats()
+    }
+
+    /// Returns when we last received a packet from the remote.
+    ///
+    /// The connection times out once this is older than the timeout delay.
+    pub fn last_received(&self) -> Instant {
+        self.last_received_message
+    }
+
+    /// Returns for how long we haven't received anything from the remote.
+    pub fn silence_duration(&self) -> Duration {
+        Instant::now().saturating_duration_since(self.last_received_message)
+    }
+
+    /// Returns how long until the connection times out if we keep receiving nothing from the remote.
+    ///
+    /// Returns `Some(0)` if the timeout is due but hasn't been processed yet by a tick, and None if
+    /// the connection is already finished.
+    /// While connecting, this is the time left before `set_connect_timeout`'s delay.
+    pub fn time_until_timeout(&self) -> Option<Duration> {
+        if self.status().is_finished() {
+            return None;
+        }
+        let deadline = match self.status() {
+            SocketStatus::SynSent(_) => self.created_at + self.connect_timeout,
+            _ => self.last_received_message + self.timeout_delay,
+        };
+        Some(deadline.saturating_duration_since(Instant::now()))
+    }
+
+    pub (crate) fn snapshot(&self) -> RemoteSnapshot {
+        let stats = self.stats();
+        let loss_estimate = if stats.packets_sent == 0 {
+            0.0
+        } else {
+            (stats.fragments_resent as f32 / stats.packets_sent as f32).min(1.0)
+        };
+        RemoteSnapshot {
+            addr: self.remote_addr(),
+            status: self.status(),
+            uptime: Instant::now().saturating_duration_since(self.created_at),
+            ping: self.ping(),
+            silence: self.silence_duration(),
+            time_until_timeout: self.time_until_timeout(),
+            pending_sends: self.sent_data_tracker.pending_count(),
+            bytes_sent: stats.bytes_sent,
+            bytes_received: stats.bytes_received,
+            loss_estimate,
+        }
+    }
+
+    pub (crate) fn export_state(&self) -> RemoteState {
+        RemoteState {
+            addr: self.remote_addr(),
+            connection_token: self.connection_token,
+            next_seq_id: self.next_local_seq_id,
+            max_payload_size: self.max_payload_size,
+            remote_max_payload_size: self.remote_max_payload_size,
+        }
+    }
+
+    /// Resumes a connection exported by another server, see `RUdpServer::import_state`.
+    ///
+    /// The remote doesn't know anything happened: the socket starts as connected, and waits for its packets.
+    pub (crate) fn from_state(udp_socket: Arc<UdpSocket>, state: &RemoteState, rng: &SharedRng) -> IoResult<RUdpSocket> {
+        let local_addr = udp_socket.local_addr()?;
+        let socket = UdpSocketWrapper::new(udp_socket, SocketStatus::Connected, state.addr);
+        let mut rudp_socket = RUdpSocket::with_socket(socket, local_addr, Instant::now());
+        rudp_socket.rng = rng.clone();
+        rudp_socket.connection_token = state.connection_token;
+        rudp_socket.next_local_seq_id = state.next_seq_id;
+        rudp_socket.max_payload_size = state.max_payload_size.clamp(MIN_PAYLOAD_SIZE, MAX_RCV_FRAGMENT_MESSAGE_SIZE);
+        rudp_socket.remote_max_payload_size = state.remote_max_payload_size.map(|size| size.clamp(MIN_PAYLOAD_SIZE, MAX_RCV_FRAGMENT_MESSAGE_SIZE));
+        rudp_socket.update_negotiated_payload_size();
+        Ok(rudp_socket)
+    }
+
+    pub (crate) fn update_cached_now(&mut self) {
+        self.cached_now = Instant::now();
+    }
+
+    /// Returns true if something has to be done for this connection at `now`: packets to process, events to drain,
+    /// messages or a heartbeat to send, a timeout to check, ... False means a tick would only find nothing to do.
     ///
-    /// Returns None if the ping has not been computed yet
-    pub fn ping(&self) -> Option<u32> {
-        self.ping_handler.current_ping_ms()
+    /// This errs on the side of caution: anything out of the ordinary (handshake, end of the connection, probe,
+    /// migration, message being reassembled) counts as work.
+    pub (crate) fn has_work(&self, now: Instant) -> bool {
+        self.has_pending_work() || self.next_due().is_some_and(|due| due <= now)
     }
 
-    pub (crate) fn update_cached_now(&mut self) {
-        self.cached_now = Instant::now();
+    /// Returns true if there is something to do right away, whatever the time is. See `next_due` for the rest.
+    pub (crate) fn has_pending_work(&self) -> bool {
+        self.received_since_tick
+            || !self.events.is_empty()
+            || !self.status().is_connected()
+            || !self.held_acks.is_empty()
+            || self.migrate_sent.is_some()
+            || self.pending_probe.is_some()
+            || self.sent_data_tracker.has_blocked_fragments()
+            || self.packet_handler.reassembly_buffered_bytes() > 0
+    }
+
+    /// Returns true if messages are waiting to be acknowledged or to be sent, and the connection is not finished.
+    pub (crate) fn has_data_to_send(&self) -> bool {
+        !self.status().is_finished()
+            && (self.send_deferred || self.sent_data_tracker.has_pending() || self.sent_data_tracker.has_blocked_fragments())
+    }
+
+    /// Returns when there will be something to do next (resend, heartbeat, timeout, ...) if nothing else happens until then.
+    pub (crate) fn next_due(&self) -> Option<Instant> {
+        let timeout = Some(self.last_received_message + self.timeout_delay);
+        let end_of_life = self.max_connection_lifetime.map(|lifetime| self.created_at + lifetime);
+        let end_of_grace = self.pending_end.map(|(end_received, _)| end_received + self.end_grace_period);
+        [self.sent_data_tracker.next_resend(), self.next_heartbeat(), timeout, end_of_life, end_of_grace].iter().flatten().min().copied()
     }
 
     pub (crate) fn inner_tick(&mut self) -> IoResult<()> {
+        self.received_since_tick = false;
         let acks_to_send = self.packet_handler.tick(self.cached_now);
+        let first_new_event = self.events.len();
         while let Some(socket_event) = self.next_packet_event() {
             self.events.push_back(socket_event);
         }
-        if self.cached_now >= self.last_received_message + self.timeout_delay && !self.socket.status().is_finished() {
+        if self.receive_priority_order || self.delivery_order.is_set() {
+            self.order_received_data(first_new_event);
+        }
+        if let SocketStatus::SynSent(_) = self.socket.status() {
+            if self.cached_now >= self.created_at + self.connect_timeout {
+                log::warn!("socket {}: the remote did not answer our connection request in {:?}", self.remote_addr(), self.connect_timeout);
+                self.set_status(SocketStatus::ConnectTimeout(self.cached_now));
+            }
+        } else if self.cached_now >= self.last_received_message + self.timeout_delay && !self.socket.status().is_finished() {
             let ago: Duration = self.cached_now - self.last_received_message;
             log::warn!("socket {} timed out: last_received_message was {}s ago", self.remote_addr(), ago.as_secs_f32());
             self.set_status(SocketStatus::TimeoutError(self.cached_now));
         }
-        for (seq_id, ack) in acks_to_send {
+        if let Some(max_connection_lifetime) = self.max_connection_lifetime {
+            if self.status().is_connected() && self.cached_now >= self.created_at + max_connection_lifetime {
+                log::info!("socket {}: connection reached its max lifetime of {:?}, ending it", self.remote_addr(), max_connection_lifetime);
+                self.send_end()?;
+                self.set_status(SocketStatus::TerminateSent(self.cached_now));
+            }
+        }
+        if self.status().is_connected() && self.asymmetry_detector.check(self.cached_now, self.sent_data_tracker.has_pending()) {
+            log::warn!("socket {}: receiving packets from the remote, but none of our key messages are acknowledged", self.remote_addr());
+            self.events.push_back(SocketEvent::AsymmetricConnectivity);
+        }
+        // the acks held during the last tick were not sent along with any message, they can't wait any longer
+        for (seq_id, ack) in ::std::mem::take(&mut self.held_acks) {
             self.send_ack(seq_id, ack)?;
         }
+        if self.ack_piggybacking {
+            self.held_acks = acks_to_send;
+        } else {
+            for (seq_id, ack) in acks_to_send {
+                self.send_ack(seq_id, ack)?;
+            }
+        }
+        if let (Some(migrate_sent), Some(token)) = (self.migrate_sent, self.migration_token()) {
+            if self.cached_now >= migrate_sent + MIGRATE_RESEND_DELAY && !self.status().is_finished() {
+                self.send_migrate(token)?;
+            }
+        }
+        if let Some(mut pending_probe) = self.pending_probe {
+            if self.cached_now >= pending_probe.deadline {
+                log::debug!("socket {}: probe {} was not answered in time", self.remote_addr(), pending_probe.probe_id);
+                self.pending_probe = None;
+                self.events.push_back(SocketEvent::ProbeFailed { probe_id: pending_probe.probe_id });
+            } else if self.cached_now >= pending_probe.last_sent + pending_probe.resend_delay && !self.status().is_finished() {
+                pending_probe.last_sent = self.cached_now;
+                self.pending_probe = Some(pending_probe);
+                self.send_probe(pending_probe.probe_id)?;
+            }
+        }
         if self.status().is_connected() {
-            if self.cached_now - self.last_sent_message > self.heartbeat_delay {
+            if self.heartbeat_enabled && self.cached_now - self.last_sent_message > self.effective_heartbeat_delay() {
                 self.send_heartbeat()?;
             }
         } else { 
@@ -533,7 +2389,48 @@ impl RUdpSocket {
                 }
             }
         }
-        self.sent_data_tracker.next_tick(self.cached_now, &self.socket);
+        if self.status().is_finished() {
+            self.sent_data_tracker.fail_callbacks();
+        }
+        let tick_bytes_left = if self.max_bytes_per_tick.is_some() { self.tick_bytes_left } else { usize::MAX };
+        let retransmit_budget = self.refill_retransmit_budget();
+        let available = self.send_allowance.unwrap_or(usize::MAX).min(tick_bytes_left).min(retransmit_budget);
+        let mut allowance = available;
+        let mut delivered = Vec::new();
+        self.send_deferred = self.sent_data_tracker.next_tick(self.cached_now, &self.socket, &mut allowance, &mut delivered);
+        self.events.extend(delivered.into_iter().map(SocketEvent::Delivered));
+        if self.status().is_connected() {
+            self.check_path_mtu();
+        }
+        self.use_send_allowance(available - allowance);
+        if self.max_retransmit_rate.is_some() {
+            self.retransmit_budget -= (available - allowance) as f64;
+        }
+        if let Some(max_bytes_per_tick) = self.max_bytes_per_tick {
+            // what was left unused is kept while resends wait, so that even a resend larger than the budget goes eventually
+            let carried = if self.send_deferred { self.tick_bytes_left } else { 0 };
+            self.tick_bytes_left = carried.saturating_add(max_bytes_per_tick);
+        }
+        let congested = self.send_deferred || self.sent_data_tracker.has_blocked_fragments();
+        if self.congested && !congested {
+            if let Some(f) = self.on_writable.0.as_mut() {
+                f();
+            }
+        }
+        self.congested = congested;
+        if let Some((end_received, last_seq_id)) = self.pending_end {
+            // this is done last, so that the acks of this tick are sent before we stop sending anything
+            let everything_received = self.received_seq_ids.contains_all_up_to(last_seq_id);
+            if everything_received || self.cached_now >= end_received + self.end_grace_period {
+                self.pending_end = None;
+                if !self.status().is_finished() {
+                    let last_status = self.status();
+                    self.set_status(SocketStatus::TerminateReceived(self.cached_now));
+                    self.events.push_back(SocketEvent::Ended { last_status });
+                }
+            }
+        }
+        self.rate_meter.record_tick(self.cached_now, &self.socket.stats.get());
         Ok(())
     }
 
@@ -545,22 +2442,53 @@ impl RUdpSocket {
     /// Do NOT use this method if you have multiple remotes for a single UdpSocket (a single port):
     /// all packets not coming from the right remote (matching IP and port) will be discarded!
     /// This warning applies if this socket has been borrowed from a `RUdpServer` as well,
-    /// because all the remotes are sharing the same port.
+    /// because all the remotes are sharing the same port. Use `RUdpServer::detach` to take it out of the server instead.
     pub fn next_tick(&mut self) -> IoResult<()> {
         self.update_cached_now();
+        self.receive_all()?;
+        // process everything we have received
+        self.inner_tick()?;
+        Ok(())
+    }
+
+    /// Feeds `bytes` to the socket as if it was a datagram received from `from`, without going through the network.
+    ///
+    /// Only available in tests and with the `testing` feature. This is meant for tests of the protocol itself:
+    /// corrupted packets, fragments out of order, packets from unknown addresses, ... The datagram is handled
+    /// like any other, so it is processed by the next tick. It is counted in the stats and goes through
+    /// the discard hook if it is discarded.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn inject_datagram(&mut self, bytes: &[u8], from: SocketAddr) -> IoResult<()> {
+        self.update_cached_now();
+        self.receive_datagram(UdpPacket::new(Box::from(bytes)), from)
+    }
+
+    fn receive_datagram(&mut self, packet: UdpPacket<Box<[u8]>>, remote_addr: SocketAddr) -> IoResult<()> {
+        if remote_addr == self.socket.remote_addr {
+            self.add_received_packet(packet);
+        } else if self.is_remote_migration(&packet) {
+            // the server moved our connection to another port, see `RUdpServer::detach`
+            self.migrate_to(remote_addr, Arc::clone(&self.socket.udp_socket))?;
+            self.add_received_packet(packet);
+        } else {
+            log::trace!("received unexpected UDP data from someone which was not remote server {}", remote_addr);
+            /* received packet from unknown source */
+            self.discard_hook.call(remote_addr, DiscardReason::UnknownSource, packet.as_bytes());
+            if self.unknown_datagrams.len() >= MAX_UNKNOWN_DATAGRAMS {
+                self.unknown_datagrams.pop_front();
+            }
+            self.unknown_datagrams.push_back((remote_addr, packet.buffer));
+        }
+        Ok(())
+    }
+
+    /// Receives every datagram waiting on the socket, and puts them in a queue for processing.
+    fn receive_all(&mut self) -> IoResult<()> {
         let mut done = false;
 
-        // receive incoming packets and put them in a queue for processing
         while !done {
             match UdpPacket::<Box<[u8]>>::from_udp_socket(&self.socket.udp_socket) {
-                Ok((packet, remote_addr)) => {
-                    if remote_addr == self.socket.remote_addr {
-                        self.add_received_packet(packet);
-                    } else {
-                        log::trace!("received unexpected UDP data from someone which was not remote server {}", remote_addr);
-                        /* received packet from unknown source */
-                    }
-                },
+                Ok((packet, remote_addr)) => self.receive_datagram(packet, remote_addr)?,
                 Err(err) => {
                     match err.kind() {
                         IoErrorKind::WouldBlock => { done = true },
@@ -571,8 +2499,6 @@ impl RUdpSocket {
                 },
             };
         };
-        // process everything we have received
-        self.inner_tick()?;
         Ok(())
     }
 
@@ -598,6 +2524,7 @@ impl RUdpSocket {
 
 impl Drop for RUdpSocket {
     fn drop(&mut self) {
+        let _r = self.socket.uncork();
         match self.socket.status() {
             SocketStatus::Connected | SocketStatus::SynSent(_) | SocketStatus::SynReceived => {
                 // TODO: At least log the error
@@ -606,4 +2533,1325 @@ impl Drop for RUdpSocket {
             _ => {},
         }
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn handshake_sets_initial_ping() {
+    let mut server = crate::RUdpServer::new("127.0.0.1:0").unwrap();
+    let mut client = RUdpSocket::connect(server.udp_socket().local_addr().unwrap()).unwrap();
+    assert!(client.ping().is_none());
+    for _ in 0..50 {
+        server.next_tick().unwrap();
+        client.next_tick().unwrap();
+        if client.status().is_connected() {
+            break;
+        }
+        ::std::thread::sleep(Duration::from_millis(5));
+    }
+    assert!(client.status().is_connected());
+    assert!(client.ping().is_some());
+}
+
+/// Creates a server on the loopback interface, and a client connected to it.
+#[cfg(test)]
+pub (crate) fn connected_pair() -> (crate::RUdpServer, RUdpSocket) {
+    let mut server = crate::RUdpServer::new("127.0.0.1:0").unwrap();
+    let mut client = RUdpSocket::connect(server.udp_socket().local_addr().unwrap()).unwrap();
+    for _ in 0..50 {
+        server.next_tick().unwrap();
+        client.next_tick().unwrap();
+        if client.status().is_connected() && server.iter().any(|(_, socket)| socket.status().is_connected()) {
+            break;
+        }
+        ::std::thread::sleep(Duration::from_millis(5));
+    }
+    assert!(client.status().is_connected());
+    let _events: Vec<_> = server.drain_events().collect();
+    let _events: Vec<_> = client.drain_events().collect();
+    (server, client)
+}
+
+/// Ticks both ends a few times, so that the packets in flight have time to arrive.
+#[cfg(test)]
+pub (crate) fn tick_pair(server: &mut crate::RUdpServer, client: &mut RUdpSocket, ticks: usize) {
+    for _ in 0..ticks {
+        ::std::thread::sleep(Duration::from_millis(5));
+        server.next_tick().unwrap();
+        client.next_tick().unwrap();
+    }
+}
+
+/// Makes the next `count` sends of `socket` fail as if its send buffer was full.
+#[cfg(test)]
+fn fill_send_buffer(socket: &mut RUdpSocket, count: usize) {
+    let mut remaining = count;
+    socket.socket.transport = Transport::new(move |udp_socket, bytes, addr| {
+        if remaining > 0 {
+            remaining -= 1;
+            return Err(IoErrorKind::WouldBlock.into());
+        }
+        udp_socket.send_to(bytes, addr)
+    });
+}
+
+#[test]
+fn cork_packs_messages() {
+    let (mut server, mut client) = connected_pair();
+
+    client.cork();
+    for i in 0..10u8 {
+        client.send_data(Arc::new([i; 16]), MessageType::KeyMessage, MessagePriority::Normal).unwrap();
+    }
+    client.uncork().unwrap();
+    tick_pair(&mut server, &mut client, 5);
+
+    let received: Vec<u8> = server.drain_events().filter_map(|(_, event)| match event {
+        SocketEvent::Data(d) => {
+            assert_eq!(d.frag_count, 1);
+            Some(d[0])
+        },
+        _ => None,
+    }).collect();
+    assert_eq!(received, (0..10u8).collect::<Vec<_>>());
+}
+
+#[test]
+fn cork_sends_a_single_datagram() {
+    let (_server, mut client) = connected_pair();
+
+    // spy on the datagrams by sending them to a raw socket instead of the server
+    let spy = UdpSocket::bind("127.0.0.1:0").unwrap();
+    spy.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+    client.socket.remote_addr = spy.local_addr().unwrap();
+
+    client.cork();
+    for i in 0..10u8 {
+        client.send_data(Arc::new([i; 16]), MessageType::Forgettable, MessagePriority::Normal).unwrap();
+    }
+    client.uncork().unwrap();
+
+    let mut buffer = [0u8; 2048];
+    let (size, _) = spy.recv_from(&mut buffer).unwrap();
+    let udp_packet = UdpPacket::new(Box::<[u8]>::from(&buffer[..size]));
+    match udp_packet.compute_packet().unwrap() {
+        Packet::Batch(payload) => assert_eq!(crate::udp_packet::unpack_batch(payload.as_ref()).unwrap().len(), 10),
+        p => panic!("expected a batch, got {:?}", p),
+    }
+    spy.set_nonblocking(true).unwrap();
+    assert!(spy.recv_from(&mut buffer).is_err());
+}
+
+#[test]
+fn end_grace_period_delivers_late_data() {
+    let (mut server, mut client) = connected_pair();
+    let client_addr = *server.addresses().next().unwrap();
+    server[client_addr].set_end_grace_period(Duration::from_secs(5));
+
+    // the server receives End before the data sent right before it
+    let data: Arc<[u8]> = Arc::new([7u8; 3000]);
+    client.cork();
+    client.send_data(data, MessageType::KeyMessage, MessagePriority::Normal).unwrap();
+    client.socket.cork_buffer.borrow_mut().as_mut().unwrap().pop();
+    client.send_end().unwrap();
+    client.uncork().unwrap();
+
+    tick_pair(&mut server, &mut client, 3);
+    assert!(server[client_addr].status().is_connected());
+    // the last fragment is re-sent after the resend delay
+    for _ in 0..100 {
+        tick_pair(&mut server, &mut client, 1);
+        if server[client_addr].status().is_finished() {
+            break;
+        }
+    }
+    let events: Vec<SocketEvent> = server.drain_events().map(|(_, event)| event).collect();
+    match events.as_slice() {
+        [SocketEvent::Data(d), SocketEvent::Ended { .. }] => assert_eq!(d.len(), 3000),
+        events => panic!("unexpected events {:?}", events),
+    }
+}
+
+#[test]
+fn end_grace_period_is_due() {
+    let (mut server, mut client) = connected_pair();
+    let client_addr = *server.addresses().next().unwrap();
+    let grace_period = Duration::from_millis(50);
+    server[client_addr].set_end_grace_period(grace_period);
+
+    // the last fragment never arrives, so the server waits for the whole grace period
+    client.cork();
+    client.send_data(Arc::new([7u8; 3000]), MessageType::KeyMessage, MessagePriority::Normal).unwrap();
+    client.socket.cork_buffer.borrow_mut().as_mut().unwrap().pop();
+    client.send_end().unwrap();
+    client.uncork().unwrap();
+    drop(client);
+
+    ::std::thread::sleep(Duration::from_millis(10));
+    server.next_tick().unwrap();
+    let (end_received, _) = server[client_addr].pending_end.unwrap();
+    assert_eq!(server[client_addr].next_due(), Some(end_received + grace_period));
+    assert!(server[client_addr].has_work(end_received + grace_period));
+    let _events: Vec<_> = server.drain_events().collect();
+    ::std::thread::sleep(grace_period);
+    assert_eq!(server.remotes_with_work().collect::<Vec<_>>(), vec![&client_addr]);
+}
+
+#[test]
+fn end_grace_period_not_waited_when_nothing_was_sent() {
+    let (mut server, mut client) = connected_pair();
+    let client_addr = *server.addresses().next().unwrap();
+    server[client_addr].set_end_grace_period(Duration::from_secs(5));
+    let _events: Vec<_> = server.drain_events().collect();
+
+    client.send_end().unwrap();
+    tick_pair(&mut server, &mut client, 3);
+    assert!(server[client_addr].status().is_finished());
+    let events: Vec<SocketEvent> = server.drain_events().map(|(_, event)| event).collect();
+    assert!(matches!(events.as_slice(), [SocketEvent::Ended { .. }]));
+}
+
+#[test]
+fn simultaneous_open() {
+    let socket_a = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let socket_b = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr_a = socket_a.local_addr().unwrap();
+    let addr_b = socket_b.local_addr().unwrap();
+    let mut a = RUdpSocket::connect_with_socket(socket_a, addr_b).unwrap();
+    let mut b = RUdpSocket::connect_with_socket(socket_b, addr_a).unwrap();
+    for _ in 0..50 {
+        ::std::thread::sleep(Duration::from_millis(5));
+        a.next_tick().unwrap();
+        b.next_tick().unwrap();
+        if a.status().is_connected() && b.status().is_connected() {
+            break;
+        }
+    }
+    assert!(a.status().is_connected());
+    assert!(b.status().is_connected());
+    assert!(matches!(a.drain_events().collect::<Vec<_>>().as_slice(), [SocketEvent::Connected]));
+    assert!(matches!(b.drain_events().collect::<Vec<_>>().as_slice(), [SocketEvent::Connected]));
+}
+
+#[test]
+fn full_send_buffer_retries_next_tick() {
+    let (mut server, mut client) = connected_pair();
+    let data: Arc<[u8]> = Arc::new([3u8; 3000]);
+    fill_send_buffer(&mut client, 2);
+    // the resend delay of Lowest is far longer than this test: only the retry can deliver the message
+    client.send_data(data, MessageType::KeyMessage, MessagePriority::Lowest).unwrap();
+
+    tick_pair(&mut server, &mut client, 4);
+    let events: Vec<SocketEvent> = server.drain_events().map(|(_, event)| event).collect();
+    match events.as_slice() {
+        [SocketEvent::Data(d)] => assert_eq!(d.len(), 3000),
+        events => panic!("unexpected events {:?}", events),
+    }
+}
+
+#[test]
+fn resends_deferred_without_allowance() {
+    let (_server, mut client) = connected_pair();
+    client.send_allowance = Some(0);
+    // the server never ticks, so the message is never acknowledged
+    client.send_data(Arc::new([1u8; 100]), MessageType::KeyMessage, MessagePriority::Highest).unwrap();
+    ::std::thread::sleep(Duration::from_millis(30));
+    client.next_tick().unwrap();
+    assert!(client.send_deferred);
+
+    client.send_allowance = Some(10_000);
+    client.next_tick().unwrap();
+    assert!(!client.send_deferred);
+    assert!(client.send_allowance.unwrap() < 10_000);
+}
+
+#[test]
+fn max_bytes_per_tick_defers_resends() {
+    let (_server, mut client) = connected_pair();
+    client.set_max_bytes_per_tick(Some(150));
+    // the server never ticks, so the messages are never acknowledged
+    for _ in 0..2 {
+        client.send_data(Arc::new([1u8; 100]), MessageType::KeyMessage, MessagePriority::Highest).unwrap();
+    }
+    ::std::thread::sleep(Duration::from_millis(30));
+    client.next_tick().unwrap();
+    // the first sends used up the budget
+    assert!(client.send_deferred);
+    assert_eq!(client.stats().fragments_resent, 0);
+
+    ::std::thread::sleep(Duration::from_millis(30));
+    client.next_tick().unwrap();
+    // room for a single message
+    assert!(client.send_deferred);
+    assert_eq!(client.stats().fragments_resent, 1);
+
+    client.set_max_bytes_per_tick(None);
+    client.next_tick().unwrap();
+    assert!(!client.send_deferred);
+    assert_eq!(client.stats().fragments_resent, 2);
+}
+
+#[test]
+fn max_retransmit_rate_caps_resends() {
+    let (_server, mut client) = connected_pair();
+    let rate = 20_000;
+    client.set_max_retransmit_rate(Some(rate));
+    // the server never ticks: every packet is lost as far as the client knows
+    for _ in 0..20 {
+        client.send_data(Arc::new([1u8; 1000]), MessageType::KeyMessage, MessagePriority::Highest).unwrap();
+    }
+    let sent_before = client.stats().bytes_sent;
+    let start = Instant::now();
+    for _ in 0..60 {
+        ::std::thread::sleep(Duration::from_millis(5));
+        client.next_tick().unwrap();
+    }
+    let resent = (client.stats().bytes_sent - sent_before) as f64;
+    // a second's worth at first, then the rate; heartbeats are not counted in the rate
+    let allowed = rate as f64 * (1.0 + start.elapsed().as_secs_f64()) + 500.0;
+    assert!(client.stats().fragments_resent > 0);
+    assert!(resent <= allowed, "resent {} bytes, allowed {}", resent, allowed);
+}
+
+#[test]
+fn congestion_factor_backs_off_under_loss() {
+    let (mut server, mut client) = connected_pair();
+    assert_eq!(client.congestion_factor(), 1.0);
+    // the server doesn't tick for a while: nothing is acknowledged, everything is resent
+    for i in 0..4u8 {
+        client.send_data(Arc::new([i; 10]), MessageType::KeyMessage, MessagePriority::Highest).unwrap();
+    }
+    for _ in 0..10 {
+        ::std::thread::sleep(Duration::from_millis(10));
+        client.next_tick().unwrap();
+    }
+    let congested = client.congestion_factor();
+    assert!(congested > 1.0, "{}", congested);
+    let seq_id = client.send_data(Arc::new([9u8; 10]), MessageType::KeyMessage, MessagePriority::Highest).unwrap();
+    assert_eq!(client.effective_resend_delay(seq_id), Some(MessagePriority::Highest.resend_delay().mul_f64(congested)));
+
+    // the link is fine again
+    for _ in 0..20 {
+        for i in 0..4u8 {
+            client.send_data(Arc::new([i; 10]), MessageType::KeyMessage, MessagePriority::Highest).unwrap();
+        }
+        tick_pair(&mut server, &mut client, 2);
+    }
+    assert!(client.congestion_factor() < congested, "{}", client.congestion_factor());
+}
+
+#[test]
+fn stats_count_and_reset() {
+    let (mut server, mut client) = connected_pair();
+    assert!(client.stats().packets_sent > 0);
+    client.reset_stats();
+    server.reset_all_stats();
+    assert_eq!(client.stats(), ConnectionStats::default());
+
+    client.send_data(Arc::new([1u8; 100]), MessageType::Forgettable, MessagePriority::Normal).unwrap();
+    tick_pair(&mut server, &mut client, 1);
+    let client_addr = *server.addresses().next().unwrap();
+    assert_eq!(client.stats().packets_sent, 1);
+    assert!(client.stats().bytes_sent > 100);
+    assert_eq!(server[client_addr].stats().bytes_received, client.stats().bytes_sent);
+}
+
+#[test]
+fn max_connection_lifetime_ends_connection() {
+    let (mut server, mut client) = connected_pair();
+    client.set_max_connection_lifetime(Duration::from_millis(1));
+    tick_pair(&mut server, &mut client, 2);
+    assert!(client.status().is_finished());
+    assert!(matches!(client.drain_events().collect::<Vec<_>>().as_slice(), [SocketEvent::Ended { .. }]));
+    let server_events: Vec<SocketEvent> = server.drain_events().map(|(_, event)| event).collect();
+    assert!(matches!(server_events.as_slice(), [SocketEvent::Ended { .. }]));
+}
+
+#[test]
+fn last_ack_bitmap_after_ack() {
+    let (mut server, mut client) = connected_pair();
+    let seq_id = client.send_data(Arc::new([2u8; 3000]), MessageType::KeyMessage, MessagePriority::Normal).unwrap();
+    assert_eq!(client.last_ack_bitmap(seq_id), None);
+    tick_pair(&mut server, &mut client, 3);
+    assert_eq!(client.last_ack_bitmap(seq_id).as_deref(), Some(&[0xFFu8][..]));
+}
+
+#[test]
+fn forgettable_with_ack_is_acked_but_never_resent() {
+    let (mut server, mut client) = connected_pair();
+    let seq_id = client.send_data(Arc::new([4u8; 3000]), MessageType::ForgettableWithAck, MessagePriority::Highest).unwrap();
+    assert!(client.pending_schedule().is_empty());
+    assert_eq!(client.is_seq_id_received(seq_id), Ok(false));
+    tick_pair(&mut server, &mut client, 5);
+    assert_eq!(client.is_seq_id_received(seq_id), Ok(true));
+    assert_eq!(client.stats().fragments_resent, 0);
+}
+
+#[test]
+fn rebind_migrates_connection() {
+    let (mut server, mut client) = connected_pair();
+    let old_addr = *server.addresses().next().unwrap();
+    client.rebind("127.0.0.1:0").unwrap();
+    let new_addr = client.local_addr();
+    tick_pair(&mut server, &mut client, 3);
+
+    assert_eq!(server.addresses().cloned().collect::<Vec<_>>(), vec![new_addr]);
+    let events: Vec<(SocketAddr, SocketEvent)> = server.drain_events().collect();
+    match events.as_slice() {
+        [(addr, SocketEvent::Migrated { from })] => {
+            assert_eq!(*addr, new_addr);
+            assert_eq!(*from, old_addr);
+        },
+        events => panic!("unexpected events {:?}", events),
+    }
+    assert!(client.migrate_sent.is_none());
+
+    client.send_data(Arc::new([5u8; 10]), MessageType::KeyMessage, MessagePriority::Normal).unwrap();
+    server.send_data(&Arc::from(&[6u8; 10][..]), MessageType::KeyMessage, MessagePriority::Normal);
+    tick_pair(&mut server, &mut client, 3);
+    assert!(matches!(server.drain_events().next(), Some((_, SocketEvent::Data(_)))));
+    assert!(matches!(client.drain_events().next(), Some(SocketEvent::Data(_))));
+}
+
+#[test]
+fn effective_resend_delay_of_pending_message() {
+    let (mut server, mut client) = connected_pair();
+    let seq_id = client.send_data(Arc::new([1u8; 10]), MessageType::KeyMessage, MessagePriority::Normal).unwrap();
+    assert_eq!(client.effective_resend_delay(seq_id), Some(MessagePriority::Normal.resend_delay()));
+    let forgettable = client.send_data(Arc::new([1u8; 10]), MessageType::Forgettable, MessagePriority::Normal).unwrap();
+    assert_eq!(client.effective_resend_delay(forgettable), None);
+    // acks make us resend sooner than the priority alone would
+    tick_pair(&mut server, &mut client, 3);
+    assert!(client.effective_resend_delay(seq_id).unwrap() < MessagePriority::Normal.resend_delay());
+}
+
+#[test]
+fn drain_data_leaves_meta_events() {
+    let (mut server, mut client) = connected_pair();
+    server.send_data(&Arc::from(&[1u8, 2][..]), MessageType::KeyMessage, MessagePriority::Normal);
+    server.send_data(&Arc::from(&[3u8][..]), MessageType::KeyMessage, MessagePriority::Normal);
+    tick_pair(&mut server, &mut client, 3);
+    client.events.push_front(SocketEvent::Timeout { last_status: SocketStatus::Connected, since: Duration::from_secs(10) });
+
+    let data: Vec<ReceivedData> = client.drain_data().collect();
+    assert_eq!(data.iter().map(|d| d.len()).collect::<Vec<_>>(), vec![2, 1]);
+    assert!(client.drain_data().next().is_none());
+    assert!(matches!(client.drain_meta().collect::<Vec<_>>().as_slice(), [SocketEvent::Timeout { .. }]));
+}
+
+#[test]
+fn connect_with_payload_reaches_server_first() {
+    let mut server = crate::RUdpServer::new("127.0.0.1:0").unwrap();
+    let server_addr = server.udp_socket().local_addr().unwrap();
+    assert!(RUdpSocket::connect_with_payload(server_addr, &[0u8; 2048]).is_err());
+
+    let mut client = RUdpSocket::connect_with_payload(server_addr, b"token").unwrap();
+    client.send_data(Arc::new([1u8; 8]), MessageType::KeyMessage, MessagePriority::Normal).unwrap();
+    tick_pair(&mut server, &mut client, 10);
+    assert!(client.status().is_connected());
+
+    let events: Vec<SocketEvent> = server.drain_events().map(|(_, event)| event).collect();
+    match events.as_slice() {
+        [SocketEvent::IncomingConnection(payload), SocketEvent::Connected, ..] => assert_eq!(&payload[..], b"token"),
+        events => panic!("unexpected events {:?}", events),
+    }
+}
+
+#[test]
+fn send_data_with_callback_reports_outcome() {
+    use std::sync::Mutex;
+
+    let (mut server, mut client) = connected_pair();
+    let results: Arc<Mutex<Vec<(u8, DeliveryResult)>>> = Arc::new(Mutex::new(Vec::new()));
+    for (i, message_type) in [MessageType::KeyMessage, MessageType::Forgettable].iter().enumerate() {
+        let results = Arc::clone(&results);
+        client.send_data_with_callback(Arc::new([1u8; 100]), *message_type, MessagePriority::Normal, move |result| {
+            results.lock().unwrap().push((i as u8, result));
+        }).unwrap();
+    }
+    for _ in 0..50 {
+        tick_pair(&mut server, &mut client, 1);
+        if results.lock().unwrap().len() == 2 {
+            break;
+        }
+    }
+    let mut outcomes = results.lock().unwrap().clone();
+    outcomes.sort_by_key(|(i, _)| *i);
+    assert_eq!(outcomes, vec![(0, DeliveryResult::Delivered), (1, DeliveryResult::Expired)]);
+
+    // the remote vanishes: the message will never be acknowledged
+    results.lock().unwrap().clear();
+    client.socket.remote_addr = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    client.set_timeout_delay(Duration::from_millis(20));
+    let r = Arc::clone(&results);
+    client.send_data_with_callback(Arc::new([2u8; 100]), MessageType::KeyMessage, MessagePriority::Normal, move |result| {
+        r.lock().unwrap().push((2, result));
+    }).unwrap();
+    for _ in 0..10 {
+        ::std::thread::sleep(Duration::from_millis(5));
+        client.next_tick().unwrap();
+    }
+    assert!(client.status().is_finished());
+    assert_eq!(*results.lock().unwrap(), vec![(2, DeliveryResult::Failed)]);
+}
+
+#[test]
+fn heartbeat_disabled_sends_nothing() {
+    let (mut server, mut client) = connected_pair();
+    let client_addr = *server.addresses().next().unwrap();
+    client.set_heartbeat_delay(Duration::from_millis(1));
+    tick_pair(&mut server, &mut client, 5);
+    assert!(server[client_addr].stats().packets_received > 0);
+
+    client.set_heartbeat_enabled(false);
+    // let the heartbeats in flight arrive
+    tick_pair(&mut server, &mut client, 2);
+    server.reset_all_stats();
+    tick_pair(&mut server, &mut client, 5);
+    assert_eq!(server[client_addr].stats().packets_received, 0);
+}
+
+#[test]
+fn silence_duration_grows_without_packets() {
+    let (mut server, mut client) = connected_pair();
+    tick_pair(&mut server, &mut client, 2);
+    let last_received = client.last_received();
+    ::std::thread::sleep(Duration::from_millis(30));
+    assert_eq!(client.last_received(), last_received);
+    assert!(client.silence_duration() >= Duration::from_millis(30));
+}
+
+#[test]
+fn last_send_queued() {
+    let (_server, mut client) = connected_pair();
+    client.send_data(Arc::new([1u8; 100]), MessageType::KeyMessage, MessagePriority::Normal).unwrap();
+    assert!(!client.was_last_send_queued());
+
+    fill_send_buffer(&mut client, 1);
+    client.send_data(Arc::new([1u8; 100]), MessageType::KeyMessage, MessagePriority::Normal).unwrap();
+    assert!(client.was_last_send_queued());
+
+    client.cork();
+    client.send_data(Arc::new([1u8; 100]), MessageType::Forgettable, MessagePriority::Normal).unwrap();
+    assert!(client.was_last_send_queued());
+    client.uncork().unwrap();
+    client.send_data(Arc::new([1u8; 100]), MessageType::Forgettable, MessagePriority::Normal).unwrap();
+    assert!(!client.was_last_send_queued());
+}
+
+#[test]
+fn cancel_all_pending_stops_resends() {
+    let (mut server, mut client) = connected_pair();
+    let delivered = client.send_data(Arc::new([1u8; 100]), MessageType::KeyMessage, MessagePriority::Highest).unwrap();
+    tick_pair(&mut server, &mut client, 5);
+    assert_eq!(client.is_seq_id_received(delivered), Ok(true));
+
+    // the remote never receives anything from now on
+    client.socket.remote_addr = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let cancelled = Arc::new(::std::sync::Mutex::new(None));
+    let cancelled_clone = Arc::clone(&cancelled);
+    client.send_data_with_callback(Arc::new([2u8; 3000]), MessageType::KeyMessage, MessagePriority::Highest, move |result| {
+        *cancelled_clone.lock().unwrap() = Some(result);
+    }).unwrap();
+    client.send_data(Arc::new([3u8; 100]), MessageType::KeyMessage, MessagePriority::Highest).unwrap();
+    assert_eq!(client.cancel_all_pending(), 2);
+    assert_eq!(*cancelled.lock().unwrap(), Some(DeliveryResult::Cancelled));
+    assert!(client.pending_schedule().is_empty());
+    assert_eq!(client.is_seq_id_received(delivered), Ok(true));
+
+    client.reset_stats();
+    ::std::thread::sleep(Duration::from_millis(50));
+    client.next_tick().unwrap();
+    assert_eq!(client.stats().fragments_resent, 0);
+    assert!(client.status().is_connected());
+}
+
+#[test]
+fn on_writable_once_buffer_drained() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let (mut server, mut client) = connected_pair();
+    let calls = Arc::new(AtomicUsize::new(0));
+    let calls_clone = Arc::clone(&calls);
+    client.set_on_writable(move || { calls_clone.fetch_add(1, Ordering::SeqCst); });
+
+    client.send_data(Arc::new([1u8; 100]), MessageType::KeyMessage, MessagePriority::Normal).unwrap();
+    tick_pair(&mut server, &mut client, 2);
+    assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+    // the 3 fragments are blocked, and the buffer is still full on the first retry
+    fill_send_buffer(&mut client, 4);
+    client.send_data(Arc::new([2u8; 3000]), MessageType::KeyMessage, MessagePriority::Normal).unwrap();
+    assert!(client.was_last_send_queued());
+    client.next_tick().unwrap();
+    assert_eq!(calls.load(Ordering::SeqCst), 0);
+    tick_pair(&mut server, &mut client, 5);
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn drain_data_batch_respects_max() {
+    let (mut server, mut client) = connected_pair();
+    let client_addr = *server.addresses().next().unwrap();
+    for i in 0..5u8 {
+        client.send_data(Arc::new([i; 4]), MessageType::KeyMessage, MessagePriority::Normal).unwrap();
+    }
+    client.send_end().unwrap();
+    tick_pair(&mut server, &mut client, 5);
+
+    let remote = &mut server[client_addr];
+    let batch = remote.drain_data_batch(3);
+    assert_eq!(batch.len(), 3);
+    let rest = remote.drain_data_batch(10);
+    assert_eq!(rest.len(), 2);
+    let mut firsts: Vec<u8> = batch.iter().chain(rest.iter()).map(|data| data[0]).collect();
+    firsts.sort_unstable();
+    assert_eq!(firsts, vec![0, 1, 2, 3, 4]);
+    assert!(matches!(remote.drain_events().collect::<Vec<_>>().as_slice(), [SocketEvent::Ended { .. }]));
+}
+
+#[test]
+fn probe_succeeds_or_fails_fast() {
+    let (mut server, mut client) = connected_pair();
+    let probe_id = client.probe(Duration::from_secs(1)).unwrap();
+    tick_pair(&mut server, &mut client, 5);
+    let events: Vec<_> = client.drain_events().collect();
+    assert!(matches!(events.as_slice(), [SocketEvent::ProbeSucceeded { probe_id: id, .. }] if *id == probe_id));
+
+    // the server isn't ticked anymore so nobody answers, the probe fails long before the connection times out
+    let probe_id = client.probe(Duration::from_millis(30)).unwrap();
+    for _ in 0..10 {
+        ::std::thread::sleep(Duration::from_millis(5));
+        client.next_tick().unwrap();
+    }
+    let events: Vec<_> = client.drain_events().collect();
+    assert!(matches!(events.as_slice(), [SocketEvent::ProbeFailed { probe_id: id }] if *id == probe_id));
+    assert!(client.status().is_connected());
+}
+
+/// Makes the next send of `socket` only send half of the datagram.
+#[cfg(test)]
+fn short_send_next(socket: &mut RUdpSocket) {
+    let mut done = false;
+    socket.socket.transport = Transport::new(move |udp_socket, bytes, addr| {
+        if done {
+            return udp_socket.send_to(bytes, addr);
+        }
+        done = true;
+        udp_socket.send_to(&bytes[..bytes.len() / 2], addr)
+    });
+}
+
+#[test]
+fn short_send_is_an_error() {
+    let (mut server, mut client) = connected_pair();
+    let packets_sent = client.stats().packets_sent;
+    short_send_next(&mut client);
+    let heartbeat = UdpPacket::from(&Packet::<Box<[u8]>>::Heartbeat);
+    let error = client.socket.send_udp_packet(&heartbeat).unwrap_err();
+    assert_eq!(error.kind(), IoErrorKind::WriteZero);
+    assert_eq!(client.stats().packets_sent, packets_sent);
+
+    // a key message cut short is sent again like any lost message
+    short_send_next(&mut client);
+    client.send_data(Arc::new([3u8; 100]), MessageType::KeyMessage, MessagePriority::Highest).unwrap();
+    tick_pair(&mut server, &mut client, 10);
+    assert!(server.drain_events().any(|(_, event)| matches!(event, SocketEvent::Data(ref d) if d.len() == 100)));
+}
+
+#[cfg(feature = "egress_filter")]
+#[test]
+fn egress_filter_corrupts_and_drops() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let (mut server, mut client) = connected_pair();
+    // the first two datagrams are corrupted, the next two dropped
+    let filtered = Arc::new(AtomicUsize::new(0));
+    let filtered_clone = Arc::clone(&filtered);
+    client.set_egress_filter(move |bytes, _addr| {
+        match filtered_clone.fetch_add(1, Ordering::SeqCst) {
+            0 | 1 => { bytes[12] ^= 0xFF; true },
+            2 | 3 => false,
+            _ => true,
+        }
+    });
+    client.send_data(Arc::new([9u8; 100]), MessageType::KeyMessage, MessagePriority::Highest).unwrap();
+    tick_pair(&mut server, &mut client, 20);
+    assert!(filtered.load(Ordering::SeqCst) > 4);
+    let client_addr = *server.addresses().next().unwrap();
+    assert!(server[client_addr].stats().malformed_packets >= 1);
+    assert!(server.drain_events().any(|(_, event)| matches!(event, SocketEvent::Data(ref d) if d.len() == 100)));
+}
+
+#[test]
+fn min_payload_size_pads_small_messages() {
+    let (mut server, mut client) = connected_pair();
+    client.set_min_payload_size(200);
+    client.reset_stats();
+    client.send_data(Arc::new([1u8; 10]), MessageType::KeyMessage, MessagePriority::Normal).unwrap();
+    client.send_data(Arc::new([2u8; 150]), MessageType::Forgettable, MessagePriority::Normal).unwrap();
+    assert_eq!(client.stats().bytes_sent, 2 * (crate::consts::FRAG_DATA_START_BYTE + 200) as u64);
+
+    tick_pair(&mut server, &mut client, 5);
+    let mut sizes: Vec<usize> = server.drain_events().filter_map(|(_, event)| match event {
+        SocketEvent::Data(d) => Some(d.len()),
+        _ => None,
+    }).collect();
+    sizes.sort_unstable();
+    assert_eq!(sizes, vec![10, 150]);
+}
+
+#[test]
+fn time_until_timeout_counts_down() {
+    let (_server, mut client) = connected_pair();
+    client.set_timeout_delay(Duration::from_secs(5));
+    let remaining = client.time_until_timeout().unwrap();
+    assert!(remaining <= Duration::from_secs(5) && remaining > Duration::from_secs(4));
+    ::std::thread::sleep(Duration::from_millis(20));
+    assert!(client.time_until_timeout().unwrap() < remaining);
+
+    client.set_timeout_delay(Duration::from_millis(1));
+    ::std::thread::sleep(Duration::from_millis(5));
+    assert_eq!(client.time_until_timeout(), Some(Duration::from_secs(0)));
+    for _ in 0..5 {
+        // a packet still in flight could delay the timeout a little
+        client.next_tick().unwrap();
+        ::std::thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(client.time_until_timeout(), None);
+}
+
+#[test]
+fn send_latest_supersedes_older_state() {
+    let (mut server, mut client) = connected_pair();
+    let first = client.send_latest(1, Arc::new([1u8; 10]), MessagePriority::Highest).unwrap();
+    let other_channel = client.send_latest(2, Arc::new([2u8; 10]), MessagePriority::Highest).unwrap();
+    let second = client.send_latest(1, Arc::new([3u8; 10]), MessagePriority::Highest).unwrap();
+    let mut pending: Vec<u32> = client.pending_schedule().iter().map(|info| info.seq_id).collect();
+    pending.sort_unstable();
+    assert_eq!(pending, vec![other_channel, second]);
+    assert!(first < second);
+
+    tick_pair(&mut server, &mut client, 10);
+    assert_eq!(client.is_seq_id_received(second), Ok(true));
+    assert_eq!(client.is_seq_id_received(other_channel), Ok(true));
+    // the superseded message is forgotten, whether it arrived or not
+    assert_eq!(client.is_seq_id_received(first), Err(()));
+    let newest = server.drain_events().filter_map(|(_, event)| match event {
+        SocketEvent::Data(d) if d[0] != 2 => Some(d.seq_id),
+        _ => None,
+    }).max();
+    assert_eq!(newest, Some(second));
+}
+
+#[test]
+fn payload_size_is_negotiated_during_handshake() {
+    let mut server = crate::RUdpServer::new("127.0.0.1:0").unwrap();
+    server.set_max_payload_size(500);
+    let mut client = RUdpSocket::connect(server.udp_socket().local_addr().unwrap()).unwrap();
+    assert_eq!(client.negotiated_payload_size(), MAX_FRAGMENT_MESSAGE_SIZE);
+    for _ in 0..50 {
+        server.next_tick().unwrap();
+        client.next_tick().unwrap();
+        if client.status().is_connected() && server.iter().any(|(_, socket)| socket.status().is_connected()) {
+            break;
+        }
+        ::std::thread::sleep(Duration::from_millis(5));
+    }
+    assert!(client.status().is_connected());
+    assert_eq!(client.negotiated_payload_size(), 500);
+    assert_eq!(server.iter().next().unwrap().1.negotiated_payload_size(), 500);
+    let _events: Vec<_> = server.drain_events().collect();
+
+    client.reset_stats();
+    client.send_data(Arc::new([7u8; 2000]), MessageType::KeyMessage, MessagePriority::Normal).unwrap();
+    // 4 fragments of 500 bytes instead of 2 larger ones
+    assert_eq!(client.stats().bytes_sent, (4 * (crate::consts::FRAG_DATA_START_BYTE + 500)) as u64);
+    tick_pair(&mut server, &mut client, 5);
+    assert!(server.drain_events().any(|(_, event)| matches!(event, SocketEvent::Data(ref d) if d.len() == 2000)));
+}
+
+#[test]
+fn fragment_payload_size_raised_on_both_ends() {
+    let mut server = crate::RUdpServer::new("127.0.0.1:0").unwrap();
+    server.set_fragment_payload_size(4000).unwrap();
+    let mut client = RUdpSocket::connect(server.udp_socket().local_addr().unwrap()).unwrap();
+    client.set_fragment_payload_size(4000).unwrap();
+    tick_pair(&mut server, &mut client, 10);
+    assert!(client.status().is_connected());
+    assert_eq!(client.negotiated_payload_size(), 4000);
+    assert_eq!(client.max_message_size(), MAX_FRAGMENTS_IN_MESSAGE * 4000);
+    let _events: Vec<_> = server.drain_events().collect();
+
+    client.reset_stats();
+    let data: Vec<u8> = (0..8000u32).map(|i| (i % 251) as u8).collect();
+    client.send_data(Arc::from(data.clone()), MessageType::KeyMessage, MessagePriority::Normal).unwrap();
+    assert_eq!(client.stats().bytes_sent, (2 * (crate::consts::FRAG_DATA_START_BYTE + 4000)) as u64);
+    tick_pair(&mut server, &mut client, 5);
+    assert!(server.drain_events().any(|(_, event)| matches!(event, SocketEvent::Data(ref d) if d.as_ref() == data.as_slice())));
+}
+
+#[test]
+fn fragment_payload_size_bounds() {
+    let (_server, mut client) = connected_pair();
+    let largest = crate::consts::MAX_RCV_UDP_DATA_SIZE - crate::consts::FRAG_DATA_START_BYTE;
+    assert!(client.set_fragment_payload_size(MIN_PAYLOAD_SIZE).is_ok());
+    assert_eq!(client.negotiated_payload_size(), MIN_PAYLOAD_SIZE);
+    assert!(client.set_fragment_payload_size(largest).is_ok());
+    assert_eq!(client.set_fragment_payload_size(MIN_PAYLOAD_SIZE - 1).unwrap_err().kind(), IoErrorKind::InvalidInput);
+    assert_eq!(client.set_fragment_payload_size(largest + 1).unwrap_err().kind(), IoErrorKind::InvalidInput);
+    // refused values leave the setting untouched
+    assert_eq!(client.max_payload_size, largest);
+}
+
+#[test]
+fn send_data_in_reserved_range() {
+    let (mut server, mut client) = connected_pair();
+    let control_start = u32::MAX - 1000;
+    assert!(client.reserve_seq_ids(control_start));
+    assert!(!client.send_data_in_reserved_range(5, Arc::new([0u8; 4]), MessageType::KeyMessage, MessagePriority::Normal));
+    assert!(client.send_data_in_reserved_range(control_start, Arc::new([1u8; 4]), MessageType::KeyMessage, MessagePriority::Normal));
+    // still being sent
+    assert!(!client.send_data_in_reserved_range(control_start, Arc::new([1u8; 4]), MessageType::KeyMessage, MessagePriority::Normal));
+    let regular_seq_id = client.send_data(Arc::new([2u8; 4]), MessageType::KeyMessage, MessagePriority::Normal).unwrap();
+    assert!(regular_seq_id < control_start);
+
+    tick_pair(&mut server, &mut client, 5);
+    let mut seq_ids: Vec<u32> = server.drain_events().filter_map(|(_, event)| match event {
+        SocketEvent::Data(d) => Some(d.seq_id),
+        _ => None,
+    }).collect();
+    seq_ids.sort_unstable();
+    assert_eq!(seq_ids, vec![regular_seq_id, control_start]);
+    // too late, a message already used a seq_id above 0
+    assert!(!client.reserve_seq_ids(0));
+}
+
+#[test]
+fn send_data_below_reserved_range_runs_out() {
+    let (_server, mut client) = connected_pair();
+    let first = client.send_data(Arc::new([0u8; 4]), MessageType::KeyMessage, MessagePriority::Normal).unwrap();
+    assert!(client.reserve_seq_ids(first + 2));
+    assert_eq!(client.send_data(Arc::new([1u8; 4]), MessageType::KeyMessage, MessagePriority::Normal).unwrap(), first + 1);
+    assert!(matches!(client.send_data(Arc::new([2u8; 4]), MessageType::KeyMessage, MessagePriority::Normal), Err(SendError::SeqIdsExhausted)));
+    assert!(client.send_data_in_reserved_range(first + 2, Arc::new([3u8; 4]), MessageType::KeyMessage, MessagePriority::Normal));
+}
+
+#[test]
+fn send_large_below_reserved_range_runs_out() {
+    let (_server, mut client) = connected_pair();
+    let first = client.send_data(Arc::new([0u8; 4]), MessageType::KeyMessage, MessagePriority::Normal).unwrap();
+    assert!(client.reserve_seq_ids(first + 3));
+    let data: Arc<[u8]> = Arc::from(vec![1u8; 2 * client.max_message_size() + 1]);
+    // 3 parts, but only 2 seq_ids left: nothing is sent
+    assert_eq!(client.send_large(Arc::clone(&data), MessageType::KeyMessage, MessagePriority::Normal), Err(SendError::SeqIdsExhausted));
+    assert_eq!(client.next_local_seq_id, first + 1);
+    let data: Arc<[u8]> = Arc::from(vec![1u8; 2 * client.max_message_size()]);
+    assert_eq!(client.send_large(data, MessageType::KeyMessage, MessagePriority::Normal), Ok(vec![first + 1, first + 2]));
+}
+
+#[test]
+fn drain_and_terminate_waits_for_acks() {
+    let (mut server, mut client) = connected_pair();
+    for i in 0..5u8 {
+        client.send_data(Arc::new([i; 3000]), MessageType::KeyMessage, MessagePriority::Normal).unwrap();
+    }
+    assert!(client.pending_sends() > 0);
+    let server_thread = ::std::thread::spawn(move || {
+        let mut events = Vec::new();
+        for _ in 0..200 {
+            server.next_tick().unwrap();
+            events.extend(server.drain_events().map(|(_, event)| event));
+            if events.iter().any(|event| matches!(event, SocketEvent::Ended { .. })) {
+                break;
+            }
+            ::std::thread::sleep(Duration::from_millis(5));
+        }
+        events
+    });
+    assert!(client.drain_and_terminate(Duration::from_secs(5)).unwrap());
+    let events = server_thread.join().unwrap();
+    assert_eq!(events.iter().filter(|event| matches!(event, SocketEvent::Data(_))).count(), 5);
+    assert!(events.iter().any(|event| matches!(event, SocketEvent::Ended { .. })));
+
+    // nobody answers anymore
+    let (_server, mut client) = connected_pair();
+    client.send_data(Arc::new([0u8; 10]), MessageType::KeyMessage, MessagePriority::Normal).unwrap();
+    assert!(!client.drain_and_terminate(Duration::from_millis(50)).unwrap());
+}
+
+#[test]
+fn terminate_graceful_reports_unacknowledged_messages() {
+    let (_server, client) = connected_pair();
+    assert!(client.terminate_graceful(Duration::from_millis(50)).is_ok());
+
+    let (_server, mut client) = connected_pair();
+    client.send_data(Arc::new([0u8; 10]), MessageType::KeyMessage, MessagePriority::Normal).unwrap();
+    let err = client.terminate_graceful(Duration::from_millis(50)).unwrap_err();
+    assert_eq!(err.kind(), IoErrorKind::TimedOut);
+}
+
+#[test]
+fn pending_bytes_until_acknowledged() {
+    let (mut server, mut client) = connected_pair();
+    assert_eq!(client.pending_bytes(), 0);
+    client.send_data(Arc::new([1u8; 3000]), MessageType::KeyMessage, MessagePriority::Highest).unwrap();
+    client.send_data(Arc::new([2u8; 100]), MessageType::KeyMessage, MessagePriority::Highest).unwrap();
+    // not tracked once sent
+    client.send_data(Arc::new([3u8; 500]), MessageType::Forgettable, MessagePriority::Highest).unwrap();
+    assert_eq!(client.pending_sends(), 2);
+    assert_eq!(client.pending_bytes(), 3100);
+    for _ in 0..40 {
+        tick_pair(&mut server, &mut client, 1);
+        if client.pending_bytes() == 0 {
+            break;
+        }
+    }
+    assert_eq!(client.pending_bytes(), 0);
+}
+
+#[test]
+fn payload_size_shrinks_on_mtu_black_hole() {
+    let (mut server, mut client) = connected_pair();
+    // datagrams larger than 800 bytes are silently lost on the way
+    client.socket.transport = Transport::new(|udp_socket, bytes, addr| {
+        if bytes.len() > 800 {
+            return Ok(bytes.len());
+        }
+        udp_socket.send_to(bytes, addr)
+    });
+    let (sender, receiver) = ::std::sync::mpsc::channel();
+    let seq_id = client.send_data_with_callback(Arc::new([3u8; 3000]), MessageType::KeyMessage, MessagePriority::Highest, move |result| sender.send(result).unwrap()).unwrap();
+    let mut shrank_to = None;
+    let mut renumbered_as = None;
+    for _ in 0..100 {
+        tick_pair(&mut server, &mut client, 1);
+        for event in client.drain_events() {
+            if let SocketEvent::PathMtuShrank { payload_size, renumbered } = event {
+                shrank_to = Some(payload_size);
+                renumbered_as = renumbered.iter().find(|(old, _)| *old == seq_id).map(|(_, new)| *new);
+            }
+        }
+        if server.drain_events().any(|(_, event)| matches!(event, SocketEvent::Data(ref d) if d.len() == 3000)) {
+            break;
+        }
+    }
+    assert_eq!(shrank_to, Some(MAX_FRAGMENT_MESSAGE_SIZE / 2));
+    assert_eq!(client.negotiated_payload_size(), MAX_FRAGMENT_MESSAGE_SIZE / 2);
+    tick_pair(&mut server, &mut client, 5);
+    assert!(matches!(receiver.try_recv(), Ok(DeliveryResult::Delivered)));
+    let new_seq_id = renumbered_as.unwrap();
+    assert_eq!(client.delivery_status(new_seq_id), DeliveryStatus::Acknowledged);
+}
+
+#[test]
+fn mtu_black_hole_keeps_messages_too_large_to_cut_again() {
+    let (mut server, mut client) = connected_pair();
+    // the remote keeps answering with heartbeats
+    server.set_heartbeat(Duration::from_millis(20));
+    client.socket.transport = Transport::new(|udp_socket, bytes, addr| {
+        if bytes.len() > 700 {
+            return Ok(bytes.len());
+        }
+        udp_socket.send_to(bytes, addr)
+    });
+    let largest = client.max_message_size();
+    let seq_id = client.send_data(Arc::from(vec![4u8; largest]), MessageType::KeyMessage, MessagePriority::Highest).unwrap();
+    let mut shrank = false;
+    for _ in 0..100 {
+        tick_pair(&mut server, &mut client, 1);
+        for event in client.drain_events() {
+            if let SocketEvent::PathMtuShrank { renumbered, .. } = event {
+                assert!(renumbered.is_empty());
+                shrank = true;
+            }
+        }
+        if shrank {
+            break;
+        }
+    }
+    assert!(shrank);
+    // it would need twice as many fragments now, so it is still sent as it was
+    assert_eq!(client.delivery_status(seq_id), DeliveryStatus::Pending);
+    tick_pair(&mut server, &mut client, 2);
+}
+
+#[test]
+fn adaptive_heartbeat_stretches_delay() {
+    let (_server, mut client) = connected_pair();
+    client.set_heartbeat_delay(Duration::from_millis(100));
+    client.set_timeout_delay(Duration::from_secs(3));
+    let fixed = client.next_heartbeat().unwrap();
+    assert_eq!(fixed, client.last_sent_message + Duration::from_millis(100));
+
+    client.set_adaptive_heartbeat(true);
+    let adaptive = client.next_heartbeat().unwrap() - client.last_sent_message;
+    // about a third of the timeout, minus the ping
+    assert!(adaptive > Duration::from_millis(900) && adaptive <= Duration::from_secs(1), "{:?}", adaptive);
+
+    // never more often than heartbeat_delay
+    client.set_heartbeat_delay(Duration::from_secs(2));
+    assert_eq!(client.next_heartbeat().unwrap(), client.last_sent_message + Duration::from_secs(2));
+
+    client.set_heartbeat_enabled(false);
+    assert!(client.next_heartbeat().is_none());
+}
+
+#[test]
+fn late_acks_are_counted() {
+    let (mut server, mut client) = connected_pair();
+    client.set_cleanup_delay(Duration::from_millis(0));
+    let seq_id = client.send_data(Arc::new([1u8; 10]), MessageType::KeyMessage, MessagePriority::Highest).unwrap();
+    tick_pair(&mut server, &mut client, 5);
+    assert!(client.sent_data_tracker.is_seq_id_received(seq_id).is_err());
+    assert_eq!(client.stats().late_acks, 0);
+
+    let late_ack = UdpPacket::from(&Packet::Ack(seq_id, &[1u8][..]));
+    client.add_received_packet(UdpPacket::new(Box::from(late_ack.as_bytes())));
+    // never sent, the remote is confused or lying
+    let bogus_ack = UdpPacket::from(&Packet::Ack(seq_id + 1000, &[1u8][..]));
+    client.add_received_packet(UdpPacket::new(Box::from(bogus_ack.as_bytes())));
+    client.next_tick().unwrap();
+    assert_eq!(client.stats().late_acks, 1);
+}
+
+#[test]
+fn try_reliable_gives_up_quietly() {
+    let (_server, mut client) = connected_pair();
+    let (sender, receiver) = ::std::sync::mpsc::channel();
+    for message_type in [MessageType::TryReliable(Duration::from_millis(20)), MessageType::KeyExpirableMessage(Duration::from_millis(20))] {
+        let sender = sender.clone();
+        client.send_data_with_callback(Arc::new([1u8; 10]), message_type, MessagePriority::Normal, move |result| sender.send(result).unwrap()).unwrap();
+    }
+    // the server is never ticked, so nothing is acknowledged
+    for _ in 0..10 {
+        ::std::thread::sleep(Duration::from_millis(5));
+        client.next_tick().unwrap();
+    }
+    let mut outcomes: Vec<DeliveryResult> = receiver.try_iter().collect();
+    outcomes.sort_by_key(|result| *result as u8);
+    assert_eq!(outcomes, vec![DeliveryResult::Expired, DeliveryResult::GaveUp]);
+}
+
+#[test]
+fn receive_priority_order() {
+    let (mut server, mut client) = connected_pair();
+    server.set_receive_priority_order(true);
+    client.set_send_priority_info(true);
+    let priorities = [MessagePriority::Low, MessagePriority::Normal, MessagePriority::Highest, MessagePriority::Custom { resend_delay: Duration::from_millis(50) }, MessagePriority::High];
+    for (i, priority) in priorities.iter().enumerate() {
+        client.send_data(Arc::new([i as u8]), MessageType::KeyMessage, *priority).unwrap();
+    }
+    // everything arrives during a single tick of the server
+    ::std::thread::sleep(Duration::from_millis(20));
+    server.next_tick().unwrap();
+    let received: Vec<(u8, i8)> = server.drain_events().filter_map(|(_, event)| match event {
+        SocketEvent::Data(data) => Some((data[0], data.priority.rank())),
+        _ => None,
+    }).collect();
+    // same priority keeps the order of arrival
+    assert_eq!(received, vec![(2, 3), (4, 1), (1, 0), (3, 0), (0, -1)]);
+}
+
+#[test]
+fn priority_info_not_sent_by_default() {
+    let (mut server, mut client) = connected_pair();
+    let server_addr = client.socket.remote_addr;
+
+    // what a remote which doesn't know about priorities would receive
+    let spy = UdpSocket::bind("127.0.0.1:0").unwrap();
+    spy.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+    client.socket.remote_addr = spy.local_addr().unwrap();
+    client.send_data(Arc::new([1u8; 10]), MessageType::KeyMessage, MessagePriority::High).unwrap();
+    client.send_data(Arc::new([2u8; 10]), MessageType::KeyMessage, MessagePriority::Low).unwrap();
+    let mut buffer = [0u8; 2048];
+    for _ in 0..2 {
+        let (size, _) = spy.recv_from(&mut buffer).unwrap();
+        let udp_packet = UdpPacket::new(Box::<[u8]>::from(&buffer[..size]));
+        // the "Frag meta" byte holds nothing but the kind of fragment
+        assert_eq!(udp_packet.frag_priority(), 0);
+        assert!(matches!(udp_packet.compute_packet().unwrap(), Packet::Fragment(_)));
+        assert_eq!(buffer[10], crate::fragment::FragmentMeta::Key as u8);
+    }
+
+    // both messages still arrive, once sent again
+    client.socket.remote_addr = server_addr;
+    let mut received: Vec<u8> = Vec::new();
+    for _ in 0..100 {
+        tick_pair(&mut server, &mut client, 1);
+        received.extend(server.drain_events().filter_map(|(_, event)| match event {
+            SocketEvent::Data(data) => Some(data[0]),
+            _ => None,
+        }));
+        if received.len() == 2 {
+            break;
+        }
+    }
+    received.sort_unstable();
+    assert_eq!(received, vec![1, 2]);
+}
+
+#[test]
+fn delivery_status_of_sent_messages() {
+    let (mut server, mut client) = connected_pair();
+    client.set_cleanup_delay(Duration::from_millis(30));
+    let forgettable = client.send_data(Arc::new([1u8; 10]), MessageType::Forgettable, MessagePriority::Normal).unwrap();
+    let key = client.send_data(Arc::new([2u8; 10]), MessageType::ForgettableWithAck, MessagePriority::Normal).unwrap();
+    assert_eq!(client.delivery_status(forgettable), DeliveryStatus::Unknown);
+    assert_eq!(client.delivery_status(key), DeliveryStatus::Pending);
+    assert_eq!(client.delivery_status(key + 1), DeliveryStatus::Unknown);
+    tick_pair(&mut server, &mut client, 3);
+    assert_eq!(client.delivery_status(key), DeliveryStatus::Acknowledged);
+    tick_pair(&mut server, &mut client, 10);
+    // forgotten once the cleanup delay is over, even though it was acknowledged
+    assert_eq!(client.delivery_status(key), DeliveryStatus::Unknown);
+    assert_eq!(client.is_seq_id_received(key), Err(()));
+}
+
+#[test]
+fn drain_unknown_keeps_the_last_datagrams() {
+    let (_server, mut client) = connected_pair();
+    let stranger = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let client_addr = SocketAddr::from(([127, 0, 0, 1], client.local_addr().port()));
+    for i in 0..(MAX_UNKNOWN_DATAGRAMS + 6) {
+        stranger.send_to(&[i as u8, 42], client_addr).unwrap();
+    }
+    ::std::thread::sleep(Duration::from_millis(10));
+    client.next_tick().unwrap();
+    let unknown: Vec<(SocketAddr, Box<[u8]>)> = client.drain_unknown().collect();
+    assert_eq!(unknown.len(), MAX_UNKNOWN_DATAGRAMS);
+    assert_eq!(unknown[0].0, stranger.local_addr().unwrap());
+    // the oldest ones were dropped
+    assert_eq!(&*unknown[0].1, &[6u8, 42][..]);
+    assert!(client.drain_unknown().next().is_none());
+    assert!(client.status().is_connected());
+}
+
+#[test]
+fn send_data_ref_is_forgettable() {
+    let (mut server, mut client) = connected_pair();
+    let seq_id = client.send_data_ref(&[7u8; 3000], MessagePriority::Normal).unwrap();
+    assert_eq!(client.delivery_status(seq_id), DeliveryStatus::Unknown);
+    assert!(client.pending_schedule().is_empty());
+    tick_pair(&mut server, &mut client, 3);
+    let events: Vec<(SocketAddr, SocketEvent)> = server.drain_events().collect();
+    match events.as_slice() {
+        [(_, SocketEvent::Data(data))] => {
+            assert_eq!(data.seq_id, seq_id);
+            assert_eq!(&**data, &[7u8; 3000][..]);
+        },
+        events => panic!("unexpected events {:?}", events),
+    }
+}
+
+#[test]
+fn ack_piggybacking_packs_acks_with_data() {
+    let (mut server, mut client) = connected_pair();
+    server.set_ack_piggybacking(true);
+    let request = client.send_data(Arc::new([1u8; 10]), MessageType::KeyMessage, MessagePriority::Normal).unwrap();
+    ::std::thread::sleep(Duration::from_millis(10));
+    server.next_tick().unwrap();
+    let remote_addr = *server.addresses().next().unwrap();
+    let packets_sent = server.get(remote_addr).unwrap().stats().packets_sent;
+    // the ack of the request goes along with the answer
+    server.get_mut(remote_addr).unwrap().send_data(Arc::new([2u8; 10]), MessageType::KeyMessage, MessagePriority::Normal).unwrap();
+    assert_eq!(server.get(remote_addr).unwrap().stats().packets_sent, packets_sent + 1);
+    ::std::thread::sleep(Duration::from_millis(10));
+    client.next_tick().unwrap();
+    assert!(client.last_ack_bitmap(request).is_some());
+    assert!(matches!(client.drain_events().next(), Some(SocketEvent::Data(_))));
+
+    // with nothing to send, acks are sent on their own a tick later
+    let request = client.send_data(Arc::new([3u8; 10]), MessageType::KeyMessage, MessagePriority::Normal).unwrap();
+    tick_pair(&mut server, &mut client, 3);
+    assert!(client.last_ack_bitmap(request).is_some());
+}
+
+#[test]
+fn send_data_too_large() {
+    let (_server, mut client) = connected_pair();
+    let max = client.max_message_size();
+    assert_eq!(max, crate::consts::MAX_MESSAGE_SIZE);
+    let next_seq_id = client.next_local_seq_id;
+    let too_large: Arc<[u8]> = Arc::from(vec![0u8; max + 1]);
+    assert_eq!(client.send_data(Arc::clone(&too_large), MessageType::KeyMessage, MessagePriority::Normal), Err(SendError::TooLarge { len: max + 1, max }));
+    assert!(client.send_data_ref(&too_large, MessagePriority::Normal).is_err());
+    assert!(client.send_latest(0, Arc::clone(&too_large), MessagePriority::Normal).is_err());
+    // nothing was sent, no seq_id was used
+    assert_eq!(client.next_local_seq_id, next_seq_id);
+    assert_eq!(client.send_data(Arc::from(vec![0u8; max]), MessageType::KeyMessage, MessagePriority::Normal), Ok(next_seq_id));
+
+    client.set_max_payload_size(MIN_PAYLOAD_SIZE);
+    assert_eq!(client.max_message_size(), MAX_FRAGMENTS_IN_MESSAGE * MIN_PAYLOAD_SIZE);
+}
+
+#[test]
+fn send_large_splits_into_messages() {
+    let (mut server, mut client) = connected_pair();
+    client.set_max_payload_size(MIN_PAYLOAD_SIZE);
+    let max = client.max_message_size();
+    let data: Vec<u8> = (0..(2 * max + 10) as u32).map(|i| (i % 251) as u8).collect();
+    assert_eq!(client.send_large(Arc::new([]), MessageType::KeyMessage, MessagePriority::Highest), Ok(Vec::new()));
+    let seq_ids = client.send_large(Arc::from(data.clone()), MessageType::KeyMessage, MessagePriority::Highest).unwrap();
+    assert_eq!(seq_ids.len(), 3);
+    assert!(!client.large_send_complete(&seq_ids));
+    tick_pair(&mut server, &mut client, 40);
+    assert!(client.large_send_complete(&seq_ids));
+
+    let mut parts: Vec<(u32, Box<[u8]>)> = server.drain_events().filter_map(|(_, event)| match event {
+        SocketEvent::Data(d) => Some((d.seq_id, d.data)),
+        _ => None,
+    }).collect();
+    parts.sort_by_key(|(seq_id, _)| *seq_id);
+    assert_eq!(parts.iter().map(|(seq_id, _)| *seq_id).collect::<Vec<_>>(), seq_ids);
+    let received: Vec<u8> = parts.into_iter().flat_map(|(_, part)| part.into_vec()).collect();
+    assert_eq!(received, data);
+}
+
+#[test]
+fn delivery_order_newest_first() {
+    let (mut server, mut client) = connected_pair();
+    server.set_delivery_order(::std::cmp::Reverse);
+    let seq_ids: Vec<u32> = (0..4u8).map(|i| client.send_data(Arc::new([i]), MessageType::KeyMessage, MessagePriority::Normal).unwrap()).collect();
+    // everything arrives during a single tick of the server
+    ::std::thread::sleep(Duration::from_millis(20));
+    server.next_tick().unwrap();
+    let received: Vec<u32> = server.drain_events().filter_map(|(_, event)| match event {
+        SocketEvent::Data(data) => Some(data.seq_id),
+        _ => None,
+    }).collect();
+    assert_eq!(received, seq_ids.into_iter().rev().collect::<Vec<_>>());
+}
+
+#[test]
+fn connect_to_ipv6_remote() {
+    let mut server = match crate::RUdpServer::new("[::1]:0") {
+        Ok(server) => server,
+        // no IPv6 on this host
+        Err(_) => return,
+    };
+    let server_addr = server.udp_socket().local_addr().unwrap();
+    let mut client = RUdpSocket::connect(server_addr).unwrap();
+    assert!(client.local_addr().is_ipv6());
+    tick_pair(&mut server, &mut client, 5);
+    assert!(client.status().is_connected());
+}
+
+#[test]
+fn connect_timeout_fails_connection() {
+    // bound, but never answers
+    let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let mut client = RUdpSocket::connect(silent.local_addr().unwrap()).unwrap();
+    client.set_timeout_delay(Duration::from_millis(5));
+    client.set_connect_timeout(Duration::from_millis(30));
+    client.next_tick().unwrap();
+    ::std::thread::sleep(Duration::from_millis(10));
+    client.next_tick().unwrap();
+    // the regular timeout doesn't apply while connecting
+    assert!(matches!(client.status(), SocketStatus::SynSent(_)));
+    ::std::thread::sleep(Duration::from_millis(25));
+    client.next_tick().unwrap();
+    assert!(matches!(client.status(), SocketStatus::ConnectTimeout(_)));
+    assert!(client.status().is_finished());
+    assert!(matches!(client.drain_events().collect::<Vec<_>>().as_slice(), [SocketEvent::ConnectFailed]));
+}
+
+#[test]
+fn delivered_event_once_per_message() {
+    let (mut server, mut client) = connected_pair();
+    let key = client.send_data(Arc::new([1u8; 10]), MessageType::KeyMessage, MessagePriority::Highest).unwrap();
+    let _forgettable = client.send_data(Arc::new([2u8; 10]), MessageType::Forgettable, MessagePriority::Highest).unwrap();
+    let mut delivered = Vec::new();
+    for _ in 0..20 {
+        tick_pair(&mut server, &mut client, 1);
+        delivered.extend(client.drain_events().filter_map(|event| match event {
+            SocketEvent::Delivered(seq_id) => Some(seq_id),
+            _ => None,
+        }));
+    }
+    assert_eq!(client.is_seq_id_received(key), Ok(true));
+    assert_eq!(delivered, vec![key]);
+}
+
+#[test]
+fn server_state_import_resumes_connections() {
+    let (mut server, mut client) = connected_pair();
+    server.send_data(&Arc::from(&[1u8][..]), MessageType::KeyMessage, MessagePriority::Highest);
+    tick_pair(&mut server, &mut client, 10);
+    let state = server.export_state();
+    assert_eq!(state.remotes.len(), 1);
+    assert_eq!(state.remotes[0].addr.port(), client.local_addr().port());
+    assert_eq!(state.remotes[0].next_seq_id, 1);
+    // the old server crashes without ending anything
+    ::std::mem::forget(server);
+
+    let mut standby = crate::RUdpServer::import_state(&state, UdpSocket::bind("127.0.0.1:0").unwrap()).unwrap();
+    // as if the traffic was redirected to the standby
+    client.socket.remote_addr = standby.udp_socket().local_addr().unwrap();
+    let _events: Vec<_> = client.drain_events().collect();
+    client.send_data(Arc::new([2u8]), MessageType::KeyMessage, MessagePriority::Highest).unwrap();
+    standby.send_data(&Arc::from(&[3u8][..]), MessageType::KeyMessage, MessagePriority::Highest);
+    tick_pair(&mut standby, &mut client, 10);
+
+    assert!(client.status().is_connected());
+    let received: Vec<Box<[u8]>> = standby.drain_events().filter_map(|(_, event)| match event {
+        SocketEvent::Data(d) => Some(d.data),
+        _ => None,
+    }).collect();
+    assert_eq!(received, vec![Box::from(&[2u8][..])]);
+    let received: Vec<(u32, Box<[u8]>)> = client.drain_events().filter_map(|event| match event {
+        SocketEvent::Data(d) => Some((d.seq_id, d.data)),
+        _ => None,
+    }).collect();
+    assert_eq!(received, vec![(1, Box::from(&[3u8][..]))]);
+}
+
+#[test]
+fn inject_datagram_out_of_order_and_corrupted() {
+    use crate::fragment::{Fragment, FragmentMeta};
+    use std::sync::Mutex;
+
+    let (_server, mut client) = connected_pair();
+    let discarded = Arc::new(Mutex::new(Vec::new()));
+    let d = Arc::clone(&discarded);
+    client.set_discard_hook(move |_addr, reason, _bytes| d.lock().unwrap().push(reason));
+    let remote_addr = client.remote_addr();
+
+    let fragments: Vec<Fragment<Box<[u8]>>> = (0..3u8).map(|frag_id| {
+        Fragment { seq_id: 7, frag_id, frag_total: 2, frag_meta: FragmentMeta::Forgettable, data: Box::from(&[frag_id; 4][..]) }
+    }).collect();
+    for frag_id in [2usize, 0, 1].iter() {
+        client.inject_datagram(UdpPacket::from(&fragments[*frag_id]).as_bytes(), remote_addr).unwrap();
+    }
+    let mut corrupted = UdpPacket::from(&fragments[0]).as_bytes().to_vec();
+    corrupted[12] ^= 0xFF;
+    client.inject_datagram(&corrupted, remote_addr).unwrap();
+    client.inject_datagram(&[1, 2, 3], "127.0.0.1:1".parse().unwrap()).unwrap();
+    client.next_tick().unwrap();
+
+    let data: Vec<Box<[u8]>> = client.drain_events().filter_map(|event| match event {
+        SocketEvent::Data(d) => Some(d.data),
+        _ => None,
+    }).collect();
+    assert_eq!(data, vec![Box::from(&[0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2][..])]);
+    let discarded = discarded.lock().unwrap();
+    assert_eq!(discarded.len(), 2);
+    assert!(matches!(discarded[0], DiscardReason::InvalidPacket(_)));
+    assert!(matches!(discarded[1], DiscardReason::UnknownSource));
+}
+
+#[test]
+fn timeout_event_tells_how_long_ago() {
+    let (server, mut client) = connected_pair();
+    client.set_timeout_delay(Duration::from_millis(20));
+    // the server is gone without a word, nothing is received anymore
+    ::std::mem::forget(server);
+    ::std::thread::sleep(Duration::from_millis(60));
+    client.next_tick().unwrap();
+    match client.drain_events().collect::<Vec<_>>().as_slice() {
+        [SocketEvent::Timeout { last_status, since }] => {
+            assert_eq!(*last_status, SocketStatus::Connected);
+            assert!(*since >= Duration::from_millis(60));
+        },
+        events => panic!("unexpected events {:?}", events),
+    }
+}
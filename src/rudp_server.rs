@@ -1,14 +1,71 @@
 use crate::rudp::*;
+use crate::snapshot::{RemoteSnapshot, ServerState};
 use std::net::{SocketAddr, UdpSocket, ToSocketAddrs};
 use std::io::{ErrorKind as IoErrorKind, Result as IoResult};
 use std::sync::Arc;
-use crate::udp_packet::UdpPacket;
-use std::time::Duration;
+use crate::udp_packet::{UdpPacket, PacketMeta};
+use crate::consts::{MIN_PAYLOAD_SIZE, DRAIN_TICK_INTERVAL, PACKET_DATA_START_BYTE, MAX_PRE_HANDSHAKE_PACKETS, MAX_PRE_HANDSHAKE_REMOTES, PRE_HANDSHAKE_DATA_TTL};
+use crate::udp_packet_handler::read_token;
+use crate::rng::SharedRng;
+use crate::fragment::MAX_FRAGMENT_MESSAGE_SIZE;
+use crate::stats::BurstTracker;
+pub use crate::stats::BurstStats;
+use crate::discard::{DiscardHook, DiscardReason};
+#[cfg(feature = "egress_filter")]
+use crate::egress_filter::EgressFilter;
+use rand::RngCore;
+use std::time::{Duration, Instant};
 
-use hashbrown::{HashMap, hash_map::Entry};
+use hashbrown::{HashMap, HashSet};
+use std::collections::BTreeSet;
 use crate::rudp::MessageType;
 use std::ops::{Index, IndexMut};
 
+/// What the server does with data received from an address before it received its Syn.
+///
+/// A client sending data right after its Syn may have it arrive first, when packets are reordered on the way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PreHandshakeDataPolicy {
+    /// Drop it, as `DiscardReason::UnexpectedData`. Reliable messages are re-sent once connected anyway. This is the default.
+    #[default]
+    Reject,
+    /// Keep it until the Syn arrives, and hand it to the new connection right after the Syn.
+    ///
+    /// At most a few seconds worth of packets are kept, for a limited number of addresses,
+    /// so that spoofed traffic can't make the server hold an unbounded amount of data.
+    Buffer,
+}
+
+/// Packets received before the Syn: (first packet received at, packets)
+type PreHandshakePackets = (Instant, Vec<UdpPacket<Box<[u8]>>>);
+
+type ConnectionCallback = dyn FnMut(SocketAddr) -> bool + Send;
+
+/// Function deciding whether a remote may connect. See `RUdpServer::set_connection_filter`.
+#[derive(Default)]
+pub (crate) struct ConnectionFilter(Option<Box<ConnectionCallback>>);
+
+impl ::std::fmt::Debug for ConnectionFilter {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "ConnectionFilter({})", if self.0.is_some() { "set" } else { "unset" })
+    }
+}
+
+impl ConnectionFilter {
+    pub (crate) fn new<F: FnMut(SocketAddr) -> bool + Send + 'static>(f: F) -> ConnectionFilter {
+        ConnectionFilter(Some(Box::new(f)))
+    }
+
+    /// Returns false if the remote must not be allowed to connect.
+    #[inline]
+    pub (crate) fn accepts(&mut self, remote_addr: SocketAddr) -> bool {
+        match &mut self.0 {
+            Some(f) => f(remote_addr),
+            None => true,
+        }
+    }
+}
+
 #[derive(Debug)]
 /// A Server that holds multiple remotes
 ///
@@ -22,8 +79,47 @@ use std::ops::{Index, IndexMut};
 pub struct RUdpServer {
     pub (crate) remotes: HashMap<SocketAddr, RUdpSocket>,
     pub (crate) udp_socket: Arc<UdpSocket>,
+    /// Sockets added with `add_listener`, polled along with `udp_socket`
+    pub (self) extra_listeners: Vec<Arc<UdpSocket>>,
     pub (self) timeout_delay: Option<Duration>,
     pub (self) heartbeat_delay: Option<Duration>,
+    pub (self) heartbeat_enabled: Option<bool>,
+    pub (self) adaptive_heartbeat: Option<bool>,
+    pub (self) receive_priority_order: Option<bool>,
+    pub (self) asymmetry_threshold: Option<(Duration, u32)>,
+    pub (self) end_grace_period: Option<Duration>,
+    pub (self) max_connection_lifetime: Option<Duration>,
+    pub (self) max_bytes_per_tick: Option<Option<usize>>,
+    pub (self) max_retransmit_rate: Option<Option<usize>>,
+    pub (self) strict_mode: Option<(bool, u32)>,
+    pub (self) ack_mode: Option<AckMode>,
+    pub (self) ack_piggybacking: Option<bool>,
+    pub (self) fragment_sizing: Option<FragmentSizing>,
+    pub (self) per_remote_reassembly_limit: Option<usize>,
+    pub (self) min_payload_size: Option<usize>,
+    pub (self) send_priority_info: Option<bool>,
+    pub (self) max_payload_size: Option<usize>,
+    /// Bytes the remotes may send per tick, all together
+    pub (self) send_budget: Option<usize>,
+    /// Remotes which may be connected at the same time, finished ones excluded
+    pub (self) max_remotes: Option<usize>,
+    /// Remotes which are not finished, counted again every tick and kept up to date in between
+    pub (self) live_remotes: usize,
+    /// Remotes which may have something to do before their next due time: they received packets, were given
+    /// data to send, or were lent to the application since their last tick. See `remotes_with_work`.
+    pub (self) dirty_remotes: HashSet<SocketAddr>,
+    /// When every remote has something to do next (resend, heartbeat, timeout, ...), as of its last tick
+    pub (self) remotes_due: BTreeSet<(Instant, SocketAddr)>,
+    pub (self) pre_handshake_data_policy: PreHandshakeDataPolicy,
+    /// Data received from addresses without a connection yet
+    pub (self) pre_handshake_packets: HashMap<SocketAddr, PreHandshakePackets>,
+    pub (self) rng: SharedRng,
+    pub (self) recv_bursts: BurstTracker,
+    pub (self) discard_hook: DiscardHook,
+    pub (self) connection_filter: ConnectionFilter,
+    pub (self) delivery_order: DeliveryOrder,
+    #[cfg(feature = "egress_filter")]
+    pub (self) egress_filter: EgressFilter,
 }
 
 impl RUdpServer {
@@ -32,21 +128,91 @@ impl RUdpServer {
     /// It's often a good idea to have a value like "0.0.0.0:YOUR_PORT",
     /// to bind your address to the internet.
     pub fn new<A: ToSocketAddrs>(local_addr: A) -> IoResult<RUdpServer> {
-        let udp_socket = Arc::new(UdpSocket::bind(local_addr)?);
+        Self::from_socket(UdpSocket::bind(local_addr)?)
+    }
+
+    /// Creates a new server out of an already bound UDP socket.
+    ///
+    /// The socket is set as non-blocking.
+    pub fn from_socket(udp_socket: UdpSocket) -> IoResult<RUdpServer> {
         udp_socket.set_nonblocking(true)?;
         Ok(RUdpServer {
             remotes: HashMap::default(),
-            udp_socket,
+            udp_socket: Arc::new(udp_socket),
+            extra_listeners: Vec::new(),
             timeout_delay: None,
             heartbeat_delay: None,
+            heartbeat_enabled: None,
+            adaptive_heartbeat: None,
+            receive_priority_order: None,
+            asymmetry_threshold: None,
+            end_grace_period: None,
+            max_connection_lifetime: None,
+            max_bytes_per_tick: None,
+            max_retransmit_rate: None,
+            strict_mode: None,
+            ack_mode: None,
+            ack_piggybacking: None,
+            fragment_sizing: None,
+            per_remote_reassembly_limit: None,
+            min_payload_size: None,
+            send_priority_info: None,
+            max_payload_size: None,
+            send_budget: None,
+            max_remotes: None,
+            live_remotes: 0,
+            dirty_remotes: HashSet::default(),
+            remotes_due: BTreeSet::new(),
+            pre_handshake_data_policy: PreHandshakeDataPolicy::default(),
+            pre_handshake_packets: HashMap::default(),
+            rng: SharedRng::default(),
+            recv_bursts: BurstTracker::default(),
+            discard_hook: DiscardHook::default(),
+            connection_filter: ConnectionFilter::default(),
+            delivery_order: DeliveryOrder::default(),
+            #[cfg(feature = "egress_filter")]
+            egress_filter: EgressFilter::default(),
         })
     }
 
+    /// Creates a new server out of an already bound `socket2::Socket`.
+    ///
+    /// This is useful to set options that std's `UdpSocket` does not expose (reuse port,
+    /// binding to a device, buffer sizes, ...) before the server starts using the socket.
+    /// The socket must be an UDP socket.
+    #[cfg(feature = "socket2")]
+    pub fn from_socket2(socket: socket2::Socket) -> IoResult<RUdpServer> {
+        Self::from_socket(socket.into())
+    }
+
+    /// Creates a new server bound with `SO_REUSEPORT`, so that several servers (in different threads
+    /// or processes) can be bound to the same port. All of them must be created this way.
+    ///
+    /// The kernel then spreads the incoming datagrams between the servers. On Linux, it picks the server
+    /// from a hash of the source and destination addresses and ports: all the datagrams of a remote
+    /// reach the same server, and its connection stays on it. This only holds as long as the set of servers
+    /// doesn't change: when a server is added or closed, existing remotes may be sent to another server,
+    /// which doesn't know about them and ignores their packets until they time out. The same goes for a remote
+    /// that migrates to a new address (see `RUdpSocket::rebind`), since its new address is hashed differently.
+    /// Other systems (e.g. the BSDs) may not spread the load at all, or do it differently.
+    #[cfg(all(feature = "socket2", unix))]
+    pub fn with_reuse_port<A: ToSocketAddrs>(local_addr: A) -> IoResult<RUdpServer> {
+        use socket2::{Domain, Socket, Type};
+
+        let local_addr = local_addr.to_socket_addrs()?.next()
+            .ok_or_else(|| ::std::io::Error::new(IoErrorKind::InvalidInput, "no address to bind to"))?;
+        let socket = Socket::new(Domain::for_address(local_addr), Type::DGRAM, None)?;
+        socket.set_reuse_port(true)?;
+        socket.bind(&local_addr.into())?;
+        Self::from_socket2(socket)
+    }
+
     fn update_timeout_delay_for_remotes(&mut self) {
         if let Some(delay) = self.timeout_delay {
             for socket in self.remotes.values_mut() {
                 socket.set_timeout_delay(delay);
             }
+            self.mark_all_dirty();
         }
     }
 
@@ -55,9 +221,15 @@ impl RUdpServer {
             for socket in self.remotes.values_mut() {
                 socket.set_heartbeat_delay(delay);
             }
+            self.mark_all_dirty();
         }
     }
 
+    /// Makes `remotes_with_work` look at every remote until the next tick, when it can't tell which ones changed.
+    fn mark_all_dirty(&mut self) {
+        self.dirty_remotes.extend(self.remotes.keys().copied());
+    }
+
     /// Set the number of iterations required before a remote is set as "dead" for all past and all new remotes.
     /// 
     /// For instance, if your tick is every 50ms, and your timeout_delay is of 24,
@@ -75,31 +247,535 @@ impl RUdpServer {
         self.update_heartbeat_delay_for_remotes();
     }
 
-    fn process_one_incoming(&mut self, udp_packet: UdpPacket<Box<[u8]>>, remote_addr: SocketAddr) -> IoResult<()> {
-        match self.remotes.entry(remote_addr) {
-            Entry::Occupied(mut o) => {
-                o.get_mut().add_received_packet(udp_packet)
+    /// Enable or disable heartbeats for all past and new remotes.
+    ///
+    /// See `RUdpSocket::set_heartbeat_enabled`.
+    pub fn set_heartbeat_enabled(&mut self, enabled: bool) {
+        self.heartbeat_enabled = Some(enabled);
+        for socket in self.remotes.values_mut() {
+            socket.set_heartbeat_enabled(enabled);
+        }
+        self.mark_all_dirty();
+    }
+
+    /// Enable or disable adaptive heartbeats for all past and new remotes.
+    ///
+    /// See `RUdpSocket::set_adaptive_heartbeat`.
+    pub fn set_adaptive_heartbeat(&mut self, adaptive: bool) {
+        self.adaptive_heartbeat = Some(adaptive);
+        for socket in self.remotes.values_mut() {
+            socket.set_adaptive_heartbeat(adaptive);
+        }
+        self.mark_all_dirty();
+    }
+
+    /// Enable or disable handing out received messages by priority for all past and new remotes.
+    ///
+    /// See `RUdpSocket::set_receive_priority_order`.
+    pub fn set_receive_priority_order(&mut self, enabled: bool) {
+        self.receive_priority_order = Some(enabled);
+        for socket in self.remotes.values_mut() {
+            socket.set_receive_priority_order(enabled);
+        }
+    }
+
+    /// Set the order in which received messages are handed out for all past and new remotes.
+    ///
+    /// See `RUdpSocket::set_delivery_order`.
+    pub fn set_delivery_order<K: Ord + 'static, F: Fn(u32) -> K + Send + Sync + 'static>(&mut self, f: F) {
+        self.delivery_order = DeliveryOrder::new(f);
+        for socket in self.remotes.values_mut() {
+            socket.delivery_order = self.delivery_order.clone();
+        }
+    }
+
+    /// Set how many remotes may be connected at the same time, or `None` for no limit, which is the default.
+    ///
+    /// Once the limit is reached, new connections are refused like with `set_connection_filter`, until some remotes
+    /// are finished. Remotes already connected are never kicked, even if there are more of them than the new limit.
+    ///
+    /// Without a limit, a flood of Syns with spoofed source addresses makes the server allocate a remote for each of them.
+    pub fn set_max_remotes(&mut self, max_remotes: Option<usize>) {
+        self.max_remotes = max_remotes;
+    }
+
+    /// Returns true if no more remotes may connect right now, see `set_max_remotes`.
+    fn is_full(&self) -> bool {
+        self.max_remotes.is_some_and(|max_remotes| self.live_remotes >= max_remotes)
+    }
+
+    /// Set what to do with data received from an address before its Syn. Default is `PreHandshakeDataPolicy::Reject`.
+    pub fn set_pre_handshake_data_policy(&mut self, policy: PreHandshakeDataPolicy) {
+        self.pre_handshake_data_policy = policy;
+        if policy == PreHandshakeDataPolicy::Reject {
+            self.pre_handshake_packets.clear();
+        }
+    }
+
+    /// Keeps a data packet from an address without a connection until its Syn arrives.
+    ///
+    /// Returns the packet back as `Err` if it can't be kept: there are too many packets for this address, or too many addresses.
+    fn buffer_pre_handshake_packet(&mut self, udp_packet: UdpPacket<Box<[u8]>>, remote_addr: SocketAddr, now: Instant) -> Result<(), UdpPacket<Box<[u8]>>> {
+        self.pre_handshake_packets.retain(|_, (since, _)| now.saturating_duration_since(*since) < PRE_HANDSHAKE_DATA_TTL);
+        if !self.pre_handshake_packets.contains_key(&remote_addr) && self.pre_handshake_packets.len() >= MAX_PRE_HANDSHAKE_REMOTES {
+            return Err(udp_packet);
+        }
+        let (_, packets) = self.pre_handshake_packets.entry(remote_addr).or_insert_with(|| (now, Vec::new()));
+        if packets.len() >= MAX_PRE_HANDSHAKE_PACKETS {
+            return Err(udp_packet);
+        }
+        packets.push(udp_packet);
+        Ok(())
+    }
+
+    /// Creates a new remote out of an incoming packet, with the settings of this server.
+    ///
+    /// Returns None if the packet could not start a connection (e.g. it is not a Syn).
+    fn new_remote(&self, listener: Arc<UdpSocket>, udp_packet: UdpPacket<Box<[u8]>>, remote_addr: SocketAddr) -> IoResult<Option<RUdpSocket>> {
+        match RUdpSocket::new_incoming(listener, udp_packet, remote_addr, &self.rng) {
+            Err(RUdpCreateError::IoError(io_error)) => Err(io_error),
+            Err(RUdpCreateError::UnexpectedData) => {
+                /* ignore unexpected data */
+                log::trace!("received unexpected UDP data from unknown remote {}", remote_addr);
+                Ok(None)
             },
-            Entry::Vacant(vacant) => {
-                // buffer len is used for debug/log purposes
-                match RUdpSocket::new_incoming(self.udp_socket.clone(), udp_packet, remote_addr) {
-                    Err(RUdpCreateError::IoError(io_error)) => return Err(io_error),
-                    Err(RUdpCreateError::UnexpectedData) => {
-                        /* ignore unexpected data */
-                        log::trace!("received unexpected UDP data from unknown remote {}", remote_addr);
-                    },
-                    Ok(mut rudp_socket) => {
-                        if let Some(delay) = self.timeout_delay {
-                            rudp_socket.set_timeout_delay(delay)
-                        }
-                        if let Some(heartbeat) = self.heartbeat_delay {
-                            rudp_socket.set_heartbeat_delay(heartbeat)
-                        }
-                        vacant.insert(rudp_socket);
-                    },
-                };
+            Ok(mut rudp_socket) => {
+                if let Some(delay) = self.timeout_delay {
+                    rudp_socket.set_timeout_delay(delay)
+                }
+                if let Some(heartbeat) = self.heartbeat_delay {
+                    rudp_socket.set_heartbeat_delay(heartbeat)
+                }
+                if let Some(enabled) = self.heartbeat_enabled {
+                    rudp_socket.set_heartbeat_enabled(enabled)
+                }
+                if let Some(adaptive) = self.adaptive_heartbeat {
+                    rudp_socket.set_adaptive_heartbeat(adaptive)
+                }
+                if let Some(enabled) = self.receive_priority_order {
+                    rudp_socket.set_receive_priority_order(enabled)
+                }
+                if let Some((window, min_inbound)) = self.asymmetry_threshold {
+                    rudp_socket.set_asymmetry_threshold(window, min_inbound)
+                }
+                if let Some(grace_period) = self.end_grace_period {
+                    rudp_socket.set_end_grace_period(grace_period)
+                }
+                if let Some(lifetime) = self.max_connection_lifetime {
+                    rudp_socket.set_max_connection_lifetime(lifetime)
+                }
+                if let Some(max_bytes_per_tick) = self.max_bytes_per_tick {
+                    rudp_socket.set_max_bytes_per_tick(max_bytes_per_tick)
+                }
+                if let Some(bytes_per_sec) = self.max_retransmit_rate {
+                    rudp_socket.set_max_retransmit_rate(bytes_per_sec)
+                }
+                if let Some((strict, threshold)) = self.strict_mode {
+                    rudp_socket.set_strict_mode(strict, threshold)
+                }
+                if let Some(ack_mode) = self.ack_mode {
+                    rudp_socket.set_ack_mode(ack_mode)
+                }
+                if let Some(enabled) = self.ack_piggybacking {
+                    rudp_socket.set_ack_piggybacking(enabled)
+                }
+                if let Some(fragment_sizing) = self.fragment_sizing {
+                    rudp_socket.set_fragment_sizing(fragment_sizing)
+                }
+                if let Some(bytes) = self.per_remote_reassembly_limit {
+                    rudp_socket.set_reassembly_limit(bytes)
+                }
+                if let Some(min_payload_size) = self.min_payload_size {
+                    rudp_socket.set_min_payload_size(min_payload_size)
+                }
+                if let Some(enabled) = self.send_priority_info {
+                    rudp_socket.set_send_priority_info(enabled)
+                }
+                rudp_socket.discard_hook = self.discard_hook.clone();
+                rudp_socket.delivery_order = self.delivery_order.clone();
+                if let Some(max_payload_size) = self.max_payload_size {
+                    rudp_socket.apply_max_payload_size(max_payload_size)
+                }
+                #[cfg(feature = "egress_filter")]
+                {
+                    rudp_socket.socket.egress_filter = self.egress_filter.clone();
+                }
+                // we are only connected once the remote proves it received our SynAck,
+                // by sending us anything else than a Syn
+                rudp_socket.send_synack()?;
+                Ok(Some(rudp_socket))
+            },
+        }
+    }
+
+    /// Set when a `SocketEvent::AsymmetricConnectivity` should be emitted, for all past and new remotes.
+    ///
+    /// See `RUdpSocket::set_asymmetry_threshold`.
+    pub fn set_asymmetry_threshold(&mut self, window: Duration, min_inbound: u32) {
+        self.asymmetry_threshold = Some((window, min_inbound));
+        for socket in self.remotes.values_mut() {
+            socket.set_asymmetry_threshold(window, min_inbound);
+        }
+    }
+
+    /// Set how long remotes keep delivering data after they ended the connection, for all past and new remotes.
+    ///
+    /// See `RUdpSocket::set_end_grace_period`.
+    pub fn set_end_grace_period(&mut self, end_grace_period: Duration) {
+        self.end_grace_period = Some(end_grace_period);
+        for socket in self.remotes.values_mut() {
+            socket.set_end_grace_period(end_grace_period);
+        }
+    }
+
+    /// Set how long connections may last, for all past and new remotes. Remotes have to connect again afterwards.
+    ///
+    /// See `RUdpSocket::set_max_connection_lifetime`.
+    pub fn set_max_connection_lifetime(&mut self, max_connection_lifetime: Duration) {
+        self.max_connection_lifetime = Some(max_connection_lifetime);
+        for socket in self.remotes.values_mut() {
+            socket.set_max_connection_lifetime(max_connection_lifetime);
+        }
+        self.mark_all_dirty();
+    }
+
+    /// Set how many bytes of messages each remote may send from one tick to the next, for all past and new remotes.
+    ///
+    /// See `RUdpSocket::set_max_bytes_per_tick`. Unlike `set_send_budget`, this is a limit for each remote on its own.
+    pub fn set_max_bytes_per_tick(&mut self, max_bytes_per_tick: Option<usize>) {
+        self.max_bytes_per_tick = Some(max_bytes_per_tick);
+        for socket in self.remotes.values_mut() {
+            socket.set_max_bytes_per_tick(max_bytes_per_tick);
+        }
+    }
+
+    /// Set a ceiling on the bytes each remote resends per second, for all past and new remotes.
+    ///
+    /// See `RUdpSocket::set_max_retransmit_rate`.
+    pub fn set_max_retransmit_rate(&mut self, bytes_per_sec: Option<usize>) {
+        self.max_retransmit_rate = Some(bytes_per_sec);
+        for socket in self.remotes.values_mut() {
+            socket.set_max_retransmit_rate(bytes_per_sec);
+        }
+    }
+
+    /// Enable or disable strict mode for all past and new remotes: remotes sending too many malformed packets are kicked.
+    ///
+    /// See `RUdpSocket::set_strict_mode`.
+    pub fn set_strict_mode(&mut self, strict: bool, malformed_packet_threshold: u32) {
+        self.strict_mode = Some((strict, malformed_packet_threshold));
+        for socket in self.remotes.values_mut() {
+            socket.set_strict_mode(strict, malformed_packet_threshold);
+        }
+    }
+
+    /// Set the largest fragment payload advertised to all past and new remotes.
+    ///
+    /// See `RUdpSocket::set_max_payload_size`. Remotes which are already connected keep the value
+    /// they negotiated, only what we send to them changes.
+    pub fn set_max_payload_size(&mut self, max_payload_size: usize) {
+        let max_payload_size = max_payload_size.clamp(MIN_PAYLOAD_SIZE, MAX_FRAGMENT_MESSAGE_SIZE);
+        self.max_payload_size = Some(max_payload_size);
+        for socket in self.remotes.values_mut() {
+            socket.apply_max_payload_size(max_payload_size);
+        }
+    }
+
+    /// Set the fragment payload size advertised to all past and new remotes, above the default if need be.
+    ///
+    /// See `RUdpSocket::set_fragment_payload_size`, which returns the same errors.
+    pub fn set_fragment_payload_size(&mut self, size: usize) -> IoResult<()> {
+        check_fragment_payload_size(size)?;
+        self.max_payload_size = Some(size);
+        for socket in self.remotes.values_mut() {
+            socket.apply_max_payload_size(size);
+        }
+        Ok(())
+    }
+
+    /// Set when we acknowledge the fragments we receive, for all past and new remotes.
+    ///
+    /// See `RUdpSocket::set_ack_mode`.
+    pub fn set_ack_mode(&mut self, ack_mode: AckMode) {
+        self.ack_mode = Some(ack_mode);
+        for socket in self.remotes.values_mut() {
+            socket.set_ack_mode(ack_mode);
+        }
+    }
+
+    /// Enable or disable sending acks along with data for all past and new remotes.
+    ///
+    /// See `RUdpSocket::set_ack_piggybacking`.
+    pub fn set_ack_piggybacking(&mut self, enabled: bool) {
+        self.ack_piggybacking = Some(enabled);
+        for socket in self.remotes.values_mut() {
+            socket.set_ack_piggybacking(enabled);
+        }
+    }
+
+    /// Set how the messages sent to all past and new remotes are cut into fragments.
+    ///
+    /// See `RUdpSocket::set_fragment_sizing`.
+    pub fn set_fragment_sizing(&mut self, fragment_sizing: FragmentSizing) {
+        self.fragment_sizing = Some(fragment_sizing);
+        for socket in self.remotes.values_mut() {
+            socket.set_fragment_sizing(fragment_sizing);
+        }
+    }
+
+    /// Pad the fragments sent to all past and new remotes, so that they all hold at least `min_payload_size` bytes.
+    ///
+    /// See `RUdpSocket::set_min_payload_size`.
+    pub fn set_min_payload_size(&mut self, min_payload_size: usize) {
+        self.min_payload_size = Some(min_payload_size);
+        for socket in self.remotes.values_mut() {
+            socket.set_min_payload_size(min_payload_size);
+        }
+    }
+
+    /// Tell all past and new remotes the priority of the messages sent to them.
+    ///
+    /// See `RUdpSocket::set_send_priority_info`.
+    pub fn set_send_priority_info(&mut self, enabled: bool) {
+        self.send_priority_info = Some(enabled);
+        for socket in self.remotes.values_mut() {
+            socket.set_send_priority_info(enabled);
+        }
+    }
+
+    /// Set how many bytes of incomplete messages each remote may have us keep for reassembly, for all past and new remotes.
+    ///
+    /// A remote going above it only loses its own oldest incomplete messages, so a single remote sending
+    /// many partial messages cannot hog the memory of the server.
+    ///
+    /// See `RUdpSocket::set_reassembly_limit`.
+    pub fn set_per_remote_reassembly_limit(&mut self, bytes: usize) {
+        self.per_remote_reassembly_limit = Some(bytes);
+        for socket in self.remotes.values_mut() {
+            socket.set_reassembly_limit(bytes);
+        }
+    }
+
+    /// Limit how many bytes all the remotes together may send per tick.
+    ///
+    /// The budget is shared between the remotes which have something to send according to their weight
+    /// (see `set_remote_weight`), so that a few remotes with large messages don't starve the others.
+    /// Idle and finished remotes get no share. A remote which could not use its whole share
+    /// because a resend was too large keeps the rest for the next tick.
+    ///
+    /// Messages are always sent right away when you call `send_data`, but they use up the share of their remote:
+    /// the budget delays re-sending the fragments that were not acknowledged.
+    pub fn set_send_budget(&mut self, bytes_per_tick: usize) {
+        self.send_budget = Some(bytes_per_tick);
+    }
+
+    /// Remove the limit set by `set_send_budget`.
+    pub fn remove_send_budget(&mut self) {
+        self.send_budget = None;
+        for socket in self.remotes.values_mut() {
+            socket.send_allowance = None;
+        }
+    }
+
+    /// Set the share of the send budget the remote gets, relative to the other remotes. Default is 1.
+    ///
+    /// A remote with a weight of 3 gets 3 times the share of a remote with a weight of 1.
+    /// A weight of 0 is treated as 1. Returns false if there is no such remote.
+    pub fn set_remote_weight(&mut self, socket_addr: SocketAddr, weight: u32) -> bool {
+        match self.remotes.get_mut(&socket_addr) {
+            Some(socket) => {
+                socket.send_weight = weight.max(1);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Set whether the CRC of the packets received from this remote is checked. Default is true.
+    ///
+    /// See `RUdpSocket::set_verify_crc`. The Syn creating a remote is always checked.
+    /// Returns false if there is no such remote.
+    pub fn set_remote_verify_crc(&mut self, socket_addr: SocketAddr, verify_crc: bool) -> bool {
+        match self.remotes.get_mut(&socket_addr) {
+            Some(socket) => {
+                socket.set_verify_crc(verify_crc);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Split the send budget between the remotes which have something to send this tick.
+    fn distribute_send_budget(&mut self) {
+        if let Some(budget) = self.send_budget {
+            let total_weight: u64 = self.remotes.values()
+                .filter(|socket| socket.has_data_to_send())
+                .map(|socket| socket.send_weight as u64)
+                .sum();
+            for socket in self.remotes.values_mut() {
+                if !socket.has_data_to_send() {
+                    socket.send_allowance = Some(0);
+                    continue;
+                }
+                let share = (budget as u64 * socket.send_weight as u64 / total_weight.max(1)) as usize;
+                // a remote which was held back keeps what it has not used, so it can afford its resend eventually
+                let carried = if socket.send_deferred { socket.send_allowance.unwrap_or(0) } else { 0 };
+                socket.send_allowance = Some(carried.saturating_add(share));
             }
+        }
+    }
+
+    /// Replace the source of randomness used by this server and all its remotes, past and new.
+    /// The OS's random number generator is used by default.
+    ///
+    /// This is mostly useful for tests, to make randomized behavior deterministic with a seeded generator.
+    ///
+    /// **Warning**: some features rely on this randomness being unpredictable for their security.
+    /// Never use a weak or seeded generator outside of tests.
+    pub fn set_rng<R: RngCore + Send + 'static>(&mut self, rng: R) {
+        self.rng = SharedRng::new(rng);
+        for socket in self.remotes.values_mut() {
+            socket.rng = self.rng.clone();
+        }
+    }
+
+    /// Set a function called with every packet the server or one of its remotes discards, for all past and new remotes.
+    ///
+    /// See `RUdpSocket::set_discard_hook`. Packets coming from an unknown address which can't start
+    /// a connection are reported as well.
+    pub fn set_discard_hook<F: FnMut(SocketAddr, DiscardReason, &[u8]) + Send + 'static>(&mut self, f: F) {
+        self.discard_hook = DiscardHook::new(f);
+        for socket in self.remotes.values_mut() {
+            socket.discard_hook = self.discard_hook.clone();
+        }
+    }
+
+    /// Set a function deciding whether a remote may connect, for instance to only accept known addresses,
+    /// or to limit the number of connections coming from the same IP.
+    ///
+    /// It is called with the address of the remote every time a Syn would start a new connection. If it returns false,
+    /// the Syn is dropped as `DiscardReason::ConnectionRefused` and the remote is never told: to it, the server
+    /// looks unreachable. Remotes already connected are not affected.
+    pub fn set_connection_filter<F: FnMut(SocketAddr) -> bool + Send + 'static>(&mut self, f: F) {
+        self.connection_filter = ConnectionFilter::new(f);
+    }
+
+    /// Set a function called with every datagram sent to a remote, for all past and new remotes.
+    ///
+    /// See `RUdpSocket::set_egress_filter`.
+    #[cfg(feature = "egress_filter")]
+    pub fn set_egress_filter<F: FnMut(&mut Vec<u8>, &mut SocketAddr) -> bool + Send + 'static>(&mut self, f: F) {
+        self.egress_filter = EgressFilter::new(f);
+        for socket in self.remotes.values_mut() {
+            socket.socket.egress_filter = self.egress_filter.clone();
+        }
+    }
+
+    /// Moves the remote owning the token in the Migrate packet to its new address.
+    ///
+    /// Returns false if no remote has this token.
+    fn migrate_remote(&mut self, listener: &Arc<UdpSocket>, udp_packet: &UdpPacket<Box<[u8]>>, new_addr: SocketAddr) -> IoResult<bool> {
+        // the header has already been checked by the caller
+        let token = read_token(&udp_packet.as_bytes()[PACKET_DATA_START_BYTE..]);
+        let old_addr = token.and_then(|token| {
+            self.remotes.iter()
+                .find(|(_, socket)| socket.connection_token == Some(token) && !socket.status().is_finished())
+                .map(|(addr, _)| *addr)
+        });
+        match old_addr.and_then(|old_addr| self.remotes.remove(&old_addr)) {
+            Some(mut socket) => {
+                socket.migrate_to(new_addr, Arc::clone(listener))?;
+                self.remotes.insert(new_addr, socket);
+                self.dirty_remotes.insert(new_addr);
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+
+    /// Returns the address of the live connection owning the token in the Rejoin packet, if any.
+    fn rejoined_remote(&self, udp_packet: &UdpPacket<Box<[u8]>>) -> Option<SocketAddr> {
+        // the header has already been checked by the caller
+        let token = read_token(&udp_packet.as_bytes()[PACKET_DATA_START_BYTE..])?;
+        self.remotes.iter()
+            .find(|(_, socket)| socket.connection_token == Some(token) && !socket.status().is_finished())
+            .map(|(addr, _)| *addr)
+    }
+
+    fn process_one_incoming(&mut self, listener: &Arc<UdpSocket>, udp_packet: UdpPacket<Box<[u8]>>, remote_addr: SocketAddr) -> IoResult<()> {
+        if let Some(socket) = self.remotes.get_mut(&remote_addr) {
+            // A finished socket may still be waiting to be cleared. If the same remote tries to connect again
+            // in the meantime, we don't want it to wait for the old one to be removed.
+            let is_reconnecting = socket.status().is_finished() && matches!(udp_packet.peek_meta(), Ok(PacketMeta::Syn) | Ok(PacketMeta::Rejoin));
+            if !is_reconnecting {
+                socket.add_received_packet(udp_packet);
+                self.dirty_remotes.insert(remote_addr);
+                return Ok(());
+            }
+            log::info!("remote {} is reconnecting, replacing its finished connection", remote_addr);
+        } else {
+            match udp_packet.peek_meta() {
+                Ok(PacketMeta::Syn) | Ok(PacketMeta::Rejoin) => {},
+                Ok(PacketMeta::Migrate) => {
+                    if !self.migrate_remote(listener, &udp_packet, remote_addr)? {
+                        log::trace!("received a Migrate with an unknown token from {}", remote_addr);
+                        self.discard_hook.call(remote_addr, DiscardReason::UnexpectedData, udp_packet.as_bytes());
+                    }
+                    return Ok(());
+                },
+                Ok(PacketMeta::Fragment(..)) | Ok(PacketMeta::Batch) if self.pre_handshake_data_policy == PreHandshakeDataPolicy::Buffer => {
+                    log::trace!("keeping data from unknown remote {} until its Syn arrives", remote_addr);
+                    if let Err(udp_packet) = self.buffer_pre_handshake_packet(udp_packet, remote_addr, Instant::now()) {
+                        self.discard_hook.call(remote_addr, DiscardReason::UnexpectedData, udp_packet.as_bytes());
+                    }
+                    return Ok(());
+                },
+                Ok(_) => {
+                    log::trace!("received unexpected UDP data from unknown remote {}", remote_addr);
+                    self.discard_hook.call(remote_addr, DiscardReason::UnexpectedData, udp_packet.as_bytes());
+                    return Ok(());
+                },
+                Err(e) => {
+                    log::trace!("received invalid UDP data from unknown remote {}", remote_addr);
+                    self.discard_hook.call(remote_addr, DiscardReason::InvalidPacket(e), udp_packet.as_bytes());
+                    return Ok(());
+                },
+            }
+        }
+        // a Rejoin takes the place of the connection it replaces, it doesn't add one
+        let rejoined_remote = match udp_packet.peek_meta() {
+            Ok(PacketMeta::Rejoin) => self.rejoined_remote(&udp_packet),
+            _ => None,
         };
+        if rejoined_remote.is_none() && self.is_full() {
+            log::debug!("refused connection from {}: too many remotes", remote_addr);
+            self.pre_handshake_packets.remove(&remote_addr);
+            self.discard_hook.call(remote_addr, DiscardReason::ConnectionRefused, udp_packet.as_bytes());
+            return Ok(());
+        }
+        if !self.connection_filter.accepts(remote_addr) {
+            log::debug!("refused connection from {}", remote_addr);
+            self.pre_handshake_packets.remove(&remote_addr);
+            self.discard_hook.call(remote_addr, DiscardReason::ConnectionRefused, udp_packet.as_bytes());
+            return Ok(());
+        }
+        // only now that the new connection is accepted, the old one can go
+        if let Some(old_addr) = rejoined_remote {
+            self.remotes.get_mut(&old_addr).expect("the remote was there a moment ago").replaced_by(remote_addr);
+            self.live_remotes -= 1;
+            self.dirty_remotes.insert(old_addr);
+        }
+        if let Some(mut rudp_socket) = self.new_remote(Arc::clone(listener), udp_packet, remote_addr)? {
+            if let Some((_, packets)) = self.pre_handshake_packets.remove(&remote_addr) {
+                log::debug!("handing {} packets received before the Syn to remote {}", packets.len(), remote_addr);
+                for packet in packets {
+                    rudp_socket.add_received_packet(packet);
+                }
+            }
+            self.remotes.insert(remote_addr, rudp_socket);
+            self.live_remotes += 1;
+            self.dirty_remotes.insert(remote_addr);
+        }
         Ok(())
     }
 
@@ -108,13 +784,42 @@ impl RUdpServer {
         Arc::clone(&self.udp_socket)
     }
 
+    /// Listen on another address as well, for instance to be reachable from both a LAN and a WAN interface.
+    ///
+    /// Remotes connecting through any of the addresses are handled by this server all the same.
+    /// Each remote is answered through the socket it connected to, so it always sees the address it connected to.
+    ///
+    /// Remotes are still identified by their own address only: the same remote address connecting
+    /// through two listeners is seen as a single remote.
+    ///
+    /// Returns the local address of the new listener, useful when binding to port 0.
+    pub fn add_listener<A: ToSocketAddrs>(&mut self, local_addr: A) -> IoResult<SocketAddr> {
+        let udp_socket = UdpSocket::bind(local_addr)?;
+        udp_socket.set_nonblocking(true)?;
+        let local_addr = udp_socket.local_addr()?;
+        self.extra_listeners.push(Arc::new(udp_socket));
+        Ok(local_addr)
+    }
+
     pub (crate) fn process_all_incoming(&mut self) -> IoResult<()> {
+        let mut received = self.process_all_incoming_from(Arc::clone(&self.udp_socket))?;
+        for i in 0..self.extra_listeners.len() {
+            received += self.process_all_incoming_from(Arc::clone(&self.extra_listeners[i]))?;
+        }
+        self.recv_bursts.record_tick(received);
+        Ok(())
+    }
+
+    /// Returns the number of packets read from `listener`.
+    fn process_all_incoming_from(&mut self, listener: Arc<UdpSocket>) -> IoResult<usize> {
         let mut done = false;
+        let mut received = 0;
 
         while !done {
-            match UdpPacket::<Box<[u8]>>::from_udp_socket(&self.udp_socket) {
+            match UdpPacket::<Box<[u8]>>::from_udp_socket(&listener) {
                 Ok((packet, remote_addr)) => {
-                    self.process_one_incoming(packet, remote_addr)?;
+                    received += 1;
+                    self.process_one_incoming(&listener, packet, remote_addr)?;
                 },
                 Err(err) => {
                     match err.kind() {
@@ -126,14 +831,67 @@ impl RUdpServer {
                 },
             };
         };
-        Ok(())
+        Ok(received)
+    }
+
+    /// Distribution of the number of packets received per tick, all listeners combined.
+    ///
+    /// Useful to size the receive buffer of the sockets: if the buffer can't hold a whole burst,
+    /// the packets past it are dropped by the OS before we get to read them.
+    pub fn recv_burst_stats(&self) -> BurstStats {
+        self.recv_bursts.stats()
+    }
+
+    /// Start counting the packets received per tick from scratch.
+    pub fn reset_recv_burst_stats(&mut self) {
+        self.recv_bursts = BurstTracker::default();
     }
 
     /// Send some data to ALL remotes
-    pub fn send_data(&mut self, data: &Arc<[u8]>, message_type: MessageType, message_priority: MessagePriority) {
-        for socket in self.remotes.values_mut() {
-            socket.send_data(Arc::clone(data), message_type, message_priority);
+    ///
+    /// Every remote has its own sequence of seq_ids, so the seq_id given to the message by each remote is returned,
+    /// to be used with `RUdpSocket::is_seq_id_received` for instance. Remotes may not all accept messages
+    /// of the same size, so the message may be too large for some of them only: see `RUdpSocket::send_data`.
+    pub fn send_data(&mut self, data: &Arc<[u8]>, message_type: MessageType, message_priority: MessagePriority) -> Vec<(SocketAddr, Result<u32, SendError>)> {
+        let dirty_remotes = &mut self.dirty_remotes;
+        self.remotes.iter_mut().map(|(addr, socket)| {
+            dirty_remotes.insert(*addr);
+            (*addr, socket.send_data(Arc::clone(data), message_type, message_priority))
+        }).collect()
+    }
+
+    /// Send some data to all remotes but `exclude`, for instance to relay what a client did to everyone else.
+    ///
+    /// Same as `send_data` otherwise: the seq_id given by every remote the message was sent to is returned.
+    pub fn send_data_except(&mut self, exclude: SocketAddr, data: &Arc<[u8]>, message_type: MessageType, message_priority: MessagePriority) -> Vec<(SocketAddr, Result<u32, SendError>)> {
+        let dirty_remotes = &mut self.dirty_remotes;
+        self.remotes.iter_mut().filter(|(addr, _)| **addr != exclude).map(|(addr, socket)| {
+            dirty_remotes.insert(*addr);
+            (*addr, socket.send_data(Arc::clone(data), message_type, message_priority))
+        }).collect()
+    }
+
+    /// Send some data to a single remote
+    ///
+    /// Returns `None` if there is no remote with this address, otherwise what `RUdpSocket::send_data` returned.
+    pub fn send_data_to(&mut self, addr: SocketAddr, data: Arc<[u8]>, message_type: MessageType, message_priority: MessagePriority) -> Option<Result<u32, SendError>> {
+        self.get_mut(addr).map(|socket| socket.send_data(data, message_type, message_priority))
+    }
+
+    /// Sends a single raw datagram to any address, through the server's socket.
+    ///
+    /// This does not create a remote, nor does it need one: it is mostly useful to answer discovery probes,
+    /// or to tell a client the server is full without tracking a connection.
+    ///
+    /// The bytes are sent as-is, bypassing everything this crate provides: there is no framing, no CRC,
+    /// no fragmentation and no reliability. A `RUdpSocket` on the other end will not be able to read them,
+    /// the remote must read its `UdpSocket` directly.
+    pub fn send_raw_to(&self, addr: SocketAddr, bytes: &[u8]) -> IoResult<()> {
+        let sent_size = self.udp_socket.send_to(bytes, addr)?;
+        if sent_size != bytes.len() {
+            log::warn!("raw datagram to {} was truncated: {} bytes out of {} were sent", addr, sent_size, bytes.len());
         }
+        Ok(())
     }
 
     #[inline]
@@ -150,8 +908,21 @@ impl RUdpServer {
             socket.update_cached_now();
         }
         self.process_all_incoming()?;
-        for socket in self.remotes.values_mut() {
+        self.distribute_send_budget();
+        self.live_remotes = 0;
+        self.dirty_remotes.clear();
+        self.remotes_due.clear();
+        for (addr, socket) in self.remotes.iter_mut() {
             socket.inner_tick()?;
+            if !socket.status().is_finished() {
+                self.live_remotes += 1;
+            }
+            if socket.has_pending_work() {
+                self.dirty_remotes.insert(*addr);
+            }
+            if let Some(due) = socket.next_due() {
+                self.remotes_due.insert((due, *addr));
+            }
         }
         Ok(())
     }
@@ -161,6 +932,7 @@ impl RUdpServer {
     }
 
     pub fn iter_mut(&mut self) -> impl Iterator<Item=(&SocketAddr, &mut RUdpSocket)> {
+        self.mark_all_dirty();
         self.remotes.iter_mut()
     }
 
@@ -168,6 +940,114 @@ impl RUdpServer {
         self.remotes.keys()
     }
 
+    /// Returns the addresses of the remotes which have something to be done right now: events to drain, packets
+    /// to process, messages or heartbeats to send, ... in no particular order.
+    ///
+    /// On a server with many mostly idle remotes, the application can look at these ones only instead of all of them.
+    /// A remote missing from this list would have nothing to do if it was ticked now; `next_tick` still ticks all of them.
+    ///
+    /// Only the remotes that changed since the last tick, and the ones whose next resend, heartbeat or timeout is due,
+    /// are looked at: this doesn't go through all the remotes.
+    pub fn remotes_with_work(&self) -> impl Iterator<Item=&SocketAddr> {
+        let now = Instant::now();
+        let due_remotes = self.remotes_due.iter()
+            .take_while(move |(due, _)| *due <= now)
+            .map(|(_, addr)| addr)
+            .filter(move |addr| !self.dirty_remotes.contains(*addr));
+        self.dirty_remotes.iter()
+            .chain(due_remotes)
+            .filter(move |addr| self.remotes.get(*addr).is_some_and(|socket| socket.has_work(now)))
+    }
+
+    /// Returns the state and main counters of every remote, in no particular order.
+    pub fn snapshot(&self) -> Vec<RemoteSnapshot> {
+        self.remotes.values().map(RUdpSocket::snapshot).collect()
+    }
+
+    /// Exports what a standby server needs to take over the connected remotes, see `import_state`.
+    ///
+    /// Only connections are exported, not the messages in flight: the remotes send again what we didn't
+    /// acknowledge, but what we didn't send yet or what the remotes didn't acknowledge yet is lost.
+    pub fn export_state(&self) -> ServerState {
+        ServerState {
+            remotes: self.remotes.values()
+                .filter(|socket| socket.status().is_connected())
+                .map(RUdpSocket::export_state)
+                .collect(),
+        }
+    }
+
+    /// Creates a server which carries on the connections of `state`, as exported by `export_state` on another server.
+    ///
+    /// `udp_socket` must receive what the remotes send to the old server: bound to the same address, or to an address
+    /// the traffic is redirected to. The remotes see nothing but a few lost packets. Settings of the old server are not
+    /// part of the state, they must be set again; they apply to the imported remotes as well.
+    ///
+    /// Messages the remotes sent again and which the old server had already received are received a second time.
+    pub fn import_state(state: &ServerState, udp_socket: UdpSocket) -> IoResult<RUdpServer> {
+        let mut server = Self::from_socket(udp_socket)?;
+        for remote in &state.remotes {
+            let socket = RUdpSocket::from_state(Arc::clone(&server.udp_socket), remote, &server.rng)?;
+            log::info!("resuming connection of remote {}", remote.addr);
+            server.remotes.insert(remote.addr, socket);
+            server.live_remotes += 1;
+        }
+        Ok(server)
+    }
+
+    /// Ticks the server until every remote has acknowledged every message sent to it, then terminates all of them.
+    ///
+    /// Returns true if everything was acknowledged, false if `deadline` passed first. Remotes whose connection ends
+    /// in the meantime are not waited for. See `RUdpSocket::drain_and_terminate`.
+    pub fn drain_and_terminate(mut self, deadline: Duration) -> IoResult<bool> {
+        let started_at = ::std::time::Instant::now();
+        let drained = loop {
+            self.next_tick()?;
+            let pending = self.remotes.values().any(|socket| !socket.status().is_finished() && socket.pending_sends() > 0);
+            if !pending {
+                break true;
+            }
+            if started_at.elapsed() >= deadline {
+                break false;
+            }
+            ::std::thread::sleep(DRAIN_TICK_INTERVAL);
+        };
+        for socket in self.remotes.values_mut() {
+            if !socket.status().is_finished() {
+                socket.send_end()?;
+            }
+        }
+        Ok(drained)
+    }
+
+    /// Returns how many bytes of incomplete incoming messages we are holding, all remotes combined.
+    ///
+    /// See `RUdpSocket::reassembly_buffered_bytes` and `set_per_remote_reassembly_limit`.
+    pub fn reassembly_buffered_bytes(&self) -> usize {
+        self.remotes.values().map(RUdpSocket::reassembly_buffered_bytes).sum()
+    }
+
+    /// Returns the bytes per second sent to all remotes together during the last second.
+    ///
+    /// See `RUdpSocket::current_send_rate`.
+    pub fn current_send_rate(&self) -> u64 {
+        self.remotes.values().map(RUdpSocket::current_send_rate).sum()
+    }
+
+    /// Returns the bytes per second received from all remotes together during the last second.
+    ///
+    /// See `RUdpSocket::current_recv_rate`.
+    pub fn current_recv_rate(&self) -> u64 {
+        self.remotes.values().map(RUdpSocket::current_recv_rate).sum()
+    }
+
+    /// Resets the stats of all the remotes. See `RUdpSocket::reset_stats`.
+    pub fn reset_all_stats(&mut self) {
+        for socket in self.remotes.values_mut() {
+            socket.reset_stats();
+        }
+    }
+
     /// Get the socket stored for given the address
     pub fn get(&self, socket_addr: SocketAddr) -> Option<&RUdpSocket> {
         self.remotes.get(&socket_addr)
@@ -175,7 +1055,34 @@ impl RUdpServer {
     
     /// Get the mutable socket stored for given the address
     pub fn get_mut(&mut self, socket_addr: SocketAddr) -> Option<&mut RUdpSocket> {
-        self.remotes.get_mut(&socket_addr)
+        let socket = self.remotes.get_mut(&socket_addr)?;
+        self.dirty_remotes.insert(socket_addr);
+        Some(socket)
+    }
+
+    /// Removes a connected remote from this server, and moves its connection to a new UDP socket of its own,
+    /// so that it can be ticked on its own with `RUdpSocket::next_tick`, for instance by a dedicated worker.
+    ///
+    /// The new socket is bound to the same IP as the one the remote used, on a port chosen by the OS.
+    /// The remote is told to send everything there from now on, which only works if it runs a version of this
+    /// crate that follows such a move. Until it does, what it sends still reaches the server, which drops it
+    /// as coming from an unknown remote: reliable messages in flight are re-sent as usual, but forgettable ones
+    /// may be lost. Events not drained yet stay with the detached socket.
+    ///
+    /// Returns None if there is no such remote, or if it is not connected: it must have received the
+    /// token we gave it when it connected, to trust the move.
+    pub fn detach(&mut self, socket_addr: SocketAddr) -> IoResult<Option<RUdpSocket>> {
+        let listener_ip = match self.remotes.get(&socket_addr) {
+            Some(socket) if socket.status().is_connected() => socket.local_addr().ip(),
+            _ => return Ok(None),
+        };
+        let udp_socket = UdpSocket::bind((listener_ip, 0))?;
+        udp_socket.set_nonblocking(true)?;
+        let local_addr = udp_socket.local_addr()?;
+        let mut socket = self.remotes.remove(&socket_addr).expect("the remote was there a moment ago");
+        self.live_remotes -= 1;
+        socket.move_to_own_socket(udp_socket, local_addr);
+        Ok(Some(socket))
     }
 
     /// Returns an iterator that drain events for all remotes.
@@ -184,18 +1091,743 @@ impl RUdpServer {
             socket.drain_events().map(move |event| (*addr, event) )
         })
     }
+
+    /// Returns an iterator that drain events for all remotes, yielding all the events of a remote at once.
+    ///
+    /// Events of a remote are in the order they were received, like with `drain_events`. The order in
+    /// which remotes are yielded is arbitrary, sort them yourself if you need one. Remotes without any
+    /// event are skipped.
+    pub fn drain_events_grouped<'a>(&'a mut self) -> impl 'a + Iterator<Item=(SocketAddr, Vec<SocketEvent>)> {
+        self.remotes.iter_mut().filter_map(|(addr, socket)| {
+            let events: Vec<SocketEvent> = socket.drain_events().collect();
+            if events.is_empty() {
+                None
+            } else {
+                Some((*addr, events))
+            }
+        })
+    }
+
+    /// Returns an iterator that drain `Data` events for all remotes, leaving the other events queued.
+    ///
+    /// See `RUdpSocket::drain_data` for the ordering guarantees.
+    pub fn drain_data<'a>(&'a mut self) -> impl 'a + Iterator<Item=(SocketAddr, ReceivedData)> {
+        self.remotes.iter_mut().flat_map(|(addr, socket)| {
+            socket.drain_data().map(move |data| (*addr, data))
+        })
+    }
+
+    /// Returns an iterator that drain all events but `Data` events for all remotes.
+    ///
+    /// See `RUdpSocket::drain_data` for the ordering guarantees.
+    pub fn drain_meta<'a>(&'a mut self) -> impl 'a + Iterator<Item=(SocketAddr, SocketEvent)> {
+        self.remotes.iter_mut().flat_map(|(addr, socket)| {
+            socket.drain_meta().map(move |event| (*addr, event))
+        })
+    }
 }
 
 impl Index<SocketAddr> for RUdpServer {
     type Output = RUdpSocket;
 
-    fn index<'a>(&'a self, index: SocketAddr) -> &'a RUdpSocket {
+    fn index(&self, index: SocketAddr) -> &RUdpSocket {
         self.get(index).expect("socket_addr {} does not exist for this server instance")
     }
 }
 
 impl IndexMut<SocketAddr> for RUdpServer {
-    fn index_mut<'a>(&'a mut self, index: SocketAddr) -> &'a mut RUdpSocket {
+    fn index_mut(&mut self, index: SocketAddr) -> &mut RUdpSocket {
         self.get_mut(index).expect("socket_addr {} does not exist for this server instance")
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+fn send_packet_to<P: AsRef<[u8]>>(udp_socket: &UdpSocket, packet: &crate::udp_packet::Packet<P>, addr: SocketAddr) {
+    udp_socket.send_to(UdpPacket::from(packet).as_bytes(), addr).unwrap();
+    // give some time for the packet to go through the loopback interface
+    ::std::thread::sleep(Duration::from_millis(10));
+}
+
+#[test]
+fn server_reconnect_after_end() {
+    use crate::udp_packet::Packet;
+
+    let mut server = RUdpServer::new("127.0.0.1:0").unwrap();
+    let server_addr = server.udp_socket().local_addr().unwrap();
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let client_addr = client.local_addr().unwrap();
+
+    send_packet_to(&client, &Packet::<Box<[u8]>>::Syn(Box::new([])), server_addr);
+    send_packet_to(&client, &Packet::<Box<[u8]>>::Heartbeat, server_addr);
+    server.next_tick().unwrap();
+    assert!(server[client_addr].status().is_connected());
+
+    send_packet_to(&client, &Packet::<Box<[u8]>>::End(0), server_addr);
+    server.next_tick().unwrap();
+    assert!(server[client_addr].status().is_finished());
+    let _events: Vec<_> = server.drain_events().collect();
+
+    // the old connection is not old enough to be cleared, but a new Syn should replace it anyway
+    send_packet_to(&client, &Packet::<Box<[u8]>>::Syn(Box::new([])), server_addr);
+    send_packet_to(&client, &Packet::<Box<[u8]>>::Heartbeat, server_addr);
+    server.next_tick().unwrap();
+    assert_eq!(server.remotes_len(), 1);
+    assert!(server[client_addr].status().is_connected());
+    let events: Vec<_> = server.drain_events().collect();
+    assert!(events.iter().any(|(addr, event)| *addr == client_addr && matches!(event, SocketEvent::Connected)));
+}
+
+#[test]
+fn server_send_raw_to() {
+    let server = RUdpServer::new("127.0.0.1:0").unwrap();
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    receiver.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+
+    server.send_raw_to(receiver.local_addr().unwrap(), b"server full").unwrap();
+
+    let mut buffer = [0u8; 32];
+    let (size, from) = receiver.recv_from(&mut buffer).unwrap();
+    assert_eq!(&buffer[..size], b"server full");
+    assert_eq!(from, server.udp_socket().local_addr().unwrap());
+    assert_eq!(server.remotes_len(), 0);
+}
+
+#[cfg(feature = "socket2")]
+#[test]
+fn server_from_socket2() {
+    use socket2::{Domain, Socket, Type};
+
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, None).unwrap();
+    socket.set_recv_buffer_size(1 << 16).unwrap();
+    socket.bind(&"127.0.0.1:0".parse::<SocketAddr>().unwrap().into()).unwrap();
+    let mut server = RUdpServer::from_socket2(socket).unwrap();
+    let mut client = crate::RUdpSocket::connect(server.udp_socket().local_addr().unwrap()).unwrap();
+    for _ in 0..50 {
+        ::std::thread::sleep(Duration::from_millis(5));
+        server.next_tick().unwrap();
+        client.next_tick().unwrap();
+    }
+    assert!(client.status().is_connected());
+    assert_eq!(server.remotes_len(), 1);
+}
+
+#[cfg(all(feature = "socket2", unix))]
+#[test]
+fn server_with_reuse_port() {
+    let first = RUdpServer::with_reuse_port("127.0.0.1:0").unwrap();
+    let addr = first.udp_socket().local_addr().unwrap();
+    let second = RUdpServer::with_reuse_port(addr).unwrap();
+    assert_eq!(second.udp_socket().local_addr().unwrap(), addr);
+    assert!(RUdpServer::new(addr).is_err());
+}
+
+#[test]
+fn server_send_budget_weights() {
+    let mut server = RUdpServer::new("127.0.0.1:0").unwrap();
+    let server_addr = server.udp_socket().local_addr().unwrap();
+    let mut clients = Vec::new();
+    for _ in 0..3 {
+        let client = crate::RUdpSocket::connect(server_addr).unwrap();
+        clients.push(client);
+    }
+    ::std::thread::sleep(Duration::from_millis(10));
+    server.next_tick().unwrap();
+    assert_eq!(server.remotes_len(), 3);
+
+    let addresses: Vec<SocketAddr> = server.addresses().cloned().collect();
+    assert!(server.set_remote_weight(addresses[0], 3));
+    server.set_send_budget(4000);
+    // the clients never tick, so these stay unacknowledged; the third remote has nothing to send
+    for addr in &addresses[..2] {
+        server.send_data_to(*addr, Arc::new([1u8; 10]), MessageType::KeyMessage, MessagePriority::Normal).unwrap().unwrap();
+    }
+    server.next_tick().unwrap();
+    assert_eq!(server[addresses[0]].send_allowance, Some(3000));
+    assert_eq!(server[addresses[1]].send_allowance, Some(1000));
+    assert_eq!(server[addresses[2]].send_allowance, Some(0));
+
+    server.remove_send_budget();
+    server.next_tick().unwrap();
+    assert_eq!(server[addresses[0]].send_allowance, None);
+}
+
+#[test]
+fn server_discard_hook() {
+    use std::sync::Mutex;
+    use crate::udp_packet::Packet;
+
+    let mut server = RUdpServer::new("127.0.0.1:0").unwrap();
+    let server_addr = server.udp_socket().local_addr().unwrap();
+    let discarded = Arc::new(Mutex::new(Vec::new()));
+    let discarded_clone = Arc::clone(&discarded);
+    server.set_discard_hook(move |addr, reason, bytes| {
+        discarded_clone.lock().unwrap().push((addr, reason, bytes.to_vec()));
+    });
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let client_addr = client.local_addr().unwrap();
+
+    // not connected yet: only a syn is accepted
+    send_packet_to(&client, &Packet::<Box<[u8]>>::Heartbeat, server_addr);
+    client.send_to(&[0u8; 16], server_addr).unwrap();
+    ::std::thread::sleep(Duration::from_millis(10));
+    server.next_tick().unwrap();
+    send_packet_to(&client, &Packet::<Box<[u8]>>::Syn(Box::new([])), server_addr);
+    send_packet_to(&client, &Packet::<Box<[u8]>>::Heartbeat, server_addr);
+    // the seq_id field of a Syn holds the payload size the remote advertises
+    let second_syn = UdpPacket::from(&Packet::Syn(Box::from(&b"again"[..]))).with_seq_id_field(700);
+    client.send_to(second_syn.as_bytes(), server_addr).unwrap();
+    ::std::thread::sleep(Duration::from_millis(10));
+    server.next_tick().unwrap();
+
+    let discarded = discarded.lock().unwrap();
+    assert_eq!(discarded.len(), 3);
+    assert_eq!(discarded[0].1, DiscardReason::UnexpectedData);
+    assert_eq!(discarded[1], (client_addr, DiscardReason::InvalidPacket(crate::UdpPacketError::InvalidCrc), vec![0u8; 16]));
+    // the datagram exactly as it was received
+    assert_eq!(discarded[2], (client_addr, DiscardReason::SynWhileConnected, second_syn.as_bytes().to_vec()));
+}
+
+#[test]
+fn server_strict_mode_kicks_garbage() {
+    use crate::udp_packet::Packet;
+
+    let mut server = RUdpServer::new("127.0.0.1:0").unwrap();
+    server.set_strict_mode(true, 3);
+    let server_addr = server.udp_socket().local_addr().unwrap();
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let client_addr = client.local_addr().unwrap();
+    send_packet_to(&client, &Packet::<Box<[u8]>>::Syn(Box::new([])), server_addr);
+    send_packet_to(&client, &Packet::<Box<[u8]>>::Heartbeat, server_addr);
+    server.next_tick().unwrap();
+    let _events: Vec<_> = server.drain_events().collect();
+
+    for _ in 0..2 {
+        client.send_to(&[0u8; 16], server_addr).unwrap();
+    }
+    ::std::thread::sleep(Duration::from_millis(10));
+    server.next_tick().unwrap();
+    assert!(server[client_addr].status().is_connected());
+    assert_eq!(server[client_addr].stats().malformed_packets, 2);
+
+    client.send_to(&[0u8; 16], server_addr).unwrap();
+    ::std::thread::sleep(Duration::from_millis(10));
+    server.next_tick().unwrap();
+    assert!(server[client_addr].status().is_finished());
+    let events: Vec<SocketEvent> = server.drain_events().map(|(_, event)| event).collect();
+    assert!(matches!(events.as_slice(), [SocketEvent::TooManyMalformedPackets]));
+}
+
+#[test]
+fn server_snapshot() {
+    let (mut server, mut client) = crate::rudp::connected_pair();
+    let client_addr = *server.addresses().next().unwrap();
+    client.send_data(Arc::new([1u8; 100]), MessageType::KeyMessage, MessagePriority::Normal).unwrap();
+    crate::rudp::tick_pair(&mut server, &mut client, 5);
+
+    let snapshot = server.snapshot();
+    assert_eq!(snapshot.len(), 1);
+    assert_eq!(snapshot[0].addr, client_addr);
+    assert!(snapshot[0].status.is_connected());
+    assert!(snapshot[0].bytes_received >= 100);
+    assert_eq!(snapshot[0].pending_sends, 0);
+    assert!(snapshot[0].loss_estimate >= 0.0 && snapshot[0].loss_estimate <= 1.0);
+}
+
+#[test]
+fn server_handshake_with_lost_synack() {
+    use crate::udp_packet::Packet;
+
+    let mut server = RUdpServer::new("127.0.0.1:0").unwrap();
+    let server_addr = server.udp_socket().local_addr().unwrap();
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+    let client_addr = client.local_addr().unwrap();
+    let mut buffer = [0u8; 2048];
+
+    // the SynAck is never read: it is lost as far as the server knows
+    send_packet_to(&client, &Packet::<Box<[u8]>>::Syn(Box::new([])), server_addr);
+    server.next_tick().unwrap();
+    assert_eq!(server[client_addr].status(), SocketStatus::SynReceived);
+    client.recv_from(&mut buffer).unwrap();
+
+    // the client tries again, and the server answers with a new SynAck
+    send_packet_to(&client, &Packet::<Box<[u8]>>::Syn(Box::new([])), server_addr);
+    server.next_tick().unwrap();
+    assert_eq!(server[client_addr].status(), SocketStatus::SynReceived);
+    let (size, _) = client.recv_from(&mut buffer).unwrap();
+    assert!(matches!(UdpPacket::new(&buffer[..size]).peek_meta(), Ok(PacketMeta::SynAck)));
+    assert!(!server.drain_events().any(|(_, event)| matches!(event, SocketEvent::Connected)));
+
+    // the client acknowledges the SynAck
+    send_packet_to(&client, &Packet::<Box<[u8]>>::Heartbeat, server_addr);
+    server.next_tick().unwrap();
+    assert!(server[client_addr].status().is_connected());
+    let events: Vec<SocketEvent> = server.drain_events().map(|(_, event)| event).collect();
+    assert!(matches!(events.as_slice(), [SocketEvent::Connected]));
+}
+
+#[test]
+fn server_multiple_listeners() {
+    let mut server = RUdpServer::new("127.0.0.1:0").unwrap();
+    let first_addr = server.udp_socket().local_addr().unwrap();
+    let second_addr = server.add_listener("127.0.0.1:0").unwrap();
+    assert_ne!(first_addr, second_addr);
+
+    // the clients drop whatever doesn't come from the address they connected to
+    let mut clients = vec![
+        crate::RUdpSocket::connect(first_addr).unwrap(),
+        crate::RUdpSocket::connect(second_addr).unwrap(),
+    ];
+    for _ in 0..50 {
+        ::std::thread::sleep(Duration::from_millis(5));
+        server.next_tick().unwrap();
+        for client in &mut clients {
+            client.next_tick().unwrap();
+        }
+        if clients.iter().all(|client| client.status().is_connected()) {
+            break;
+        }
+    }
+    assert!(clients.iter().all(|client| client.status().is_connected()));
+    assert_eq!(server.remotes_len(), 2);
+
+    server.send_data(&Arc::from(&[5u8; 10][..]), MessageType::KeyMessage, MessagePriority::Normal);
+    for _ in 0..5 {
+        ::std::thread::sleep(Duration::from_millis(5));
+        server.next_tick().unwrap();
+        for client in &mut clients {
+            client.next_tick().unwrap();
+        }
+    }
+    for client in &mut clients {
+        assert!(client.drain_events().any(|event| matches!(event, SocketEvent::Data(_))));
+    }
+}
+
+#[test]
+fn server_per_remote_reassembly_limit() {
+    use crate::udp_packet::Packet;
+    use crate::fragment::{Fragment, FragmentMeta};
+
+    let (mut server, mut client) = crate::rudp::connected_pair();
+    server.set_per_remote_reassembly_limit(8 * 1024);
+    let server_addr = server.udp_socket().local_addr().unwrap();
+    let client_addr = *server.addresses().next().unwrap();
+
+    let greedy = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let greedy_addr = greedy.local_addr().unwrap();
+    send_packet_to(&greedy, &Packet::<Box<[u8]>>::Syn(Box::new([])), server_addr);
+    send_packet_to(&greedy, &Packet::<Box<[u8]>>::Heartbeat, server_addr);
+    server.next_tick().unwrap();
+    assert!(server[greedy_addr].status().is_connected());
+
+    // the greedy remote starts many large messages, but never completes any of them
+    for seq_id in 0..20 {
+        let fragment = Fragment { seq_id, frag_id: 0, frag_total: 9, frag_meta: FragmentMeta::Key, data: Box::new([0u8; 1000]) as Box<[u8]> };
+        send_packet_to(&greedy, &Packet::Fragment(fragment), server_addr);
+    }
+    // meanwhile, a well-behaved remote sends a large message
+    client.send_data(Arc::from(&[7u8; 6000][..]), MessageType::KeyMessage, MessagePriority::Normal).unwrap();
+    crate::rudp::tick_pair(&mut server, &mut client, 5);
+
+    let events: Vec<_> = server.drain_events().collect();
+    let dropped = events.iter().filter(|(addr, event)| *addr == greedy_addr && matches!(event, SocketEvent::InboundMessageDropped { .. })).count();
+    assert!(dropped >= 12);
+    assert!(events.iter().any(|(addr, event)| *addr == client_addr && matches!(event, SocketEvent::Data(d) if d.len() == 6000)));
+    assert!(!events.iter().any(|(addr, event)| *addr == client_addr && matches!(event, SocketEvent::InboundMessageDropped { .. })));
+}
+
+#[test]
+fn server_settings_in_remote_config() {
+    let (mut server, _client) = crate::rudp::connected_pair();
+    let client_addr = *server.addresses().next().unwrap();
+    let default_config = server[client_addr].config();
+    assert_eq!(default_config.ack_mode, AckMode::Incremental);
+    assert_eq!(default_config.reassembly_limit, None);
+
+    server.set_ack_mode(AckMode::CompleteOnly);
+    server.set_per_remote_reassembly_limit(4096);
+    server.set_heartbeat_enabled(false);
+    server.set_remote_weight(client_addr, 3);
+    let config = server[client_addr].config();
+    assert_eq!(config.ack_mode, AckMode::CompleteOnly);
+    assert_eq!(config.reassembly_limit, Some(4096));
+    assert!(!config.heartbeat_enabled);
+    assert_eq!(config.send_weight, 3);
+    assert_eq!(config.timeout_delay, default_config.timeout_delay);
+}
+
+#[test]
+fn server_replaces_connection_on_reconnect() {
+    let (mut server, client) = crate::rudp::connected_pair();
+    let old_addr = *server.addresses().next().unwrap();
+
+    let mut new_client = client.reconnect().unwrap();
+    crate::rudp::tick_pair(&mut server, &mut new_client, 5);
+    assert!(new_client.status().is_connected());
+    let new_addr = new_client.local_addr();
+    let new_addr = *server.addresses().find(|addr| addr.port() == new_addr.port()).unwrap();
+    assert_ne!(new_addr, old_addr);
+
+    let events: Vec<_> = server.drain_events().collect();
+    assert!(events.iter().any(|(addr, event)| *addr == old_addr && matches!(event, SocketEvent::Replaced { by } if *by == new_addr)));
+    assert!(server[old_addr].status().is_finished());
+    assert!(server[new_addr].status().is_connected());
+}
+
+#[test]
+fn server_drain_events_grouped() {
+    let mut server = RUdpServer::new("127.0.0.1:0").unwrap();
+    let server_addr = server.udp_socket().local_addr().unwrap();
+    let mut clients = vec![
+        crate::RUdpSocket::connect(server_addr).unwrap(),
+        crate::RUdpSocket::connect(server_addr).unwrap(),
+    ];
+    for _ in 0..50 {
+        ::std::thread::sleep(Duration::from_millis(5));
+        server.next_tick().unwrap();
+        for client in &mut clients {
+            client.next_tick().unwrap();
+        }
+        if clients.iter().all(|client| client.status().is_connected()) && server.iter().all(|(_, socket)| socket.status().is_connected()) {
+            break;
+        }
+    }
+    assert!(clients.iter().all(|client| client.status().is_connected()));
+    let _events: Vec<_> = server.drain_events().collect();
+
+    for (i, client) in clients.iter_mut().enumerate() {
+        for n in 0..3u8 {
+            client.send_data(Arc::from(&[i as u8, n][..]), MessageType::KeyMessage, MessagePriority::Normal).unwrap();
+        }
+    }
+    for _ in 0..5 {
+        ::std::thread::sleep(Duration::from_millis(5));
+        server.next_tick().unwrap();
+        for client in &mut clients {
+            client.next_tick().unwrap();
+        }
+    }
+
+    let groups: Vec<_> = server.drain_events_grouped().collect();
+    assert_eq!(groups.len(), 2);
+    for (_, events) in groups {
+        let data: Vec<_> = events.iter().filter_map(|event| match event {
+            SocketEvent::Data(d) => Some(d.to_vec()),
+            _ => None,
+        }).collect();
+        assert_eq!(data.len(), 3);
+        assert!(data.iter().all(|d| d[0] == data[0][0]));
+        assert_eq!(data.iter().map(|d| d[1]).collect::<Vec<_>>(), vec![0, 1, 2]);
+    }
+    // everything has been drained
+    assert_eq!(server.drain_events_grouped().count(), 0);
+}
+
+#[test]
+fn server_remote_verify_crc() {
+    use crate::udp_packet::Packet;
+    use crate::fragment::{Fragment, FragmentMeta};
+
+    let mut server = RUdpServer::new("127.0.0.1:0").unwrap();
+    let server_addr = server.udp_socket().local_addr().unwrap();
+    let trusted = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let untrusted = UdpSocket::bind("127.0.0.1:0").unwrap();
+    for client in &[&trusted, &untrusted] {
+        send_packet_to(client, &Packet::<Box<[u8]>>::Syn(Box::new([])), server_addr);
+        send_packet_to(client, &Packet::<Box<[u8]>>::Heartbeat, server_addr);
+    }
+    server.next_tick().unwrap();
+    let trusted_addr = trusted.local_addr().unwrap();
+    let untrusted_addr = untrusted.local_addr().unwrap();
+    assert!(server.set_remote_verify_crc(trusted_addr, false));
+    assert!(!server[trusted_addr].config().verify_crc);
+    let _events: Vec<_> = server.drain_events().collect();
+
+    let fragment = Fragment { seq_id: 1, frag_id: 0, frag_total: 0, frag_meta: FragmentMeta::Key, data: Box::from(&b"hello"[..]) };
+    let mut bytes = UdpPacket::from(&Packet::Fragment(fragment)).as_bytes().to_vec();
+    bytes[0] ^= 0xFF;
+    for client in &[&trusted, &untrusted] {
+        client.send_to(&bytes, server_addr).unwrap();
+    }
+    ::std::thread::sleep(Duration::from_millis(10));
+    server.next_tick().unwrap();
+
+    let events: Vec<_> = server.drain_events().collect();
+    assert!(events.iter().any(|(addr, event)| *addr == trusted_addr && matches!(event, SocketEvent::Data(d) if d.as_ref() == b"hello")));
+    assert!(!events.iter().any(|(addr, event)| *addr == untrusted_addr && matches!(event, SocketEvent::Data(_))));
+    assert_eq!(server[untrusted_addr].stats().malformed_packets, 1);
+}
+
+#[test]
+fn server_recv_burst_stats() {
+    use crate::udp_packet::Packet;
+
+    let mut server = RUdpServer::new("127.0.0.1:0").unwrap();
+    let server_addr = server.udp_socket().local_addr().unwrap();
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    send_packet_to(&client, &Packet::<Box<[u8]>>::Syn(Box::new([])), server_addr);
+    server.next_tick().unwrap();
+    server.reset_recv_burst_stats();
+
+    for _ in 0..5 {
+        send_packet_to(&client, &Packet::<Box<[u8]>>::Heartbeat, server_addr);
+    }
+    server.next_tick().unwrap();
+    server.next_tick().unwrap();
+    let stats = server.recv_burst_stats();
+    assert_eq!(stats.max, 5);
+    assert!((stats.avg - 2.5).abs() < 1e-9);
+    assert_eq!(stats.p99, 5);
+}
+
+#[test]
+fn server_reassembly_buffered_bytes() {
+    use crate::udp_packet::Packet;
+    use crate::fragment::{Fragment, FragmentMeta};
+
+    let mut server = RUdpServer::new("127.0.0.1:0").unwrap();
+    let server_addr = server.udp_socket().local_addr().unwrap();
+    let clients = [UdpSocket::bind("127.0.0.1:0").unwrap(), UdpSocket::bind("127.0.0.1:0").unwrap()];
+    for (i, client) in clients.iter().enumerate() {
+        send_packet_to(client, &Packet::<Box<[u8]>>::Syn(Box::new([])), server_addr);
+        send_packet_to(client, &Packet::<Box<[u8]>>::Heartbeat, server_addr);
+        // the first fragment of a message of 3
+        let fragment = Fragment { seq_id: 0, frag_id: 0, frag_total: 2, frag_meta: FragmentMeta::Key, data: vec![0u8; 100 * (i + 1)] };
+        send_packet_to(client, &Packet::Fragment(fragment), server_addr);
+    }
+    server.next_tick().unwrap();
+    assert_eq!(server[clients[0].local_addr().unwrap()].reassembly_buffered_bytes(), 100);
+    assert_eq!(server.reassembly_buffered_bytes(), 300);
+
+    let fragment = Fragment { seq_id: 0, frag_id: 1, frag_total: 2, frag_meta: FragmentMeta::Key, data: vec![0u8; 100] };
+    send_packet_to(&clients[0], &Packet::Fragment(fragment), server_addr);
+    let fragment = Fragment { seq_id: 0, frag_id: 2, frag_total: 2, frag_meta: FragmentMeta::Key, data: vec![0u8; 100] };
+    send_packet_to(&clients[0], &Packet::Fragment(fragment), server_addr);
+    server.next_tick().unwrap();
+    // the message of the first client is complete
+    assert_eq!(server.reassembly_buffered_bytes(), 200);
+}
+
+#[test]
+fn server_send_data_returns_seq_ids() {
+    let (mut server, mut client) = crate::rudp::connected_pair();
+    let first = server.send_data(&Arc::from(&b"hello"[..]), MessageType::KeyMessage, MessagePriority::Highest);
+    let second = server.send_data(&Arc::from(&b"world"[..]), MessageType::KeyMessage, MessagePriority::Highest);
+    assert_eq!(first.len(), 1);
+    let client_addr = first[0].0;
+    assert_eq!(second[0].0, client_addr);
+    let (first_seq_id, second_seq_id) = (first[0].1.unwrap(), second[0].1.unwrap());
+    assert_eq!(second_seq_id, first_seq_id + 1);
+    crate::rudp::tick_pair(&mut server, &mut client, 10);
+    assert_eq!(server.get(client_addr).unwrap().is_seq_id_received(second_seq_id), Ok(true));
+}
+
+#[test]
+fn server_send_data_to_single_remote() {
+    let (mut server, mut first_client) = crate::rudp::connected_pair();
+    let first_addr = *server.addresses().next().unwrap();
+    let mut second_client = crate::RUdpSocket::connect(server.udp_socket().local_addr().unwrap()).unwrap();
+    crate::rudp::tick_pair(&mut server, &mut second_client, 10);
+    assert!(second_client.status().is_connected());
+    assert_eq!(server.remotes_len(), 2);
+    let _events: Vec<_> = server.drain_events().collect();
+
+    let unknown_addr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+    assert!(server.send_data_to(unknown_addr, Arc::from(&b"nobody"[..]), MessageType::KeyMessage, MessagePriority::Highest).is_none());
+    let seq_id = server.send_data_to(first_addr, Arc::from(&b"only you"[..]), MessageType::KeyMessage, MessagePriority::Highest);
+    assert!(matches!(seq_id, Some(Ok(_))));
+    for _ in 0..10 {
+        ::std::thread::sleep(Duration::from_millis(5));
+        server.next_tick().unwrap();
+        first_client.next_tick().unwrap();
+        second_client.next_tick().unwrap();
+    }
+    assert!(first_client.drain_events().any(|event| matches!(event, SocketEvent::Data(d) if d.as_ref() == b"only you")));
+    assert!(!second_client.drain_events().any(|event| matches!(event, SocketEvent::Data(_))));
+}
+
+#[test]
+fn server_send_data_except_one_remote() {
+    let (mut server, mut first_client) = crate::rudp::connected_pair();
+    let first_addr = *server.addresses().next().unwrap();
+    let mut second_client = crate::RUdpSocket::connect(server.udp_socket().local_addr().unwrap()).unwrap();
+    crate::rudp::tick_pair(&mut server, &mut second_client, 10);
+    assert!(second_client.status().is_connected());
+    let _events: Vec<_> = server.drain_events().collect();
+
+    let sent = server.send_data_except(first_addr, &Arc::from(&b"relayed"[..]), MessageType::KeyMessage, MessagePriority::Highest);
+    assert_eq!(sent.len(), 1);
+    assert_ne!(sent[0].0, first_addr);
+    assert!(sent[0].1.is_ok());
+    for _ in 0..10 {
+        ::std::thread::sleep(Duration::from_millis(5));
+        server.next_tick().unwrap();
+        first_client.next_tick().unwrap();
+        second_client.next_tick().unwrap();
+    }
+    assert!(!first_client.drain_events().any(|event| matches!(event, SocketEvent::Data(_))));
+    assert!(second_client.drain_events().any(|event| matches!(event, SocketEvent::Data(d) if d.as_ref() == b"relayed")));
+}
+
+#[test]
+fn server_connection_filter() {
+    let mut server = RUdpServer::new("127.0.0.1:0").unwrap();
+    let server_addr = server.udp_socket().local_addr().unwrap();
+    let mut allowed = crate::RUdpSocket::connect(server_addr).unwrap();
+    let allowed_port = allowed.local_addr().port();
+    server.set_connection_filter(move |addr| addr.port() == allowed_port);
+    let discarded = Arc::new(::std::sync::Mutex::new(Vec::new()));
+    let discarded_clone = Arc::clone(&discarded);
+    server.set_discard_hook(move |addr, reason, _bytes| discarded_clone.lock().unwrap().push((addr, reason)));
+    let mut refused = crate::RUdpSocket::connect(server_addr).unwrap();
+    for _ in 0..10 {
+        ::std::thread::sleep(Duration::from_millis(5));
+        server.next_tick().unwrap();
+        allowed.next_tick().unwrap();
+        refused.next_tick().unwrap();
+    }
+    assert!(allowed.status().is_connected());
+    assert!(!refused.status().is_connected());
+    assert_eq!(server.remotes_len(), 1);
+    let refused_port = refused.local_addr().port();
+    assert!(discarded.lock().unwrap().iter().any(|(addr, reason)| addr.port() == refused_port && *reason == DiscardReason::ConnectionRefused));
+}
+
+#[test]
+fn server_connection_filter_refuses_rejoin() {
+    let (mut server, client) = crate::rudp::connected_pair();
+    let old_addr = *server.addresses().next().unwrap();
+    server.set_connection_filter(move |addr| addr == old_addr);
+
+    let mut new_client = client.reconnect().unwrap();
+    crate::rudp::tick_pair(&mut server, &mut new_client, 5);
+    assert!(!new_client.status().is_connected());
+    assert_eq!(server.remotes_len(), 1);
+    // the old connection is left alone
+    assert!(!server.drain_events().any(|(_, event)| matches!(event, SocketEvent::Replaced { .. })));
+    assert!(server[old_addr].status().is_connected());
+}
+
+#[test]
+fn server_max_remotes_accepts_rejoin() {
+    let (mut server, client) = crate::rudp::connected_pair();
+    let old_addr = *server.addresses().next().unwrap();
+    server.set_max_remotes(Some(1));
+
+    let mut new_client = client.reconnect().unwrap();
+    crate::rudp::tick_pair(&mut server, &mut new_client, 5);
+    assert!(new_client.status().is_connected());
+    assert!(server[old_addr].status().is_finished());
+}
+
+#[test]
+fn server_max_remotes() {
+    use crate::udp_packet::Packet;
+
+    let mut server = RUdpServer::new("127.0.0.1:0").unwrap();
+    let server_addr = server.udp_socket().local_addr().unwrap();
+    server.set_max_remotes(Some(10));
+    let syn: Packet<Box<[u8]>> = Packet::Syn(Box::from(&[][..]));
+    let senders: Vec<UdpSocket> = (0..100).map(|_| UdpSocket::bind("127.0.0.1:0").unwrap()).collect();
+    for sender in &senders {
+        send_packet_to(sender, &syn, server_addr);
+    }
+    for _ in 0..5 {
+        ::std::thread::sleep(Duration::from_millis(5));
+        server.next_tick().unwrap();
+    }
+    assert_eq!(server.remotes_len(), 10);
+
+    // finished remotes leave room for new ones
+    let connected = senders.iter().find(|sender| server.get(sender.local_addr().unwrap()).is_some()).unwrap();
+    send_packet_to(connected, &Packet::<Box<[u8]>>::End(0), server_addr);
+    ::std::thread::sleep(Duration::from_millis(5));
+    server.next_tick().unwrap();
+    assert!(server.get(connected.local_addr().unwrap()).unwrap().status().is_finished());
+    let late_sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    send_packet_to(&late_sender, &syn, server_addr);
+    ::std::thread::sleep(Duration::from_millis(5));
+    server.next_tick().unwrap();
+    assert!(server.get(late_sender.local_addr().unwrap()).is_some());
+}
+
+#[test]
+fn detach_moves_remote_to_own_socket() {
+    let (mut server, mut client) = crate::rudp::connected_pair();
+    let client_addr = *server.addresses().next().unwrap();
+    let server_addr = server.udp_socket().local_addr().unwrap();
+    let mut detached = server.detach(client_addr).unwrap().unwrap();
+    assert_eq!(server.remotes_len(), 0);
+    assert_ne!(detached.local_addr(), server_addr);
+    for _ in 0..3 {
+        ::std::thread::sleep(Duration::from_millis(5));
+        detached.next_tick().unwrap();
+        client.next_tick().unwrap();
+    }
+    assert_eq!(client.remote_addr(), detached.local_addr());
+    assert!(matches!(client.drain_events().next(), Some(SocketEvent::Migrated { from }) if from == server_addr));
+
+    client.send_data(Arc::new([5u8; 10]), MessageType::KeyMessage, MessagePriority::Normal).unwrap();
+    detached.send_data(Arc::new([6u8; 10]), MessageType::KeyMessage, MessagePriority::Normal).unwrap();
+    for _ in 0..3 {
+        ::std::thread::sleep(Duration::from_millis(5));
+        detached.next_tick().unwrap();
+        client.next_tick().unwrap();
+    }
+    assert!(matches!(detached.drain_events().next(), Some(SocketEvent::Data(_))));
+    assert!(matches!(client.drain_events().next(), Some(SocketEvent::Data(_))));
+    assert!(detached.status().is_connected() && client.status().is_connected());
+}
+
+#[test]
+fn server_pre_handshake_data_policy() {
+    use crate::fragment::{Fragment, FragmentMeta};
+    use crate::udp_packet::Packet;
+
+    for policy in [PreHandshakeDataPolicy::Reject, PreHandshakeDataPolicy::Buffer].iter() {
+        let mut server = RUdpServer::new("127.0.0.1:0").unwrap();
+        server.set_pre_handshake_data_policy(*policy);
+        let server_addr = server.udp_socket().local_addr().unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let fragment = Fragment { seq_id: 0, frag_id: 0, frag_total: 0, frag_meta: FragmentMeta::Forgettable, data: Box::<[u8]>::from(&[1u8, 2, 3][..]) };
+        client.send_to(UdpPacket::from(&fragment).as_bytes(), server_addr).unwrap();
+        ::std::thread::sleep(Duration::from_millis(10));
+        server.next_tick().unwrap();
+        send_packet_to(&client, &Packet::<Box<[u8]>>::Syn(Box::new([])), server_addr);
+        send_packet_to(&client, &Packet::<Box<[u8]>>::Heartbeat, server_addr);
+        server.next_tick().unwrap();
+
+        let received: Vec<_> = server.drain_events()
+            .filter(|(_, event)| matches!(event, SocketEvent::Data(data) if data[..] == [1, 2, 3]))
+            .collect();
+        assert_eq!(received.len(), if *policy == PreHandshakeDataPolicy::Buffer { 1 } else { 0 });
+    }
+}
+
+#[test]
+fn server_remotes_with_work() {
+    use crate::rudp::{connected_pair, tick_pair};
+
+    let (mut server, mut client) = connected_pair();
+    tick_pair(&mut server, &mut client, 2);
+    let _events: Vec<_> = server.drain_events().collect();
+    assert_eq!(server.remotes_with_work().count(), 0);
+
+    client.send_data(Arc::new([1u8; 10]), MessageType::KeyMessage, MessagePriority::Normal).unwrap();
+    ::std::thread::sleep(Duration::from_millis(10));
+    server.next_tick().unwrap();
+    // the message waits to be drained
+    let client_addr = *server.addresses().next().unwrap();
+    assert_eq!(server.remotes_with_work().collect::<Vec<_>>(), vec![&client_addr]);
+    let _events: Vec<_> = server.drain_events().collect();
+    assert_eq!(server.remotes_with_work().count(), 0);
+
+    // a heartbeat is due
+    server.set_heartbeat(Duration::from_millis(20));
+    server.next_tick().unwrap();
+    assert_eq!(server.remotes_with_work().count(), 0);
+    ::std::thread::sleep(Duration::from_millis(30));
+    assert_eq!(server.remotes_with_work().collect::<Vec<_>>(), vec![&client_addr]);
+}
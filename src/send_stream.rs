@@ -0,0 +1,116 @@
+use crate::rudp::{RUdpSocket, MessageType, MessagePriority};
+use crate::fragment::MAX_FRAGMENT_MESSAGE_SIZE;
+use crate::consts::MAX_MESSAGE_SIZE;
+use std::sync::Arc;
+
+/// Sends data as it is produced, without having to hold all of it first. See `RUdpSocket::open_stream`.
+///
+/// The data is cut into chunks, each one sent as its own message as soon as it is full.
+/// The remote receives them as a sequence of `Data` events with consecutive seq_ids:
+/// they are not re-assembled into a single message, and like any other messages they may
+/// arrive out of order. Use `ReceivedData::seq_id` to put them back in order.
+///
+/// What has been written but not sent yet is sent by `finish`, or when the stream is dropped.
+#[derive(Debug)]
+pub struct SendStream<'a> {
+    socket: &'a mut RUdpSocket,
+    message_type: MessageType,
+    message_priority: MessagePriority,
+    chunk_size: usize,
+    buffer: Vec<u8>,
+    seq_ids: Vec<u32>,
+}
+
+impl<'a> SendStream<'a> {
+    pub (crate) fn new(socket: &'a mut RUdpSocket, message_type: MessageType, message_priority: MessagePriority) -> SendStream<'a> {
+        SendStream {
+            socket,
+            message_type,
+            message_priority,
+            chunk_size: MAX_FRAGMENT_MESSAGE_SIZE,
+            buffer: Vec::with_capacity(MAX_FRAGMENT_MESSAGE_SIZE),
+            seq_ids: Vec::new(),
+        }
+    }
+
+    /// Set the size of the messages the data is cut into. The default is the size of a single fragment,
+    /// so that every fragment is sent as soon as it is full.
+    ///
+    /// Larger chunks mean fewer messages for the remote to put back in order, but data waits longer before
+    /// being sent. The size is capped to the size of the largest possible message, see `RUdpSocket::max_message_size`.
+    pub fn set_chunk_size(&mut self, chunk_size: usize) {
+        self.chunk_size = chunk_size.clamp(1, MAX_MESSAGE_SIZE);
+        self.send_full_chunks();
+    }
+
+    /// Append data to the stream. Every chunk that is full is sent right away.
+    pub fn write(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+        self.send_full_chunks();
+    }
+
+    /// Sends what is left, and returns the seq_ids of all the messages sent by this stream, in order.
+    pub fn finish(mut self) -> Vec<u32> {
+        self.send_rest();
+        ::std::mem::take(&mut self.seq_ids)
+    }
+
+    fn send_full_chunks(&mut self) {
+        while self.buffer.len() >= self.chunk_size {
+            let rest = self.buffer.split_off(self.chunk_size);
+            let chunk = ::std::mem::replace(&mut self.buffer, rest);
+            self.send_chunk(chunk);
+        }
+    }
+
+    fn send_rest(&mut self) {
+        if !self.buffer.is_empty() {
+            let chunk = ::std::mem::take(&mut self.buffer);
+            self.send_chunk(chunk);
+        }
+    }
+
+    fn send_chunk(&mut self, chunk: Vec<u8>) {
+        // the largest message may be smaller than the chunk size, if the payload size was negotiated lower
+        let max_message_size = self.socket.max_message_size();
+        for part in chunk.chunks(max_message_size) {
+            let seq_id = self.socket.send_data(Arc::from(part), self.message_type, self.message_priority)
+                .expect("parts are never larger than the largest message");
+            self.seq_ids.push(seq_id);
+        }
+    }
+}
+
+impl Drop for SendStream<'_> {
+    fn drop(&mut self) {
+        self.send_rest();
+    }
+}
+
+#[test]
+fn send_stream_in_chunks() {
+    use crate::rudp::{connected_pair, tick_pair, SocketEvent};
+
+    let (mut server, mut client) = connected_pair();
+    let data: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+    let seq_ids = {
+        let mut stream = client.open_stream(MessageType::KeyMessage, MessagePriority::Normal);
+        stream.set_chunk_size(2000);
+        for piece in data.chunks(300) {
+            stream.write(piece);
+        }
+        stream.finish()
+    };
+    assert_eq!(seq_ids.len(), 3);
+    tick_pair(&mut server, &mut client, 5);
+
+    let mut chunks: Vec<(u32, Box<[u8]>)> = server.drain_events().filter_map(|(_, event)| match event {
+        SocketEvent::Data(d) => Some((d.seq_id, d.data)),
+        _ => None,
+    }).collect();
+    chunks.sort_by_key(|(seq_id, _)| *seq_id);
+    assert_eq!(chunks.iter().map(|(seq_id, _)| *seq_id).collect::<Vec<_>>(), seq_ids);
+    assert_eq!(chunks.iter().map(|(_, chunk)| chunk.len()).collect::<Vec<_>>(), vec![2000, 2000, 1000]);
+    let received: Vec<u8> = chunks.into_iter().flat_map(|(_, chunk)| chunk.into_vec()).collect();
+    assert_eq!(received, data);
+}
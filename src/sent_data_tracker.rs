@@ -1,12 +1,15 @@
 use hashbrown::HashMap;
 use crate::rudp::UdpSocketWrapper;
-use crate::fragment::{build_fragments_from_bytes, FragmentMeta};
+use crate::fragment::{build_fragments_from_bytes, Fragment, FragmentMeta, FragmentSizing, MAX_FRAGMENT_MESSAGE_SIZE};
 use crate::udp_packet::UdpPacket;
 use crate::ack::Ack;
-use crate::rudp::{MessageType, MessagePriority};
+use crate::rudp::{MessageType, MessagePriority, SendError};
 use crate::misc::BoxedSlice;
-use crate::consts::SEQ_DATA_CLEANUP_DELAY;
-use std::time::Instant;
+use crate::consts::{SEQ_DATA_CLEANUP_DELAY, MAX_BLOCKED_SEND_RETRIES, FRAG_DATA_START_BYTE, MAX_FRAGMENTS_IN_MESSAGE};
+use crate::consts::{CONGESTION_SAMPLE_SIZE, CONGESTION_LOSS_THRESHOLD, CONGESTION_BACKOFF, CONGESTION_RECOVERY_STEP, MAX_CONGESTION_FACTOR};
+use std::collections::VecDeque;
+use std::io::ErrorKind as IoErrorKind;
+use std::time::{Duration, Instant};
 
 #[cfg(feature = "extended_debug")]
 use hex::encode as hex_encode;
@@ -16,7 +19,15 @@ pub (crate) enum PacketExpiration {
     Key,
     ExpirableKey {
         expiration: Instant,
-    }
+    },
+    /// Same as ExpirableKey, but expiring is an expected outcome
+    TryReliable {
+        expiration: Instant,
+    },
+    /// Never re-sent, only tracked to know whether the remote received it
+    AckOnly {
+        expiration: Instant,
+    },
 }
 
 impl From<Option<PacketExpiration>> for FragmentMeta {
@@ -25,23 +36,78 @@ impl From<Option<PacketExpiration>> for FragmentMeta {
             None => FragmentMeta::Forgettable,
             Some(PacketExpiration::Key) => FragmentMeta::Key,
             Some(PacketExpiration::ExpirableKey { .. }) => FragmentMeta::KeyExpirable,
+            Some(PacketExpiration::TryReliable { .. }) => FragmentMeta::TryReliable,
+            Some(PacketExpiration::AckOnly { .. }) => FragmentMeta::ForgettableAcked,
         }
     }
 }
 
 impl PacketExpiration {
-    fn from_message_type(message_type: MessageType, now: Instant) -> Option<PacketExpiration> {
+    fn from_message_type(message_type: MessageType, now: Instant, cleanup_delay: Duration) -> Option<PacketExpiration> {
         match message_type {
             MessageType::Forgettable => None,
             MessageType::KeyExpirableMessage(v) => Some(PacketExpiration::ExpirableKey {
                 expiration: now + v,
             }),
+            MessageType::TryReliable(v) => Some(PacketExpiration::TryReliable {
+                expiration: now + v,
+            }),
             MessageType::KeyMessage => Some(PacketExpiration::Key),
+            // past this delay, an ack is very unlikely to come anymore
+            MessageType::ForgettableWithAck => Some(PacketExpiration::AckOnly {
+                expiration: now + cleanup_delay,
+            }),
         }
     }
 }
 
-pub (self) struct SentDataSet<D: AsRef<[u8]> + 'static + Clone> {
+/// Outcome of a message sent with `send_data_with_callback`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryResult {
+    /// The remote acknowledged every fragment of the message
+    Delivered,
+    /// The message expired before the remote acknowledged all of it
+    Expired,
+    /// Same as `Expired`, for a `TryReliable` message: giving up was an acceptable outcome
+    GaveUp,
+    /// The connection ended before the remote acknowledged all of it
+    Failed,
+    /// The message was cancelled with `cancel_all_pending` before the remote acknowledged all of it
+    Cancelled,
+}
+
+/// Where a message sent earlier stands, see `RUdpSocket::delivery_status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryStatus {
+    /// The message is not tracked: it was never sent, it is `Forgettable`, or it was forgotten
+    /// some time after it was acknowledged or expired
+    Unknown,
+    /// The remote has not acknowledged every fragment of the message yet
+    Pending,
+    /// The remote acknowledged every fragment of the message
+    Acknowledged,
+}
+
+/// Called once with the outcome of a message.
+pub (crate) struct DeliveryCallback(Box<dyn FnOnce(DeliveryResult) + Send>);
+
+impl DeliveryCallback {
+    pub fn new<F: FnOnce(DeliveryResult) + Send + 'static>(f: F) -> DeliveryCallback {
+        DeliveryCallback(Box::new(f))
+    }
+
+    pub fn call(self, result: DeliveryResult) {
+        (self.0)(result)
+    }
+}
+
+impl ::std::fmt::Debug for DeliveryCallback {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "DeliveryCallback")
+    }
+}
+
+struct SentDataSet<D: AsRef<[u8]> + 'static + Clone> {
     pub (self) data: D,
     pub (self) frag_total: u8,
     pub (self) expiration_type: PacketExpiration,
@@ -53,6 +119,15 @@ pub (self) struct SentDataSet<D: AsRef<[u8]> + 'static + Clone> {
     /// (Oldest unanswered ack, Newest unanswered ack)
     pub (self) unanswered_ack: Option<(Instant, Instant)>,
     pub (self) message_priority: MessagePriority,
+    pub (self) callback: Option<DeliveryCallback>,
+    /// Resent fragments must be cut exactly like the first ones
+    pub (self) layout: FragmentLayout,
+    /// Number of fragments the remote was missing when we last sent this set
+    pub (self) remaining_at_last_send: usize,
+    /// Number of times in a row this set was resent without the remote acknowledging any new fragment
+    pub (self) resends_without_progress: u32,
+    /// Last time the remote acknowledged a new fragment of this set, or when it was first sent
+    pub (self) last_progress: Instant,
 }
 
 #[cfg(feature = "extended_debug")]
@@ -84,7 +159,7 @@ impl<D: AsRef<[u8]> + 'static + Clone> ::std::fmt::Debug for SentDataSet<D> {
 }
 
 impl<D: AsRef<[u8]> + 'static + Clone> SentDataSet<D> {
-    pub fn new(data: D, frag_total: u8, now: Instant, expiration_type: PacketExpiration, message_priority: MessagePriority) -> SentDataSet<D> {
+    pub fn new(data: D, frag_total: u8, now: Instant, expiration_type: PacketExpiration, message_priority: MessagePriority, layout: FragmentLayout) -> SentDataSet<D> {
         SentDataSet {
             data,
             frag_total,
@@ -94,6 +169,11 @@ impl<D: AsRef<[u8]> + 'static + Clone> SentDataSet<D> {
             unanswered_ack: None,
             complete_since: None,
             message_priority,
+            callback: None,
+            layout,
+            remaining_at_last_send: frag_total as usize + 1,
+            resends_without_progress: 0,
+            last_progress: now,
         }
     }
 
@@ -101,29 +181,89 @@ impl<D: AsRef<[u8]> + 'static + Clone> SentDataSet<D> {
     ///
     /// None means the remote has not received the message yet (as of what we know)
     /// Some(instant) is the time when the first complete ack has been received
-    pub (self) fn attempt_resend_packets(&mut self, seq_id: u32, now: Instant, socket: &UdpSocketWrapper) -> Option<Instant> {
-        let resend_delay = self.message_priority.resend_delay();
-        if now >= self.last_sent_packet + resend_delay {
+    ///
+    /// `allowance` is the number of bytes we may still send this tick. If resending would go over it,
+    /// nothing is sent and `deferred` is set to true: we will try again next tick.
+    pub (self) fn attempt_resend_packets(&mut self, seq_id: u32, now: Instant, socket: &UdpSocketWrapper, allowance: &mut usize, deferred: &mut bool, congestion_factor: f64) -> Option<Instant> {
+        if now >= self.next_resend(congestion_factor) {
+            let resend_size = self.resend_size();
+            if resend_size > *allowance {
+                *deferred = true;
+                return None;
+            }
+            *allowance -= resend_size;
             self.resend_packets(seq_id, now, socket)
         } else {
-            if let Some((old, new)) = self.unanswered_ack {
-                // if we have received an unanswered ack 80% of resend_delay ago,
-                // OR if we have NOT received an ack for 60% of resend_delay, resend the packets
-                if now >= old + resend_delay * 4 / 5 || now - new >= resend_delay * 3 / 5 {
-                    self.resend_packets(seq_id, now, socket)
-                } else {
-                    None
-                }
-            } else {
-                None
-            }
+            None
+        }
+    }
+
+    /// Returns roughly how many bytes the next resend of this set will weigh.
+    pub (self) fn resend_size(&self) -> usize {
+        let remaining_fragments = self.remaining_fragments();
+        let upper_bound = remaining_fragments * (FRAG_DATA_START_BYTE + self.layout.max_fragment_size);
+        let padded_size = remaining_fragments * (FRAG_DATA_START_BYTE + self.layout.min_payload_size);
+        upper_bound.min((self.data.as_ref().len() + remaining_fragments * FRAG_DATA_START_BYTE).max(padded_size))
+    }
+
+    /// Returns the time at which the missing fragments of this set will be sent again.
+    ///
+    /// The resend delay of the priority is stretched by `congestion_factor`, see `SentDataTracker::congestion_factor`.
+    pub (self) fn next_resend(&self, congestion_factor: f64) -> Instant {
+        let resend_delay = self.message_priority.resend_delay().mul_f64(congestion_factor);
+        let next_resend = self.last_sent_packet + resend_delay;
+        if let Some((old, new)) = self.unanswered_ack {
+            // if we have received an unanswered ack 80% of resend_delay ago,
+            // OR if we have NOT received an ack for 60% of resend_delay, resend the packets
+            next_resend
+                .min(old + resend_delay * 4 / 5)
+                .min(new + resend_delay * 3 / 5)
+        } else {
+            next_resend
+        }
+    }
+
+    /// Returns the delay between the last time this set was sent and the next time it will be sent again.
+    ///
+    /// This is the resend delay of the priority, unless recent acks make us resend sooner or congestion later.
+    pub (self) fn effective_resend_delay(&self, congestion_factor: f64) -> Duration {
+        self.next_resend(congestion_factor).saturating_duration_since(self.last_sent_packet)
+    }
+
+    /// Returns the number of fragments the remote has not acknowledged yet, as of what we know.
+    pub (self) fn remaining_fragments(&self) -> usize {
+        match &self.last_received_ack {
+            Some((_, ack)) => ack.missing_iter(self.frag_total).count(),
+            None => self.frag_total as usize + 1,
+        }
+    }
+
+    #[inline]
+    pub (self) fn is_resent(&self) -> bool {
+        !matches!(self.expiration_type, PacketExpiration::AckOnly { .. })
+    }
+
+    /// Returns true if the remote has yet to acknowledge some fragments of this set.
+    ///
+    /// `complete_since` is only set on the next tick, the last ack received tells us earlier.
+    #[inline]
+    pub (self) fn is_pending(&self) -> bool {
+        self.is_resent() && self.complete_since.is_none() && self.complete_ack_received().is_none()
+    }
+
+    /// Returns when the remote acknowledged every fragment of this set, as of what we know.
+    pub (self) fn complete_ack_received(&self) -> Option<Instant> {
+        match &self.last_received_ack {
+            Some((ack_received, ack)) if ack.missing_iter(self.frag_total).next().is_none() => Some(*ack_received),
+            _ => None,
         }
     }
 
     #[inline]
     pub fn is_expired(&self, now: Instant) -> bool {
         match self.expiration_type {
-            PacketExpiration::ExpirableKey { expiration } =>
+            PacketExpiration::ExpirableKey { expiration } | PacketExpiration::TryReliable { expiration }
+                | PacketExpiration::AckOnly { expiration } =>
                 now > expiration,
             _ => false,
         }
@@ -131,8 +271,16 @@ impl<D: AsRef<[u8]> + 'static + Clone> SentDataSet<D> {
 
     /// Returns whether or not all acks have been received by the other party
     pub (self) fn resend_packets(&mut self, seq_id: u32, now: Instant, socket: &UdpSocketWrapper) -> Option<Instant> {
+        let remaining_fragments = self.remaining_fragments();
+        if remaining_fragments < self.remaining_at_last_send {
+            self.resends_without_progress = 0;
+            self.last_progress = now;
+        } else {
+            self.resends_without_progress += 1;
+        }
+        self.remaining_at_last_send = remaining_fragments;
         let frag_meta = FragmentMeta::from(Some(self.expiration_type));
-        let (fragments, frag_total) = build_fragments_from_bytes(self.data.as_ref(), seq_id, frag_meta).expect("Unreachable: message has been sent once but couldn't be resent because too big");
+        let (fragments, frag_total) = build_fragments_from_bytes(self.data.as_ref(), seq_id, frag_meta, self.layout.sizing, self.layout.max_fragment_size).expect("Unreachable: message has been sent once but couldn't be resent because too big");
         
         let mut last_complete_ack: Option<Instant> = None;
         match &self.last_received_ack {
@@ -149,8 +297,9 @@ impl<D: AsRef<[u8]> + 'static + Clone> SentDataSet<D> {
                     complete = false;
                     let fragment = &all_fragments[frag_id as usize];
                     log::trace!("resending seq_id={} frag_id={} because we received incomplete ack", seq_id, frag_id);
-                    let _r = socket.send_udp_packet(&UdpPacket::from(fragment));
+                    let _r = socket.send_udp_packet(&self.layout.udp_packet(fragment, self.message_priority));
                     // TODO log the error if any
+                    socket.update_stats(|stats| stats.fragments_resent += 1);
                 }
                 if complete {
                     last_complete_ack = Some(*ack_received_instant);
@@ -160,8 +309,9 @@ impl<D: AsRef<[u8]> + 'static + Clone> SentDataSet<D> {
                 // no ack has been received, resend everything we have
                 for fragment in fragments {
                     log::trace!("resending seq_id={} frag_id={} because we received no ack", seq_id, fragment.frag_id);
-                    let _r = socket.send_udp_packet(&UdpPacket::from(&fragment));
+                    let _r = socket.send_udp_packet(&self.layout.udp_packet(&fragment, self.message_priority));
                     // TODO log the error if any
+                    socket.update_stats(|stats| stats.fragments_resent += 1);
                 }
 
                 // obviously no acks have been received, so this set can't be complete, so don't set "last_received_ack"
@@ -173,33 +323,272 @@ impl<D: AsRef<[u8]> + 'static + Clone> SentDataSet<D> {
     } 
 }
 
+/// Describes a message that has been sent, but that the remote has not fully acknowledged yet.
+#[derive(Debug, Clone, Copy)]
+pub struct PendingInfo {
+    pub seq_id: u32,
+    pub priority: MessagePriority,
+    /// When the missing fragments of this message will be sent again
+    pub next_resend: Instant,
+    /// Number of fragments the remote has not acknowledged yet
+    pub remaining_fragments: usize,
+    /// Total number of fragments of this message
+    pub total_fragments: usize,
+}
+
+/// How the messages are cut into fragments and put on the wire.
+#[derive(Debug, Clone, Copy)]
+pub (crate) struct FragmentLayout {
+    pub (crate) sizing: FragmentSizing,
+    /// Maximum size of the data of a fragment, see `RUdpSocket::negotiated_payload_size`
+    pub (crate) max_fragment_size: usize,
+    /// Fragments with less data than that are padded, see `RUdpSocket::set_min_payload_size`
+    pub (crate) min_payload_size: usize,
+    /// Fragments carry the priority of their message, see `RUdpSocket::set_send_priority_info`
+    pub (crate) send_priority: bool,
+}
+
+impl Default for FragmentLayout {
+    fn default() -> FragmentLayout {
+        FragmentLayout {
+            sizing: FragmentSizing::default(),
+            max_fragment_size: MAX_FRAGMENT_MESSAGE_SIZE,
+            min_payload_size: 0,
+            send_priority: false,
+        }
+    }
+}
+
+impl FragmentLayout {
+    /// Builds the packet sending `fragment`, padded and carrying `priority` if this layout says so.
+    pub (crate) fn udp_packet<T: AsRef<[u8]>>(&self, fragment: &Fragment<T>, priority: MessagePriority) -> UdpPacket<Box<[u8]>> {
+        let udp_packet = UdpPacket::padded_fragment(fragment, self.min_payload_size);
+        if self.send_priority {
+            udp_packet.with_frag_priority(priority.wire_value())
+        } else {
+            udp_packet
+        }
+    }
+}
+
 #[derive(Debug)]
 pub (crate) struct SentDataTracker<D: AsRef<[u8]> + 'static + Clone> {
     pub (self) sets: HashMap<u32, SentDataSet<D>>,
+    /// Fragments that could not be sent the first time because the local send buffer was full,
+    /// along with the number of times we tried to send them again.
+    pub (self) blocked_fragments: VecDeque<(u8, UdpPacket<Box<[u8]>>)>,
+    /// Callbacks of messages which are not tracked, to be called next tick
+    pub (self) untracked_callbacks: Vec<DeliveryCallback>,
+    pub (crate) layout: FragmentLayout,
+    /// Last message sent on each channel with `RUdpSocket::send_latest`
+    pub (self) latest_by_channel: HashMap<u16, u32>,
+    /// How long we keep acknowledged messages around, to recognize the acks still in flight
+    pub (crate) cleanup_delay: Duration,
+    /// Resend delays are multiplied by this, see `congestion_factor`
+    pub (self) congestion_factor: f64,
+    /// Resends since the congestion factor was last updated
+    pub (self) resends_sampled: u32,
+    /// Messages acknowledged since the congestion factor was last updated
+    pub (self) acks_sampled: u32,
 }
 
 impl<D: AsRef<[u8]> + 'static + Clone> SentDataTracker<D> {
     pub fn new() -> SentDataTracker<D> {
         SentDataTracker {
             sets: Default::default(),
+            blocked_fragments: VecDeque::new(),
+            untracked_callbacks: Vec::new(),
+            layout: FragmentLayout::default(),
+            latest_by_channel: HashMap::default(),
+            cleanup_delay: SEQ_DATA_CLEANUP_DELAY,
+            congestion_factor: 1.0,
+            resends_sampled: 0,
+            acks_sampled: 0,
         }
     }
 
-    pub fn send_data(&mut self, seq_id: u32, data: D, now: Instant, message_type: MessageType, message_priority: MessagePriority, socket: &UdpSocketWrapper) {
-        let expiration = PacketExpiration::from_message_type(message_type, now);
-        let (fragments, frag_total) = build_fragments_from_bytes(data.as_ref(), seq_id, FragmentMeta::from(expiration)).expect("Your message is too big to be sent via RUDP.");
-        for fragment in fragments {
-            let _r = socket.send_udp_packet(&UdpPacket::from(&fragment));
-            // TODO log the error if any
+    /// How much the resend delays of the priorities are stretched because the link looks congested, 1.0 meaning not at all.
+    ///
+    /// When most of what we send has to be resent, resending just as often would only make things worse:
+    /// the factor is then multiplied (up to 8), and brought back down little by little once messages
+    /// are acknowledged without needing to be resent.
+    pub fn congestion_factor(&self) -> f64 {
+        self.congestion_factor
+    }
+
+    fn sample_congestion(&mut self) {
+        let samples = self.resends_sampled + self.acks_sampled;
+        if samples < CONGESTION_SAMPLE_SIZE {
+            return;
+        }
+        if self.resends_sampled as f64 > samples as f64 * CONGESTION_LOSS_THRESHOLD {
+            self.congestion_factor = (self.congestion_factor * CONGESTION_BACKOFF).min(MAX_CONGESTION_FACTOR);
+            log::debug!("too many resends, stretching resend delays by {}", self.congestion_factor);
+        } else {
+            self.congestion_factor = (self.congestion_factor - CONGESTION_RECOVERY_STEP).max(1.0);
         }
+        self.resends_sampled = 0;
+        self.acks_sampled = 0;
+    }
+
+    /// Returns false if some fragments could not be sent right away because the send buffer was full,
+    /// or an error if the message is too large for the current layout, in which case nothing is sent.
+    pub fn send_data(&mut self, seq_id: u32, data: D, now: Instant, message_type: MessageType, message_priority: MessagePriority, socket: &UdpSocketWrapper) -> Result<bool, SendError> {
+        let expiration = PacketExpiration::from_message_type(message_type, now, self.cleanup_delay);
+        let (all_sent, frag_total) = self.send_fragments(seq_id, data.as_ref(), FragmentMeta::from(expiration), message_priority, socket)?;
 
         if let Some(packet_expiration) = expiration {
-            let sent_data_set = SentDataSet::new(data.clone(), frag_total, now, packet_expiration, message_priority);
+            let sent_data_set = SentDataSet::new(data.clone(), frag_total, now, packet_expiration, message_priority, self.layout);
 
             if self.sets.insert(seq_id, sent_data_set).is_some() {
                 panic!("seq_id {:?} is already registered in sent_data_tracker", seq_id);
             }
         }
+        Ok(all_sent)
+    }
+
+    /// Sends a `Forgettable` message straight from `data`: nothing is kept, so the data doesn't have to be owned.
+    ///
+    /// Returns false if some fragments could not be sent right away because the send buffer was full,
+    /// or an error if the message is too large for the current layout, in which case nothing is sent.
+    pub fn send_forgettable(&mut self, seq_id: u32, data: &[u8], message_priority: MessagePriority, socket: &UdpSocketWrapper) -> Result<bool, SendError> {
+        let (all_sent, _frag_total) = self.send_fragments(seq_id, data, FragmentMeta::Forgettable, message_priority, socket)?;
+        Ok(all_sent)
+    }
+
+    /// Cuts `data` into fragments with the current layout and sends all of them.
+    ///
+    /// Returns false if some fragments could not be sent right away, along with the frag_total of the message.
+    /// Returns an error without sending anything if the message needs more fragments than a message can have.
+    fn send_fragments(&mut self, seq_id: u32, data: &[u8], frag_meta: FragmentMeta, priority: MessagePriority, socket: &UdpSocketWrapper) -> Result<(bool, u8), SendError> {
+        let mut all_sent = true;
+        let max_fragment_size = self.layout.max_fragment_size;
+        let (fragments, frag_total) = build_fragments_from_bytes(data, seq_id, frag_meta, self.layout.sizing, max_fragment_size)
+            .map_err(|()| SendError::TooLarge { len: data.len(), max: MAX_FRAGMENTS_IN_MESSAGE * max_fragment_size })?;
+        for fragment in fragments {
+            let udp_packet = self.layout.udp_packet(&fragment, priority);
+            match socket.send_udp_packet(&udp_packet) {
+                Ok(()) => {},
+                Err(ref e) if e.kind() == IoErrorKind::WouldBlock => {
+                    // don't wait for the resend delay when it's our own send buffer that is full
+                    log::debug!("send buffer full, seq_id={} frag_id={} will be sent again next tick", seq_id, fragment.frag_id);
+                    self.blocked_fragments.push_back((0, udp_packet));
+                    all_sent = false;
+                },
+                Err(e) => {
+                    log::warn!("failed to send seq_id={} frag_id={}: {}", seq_id, fragment.frag_id, e);
+                }
+            }
+        }
+        Ok((all_sent, frag_total))
+    }
+
+    /// Returns when the remote last made progress on the message stuck for the longest time, among the messages
+    /// resent at least `min_resends` times in a row without any new fragment being acknowledged, and with fragments
+    /// larger than `fragment_size`.
+    pub fn stuck_since(&self, min_resends: u32, fragment_size: usize) -> Option<Instant> {
+        self.sets.values()
+            .filter(|set| set.is_pending() && set.resends_without_progress >= min_resends)
+            .filter(|set| set.data.as_ref().len().min(set.layout.max_fragment_size) > fragment_size)
+            .map(|set| set.last_progress)
+            .min()
+    }
+
+    /// Returns the seq_ids of the messages not acknowledged yet whose fragments are larger than the current layout allows.
+    ///
+    /// Messages which would need more fragments than a message can have with the current layout are left out.
+    pub fn oversized_pending(&self) -> Vec<u32> {
+        let max_message_size = MAX_FRAGMENTS_IN_MESSAGE * self.layout.max_fragment_size;
+        let mut seq_ids: Vec<u32> = self.sets.iter()
+            .filter(|(_, set)| set.is_pending() && set.layout.max_fragment_size > self.layout.max_fragment_size)
+            .filter(|(_, set)| set.data.as_ref().len() > self.layout.max_fragment_size && set.data.as_ref().len() <= max_message_size)
+            .map(|(seq_id, _)| *seq_id)
+            .collect();
+        seq_ids.sort_unstable();
+        seq_ids
+    }
+
+    /// Sends the message `seq_id` again from scratch as `new_seq_id`, cut with the current layout.
+    ///
+    /// The remote can't mix fragments of different sizes in the same message, hence the new seq_id.
+    /// The message keeps its expiration, priority and callback. If it is too large to be cut with the current layout,
+    /// it is left as it is, under `seq_id`, and the error is returned.
+    pub fn refragment(&mut self, seq_id: u32, new_seq_id: u32, now: Instant, socket: &UdpSocketWrapper) -> Result<(), SendError> {
+        let set = match self.sets.remove(&seq_id) {
+            Some(set) => set,
+            None => return Ok(()),
+        };
+        let frag_meta = FragmentMeta::from(Some(set.expiration_type));
+        let (_all_sent, frag_total) = match self.send_fragments(new_seq_id, set.data.as_ref(), frag_meta, set.message_priority, socket) {
+            Ok(sent) => sent,
+            Err(e) => {
+                self.sets.insert(seq_id, set);
+                return Err(e);
+            },
+        };
+        let mut new_set = SentDataSet::new(set.data, frag_total, now, set.expiration_type, set.message_priority, self.layout);
+        new_set.callback = set.callback;
+        self.sets.insert(new_seq_id, new_set);
+        for latest in self.latest_by_channel.values_mut() {
+            if *latest == seq_id {
+                *latest = new_seq_id;
+            }
+        }
+        Ok(())
+    }
+
+    /// Registers the callback to call once the outcome of `seq_id` is known.
+    pub fn set_callback(&mut self, seq_id: u32, callback: DeliveryCallback) {
+        match self.sets.get_mut(&seq_id) {
+            Some(set) => set.callback = Some(callback),
+            None => self.untracked_callbacks.push(callback),
+        }
+    }
+
+    /// Forgets every message not acknowledged yet, as well as the fragments waiting for the send buffer.
+    ///
+    /// Returns how many messages were forgotten.
+    pub fn cancel_pending(&mut self) -> usize {
+        self.blocked_fragments.clear();
+        let mut cancelled = 0;
+        self.sets.retain(|_, set| {
+            if set.complete_since.is_some() {
+                return true;
+            }
+            if let Some(callback) = set.callback.take() {
+                callback.call(DeliveryResult::Cancelled);
+            }
+            cancelled += 1;
+            false
+        });
+        cancelled
+    }
+
+    /// Records `seq_id` as the latest message of `channel`, and forgets the previous one if it is not acknowledged yet.
+    ///
+    /// Returns the seq_id of the message which was forgotten, if any.
+    pub fn supersede(&mut self, channel: u16, seq_id: u32) -> Option<u32> {
+        let previous = self.latest_by_channel.insert(channel, seq_id)?;
+        match self.sets.get(&previous) {
+            Some(set) if set.complete_since.is_none() => {
+                if let Some(set) = self.sets.remove(&previous) {
+                    if let Some(callback) = set.callback {
+                        callback.call(DeliveryResult::Cancelled);
+                    }
+                }
+                Some(previous)
+            },
+            _ => None,
+        }
+    }
+
+    /// Calls the callbacks of every message not acknowledged yet with `Failed`.
+    pub fn fail_callbacks(&mut self) {
+        for set in self.sets.values_mut() {
+            if let Some(callback) = set.callback.take() {
+                callback.call(DeliveryResult::Failed);
+            }
+        }
     }
 
     fn remove_seq_id(&mut self, seq_id: u32) {
@@ -213,7 +602,74 @@ impl<D: AsRef<[u8]> + 'static + Clone> SentDataTracker<D> {
         }
     }
 
-    pub fn receive_ack(&mut self, seq_id: u32, data: BoxedSlice<u8>, now: Instant) {
+    pub fn delivery_status(&self, seq_id: u32) -> DeliveryStatus {
+        match self.is_seq_id_received(seq_id) {
+            Err(()) => DeliveryStatus::Unknown,
+            Ok(false) => DeliveryStatus::Pending,
+            Ok(true) => DeliveryStatus::Acknowledged,
+        }
+    }
+
+    /// Returns the bytes of the last ack received for `seq_id`, if any.
+    pub fn last_ack_bitmap(&self, seq_id: u32) -> Option<Box<[u8]>> {
+        self.sets.get(&seq_id)
+            .and_then(|set| set.last_received_ack.as_ref())
+            .map(|(_, ack)| Box::from(ack.as_bytes()))
+    }
+
+    /// Returns the resend delay currently used for `seq_id`, if it is waiting to be acknowledged.
+    pub fn effective_resend_delay(&self, seq_id: u32) -> Option<Duration> {
+        self.sets.get(&seq_id)
+            .filter(|set| set.complete_since.is_none() && set.is_resent())
+            .map(|set| set.effective_resend_delay(self.congestion_factor))
+    }
+
+    /// Returns the earliest time at which a message waiting to be acknowledged will be sent again.
+    pub fn next_resend(&self) -> Option<Instant> {
+        self.sets.values()
+            .filter(|set| set.is_pending())
+            .map(|set| set.next_resend(self.congestion_factor))
+            .min()
+    }
+
+    /// Returns true if at least one message is waiting to be acknowledged by the remote.
+    pub fn has_pending(&self) -> bool {
+        self.sets.values().any(|set| set.is_pending())
+    }
+
+    /// Returns true if some fragments are waiting for the send buffer to have room for them.
+    pub fn has_blocked_fragments(&self) -> bool {
+        !self.blocked_fragments.is_empty()
+    }
+
+    /// Returns the number of messages waiting to be acknowledged by the remote.
+    pub fn pending_count(&self) -> usize {
+        self.sets.values().filter(|set| set.is_pending()).count()
+    }
+
+    /// Returns the size of all the messages waiting to be acknowledged by the remote, in bytes.
+    pub fn pending_bytes(&self) -> usize {
+        self.sets.values().filter(|set| set.is_pending()).map(|set| set.data.as_ref().len()).sum()
+    }
+
+    /// Returns all the messages not acknowledged yet, the ones that will be re-sent first coming first.
+    pub fn pending_schedule(&self) -> Vec<PendingInfo> {
+        let mut schedule: Vec<PendingInfo> = self.sets.iter()
+            .filter(|(_, set)| set.is_pending())
+            .map(|(seq_id, set)| PendingInfo {
+                seq_id: *seq_id,
+                priority: set.message_priority,
+                next_resend: set.next_resend(self.congestion_factor),
+                remaining_fragments: set.remaining_fragments(),
+                total_fragments: set.frag_total as usize + 1,
+            })
+            .collect();
+        schedule.sort_by_key(|info| (info.next_resend, info.seq_id));
+        schedule
+    }
+
+    /// Returns false if the message `seq_id` isn't tracked (anymore).
+    pub fn receive_ack(&mut self, seq_id: u32, data: BoxedSlice<u8>, now: Instant) -> bool {
         if let Some(set) = self.sets.get_mut(&seq_id) {
             let ack = Ack::new(data);
             set.last_received_ack = Some((now, ack));
@@ -225,39 +681,107 @@ impl<D: AsRef<[u8]> + 'static + Clone> SentDataTracker<D> {
                     set.unanswered_ack = Some((now, now))
                 }
             };
+            true
         } else {
             // couldn't find the matching fragment set... 2 possibilities:
             // * The remote lied, we never had such a seq_id
             // * We dropped the message on our end, so we can't even try to recover it 
             // in either case, the only thing we can do is to drop the ack and give up on life.
-        };
-        // if remove_ack {
-        //     self.remove_seq_id(seq_id);
-        // }
+            false
+        }
+    }
+
+    /// Tries to send again the fragments which were blocked by a full send buffer, in order.
+    fn retry_blocked_fragments(&mut self, socket: &UdpSocketWrapper) {
+        while let Some((retries, udp_packet)) = self.blocked_fragments.pop_front() {
+            match socket.send_udp_packet(&udp_packet) {
+                Ok(()) => {},
+                Err(ref e) if e.kind() == IoErrorKind::WouldBlock => {
+                    if retries + 1 < MAX_BLOCKED_SEND_RETRIES {
+                        self.blocked_fragments.push_front((retries + 1, udp_packet));
+                    } else {
+                        log::debug!("giving up on sending a blocked fragment, leaving it to the resend mechanism");
+                    }
+                    // the buffer is still full, no need to try the others
+                    break;
+                },
+                Err(e) => {
+                    log::warn!("failed to send a blocked fragment: {}", e);
+                }
+            }
+        }
     }
 
     /// Clears data that is too old to be stored here (acks missing a part taht are too old, ...)
-    pub fn next_tick(&mut self, now: Instant, socket: &UdpSocketWrapper) {
+    /// and re-sends what has not been acknowledged yet, as long as it fits in `allowance` (in bytes).
+    ///
+    /// Returns true if some resends had to be deferred to the next tick because of the allowance.
+    /// When the allowance is limited, messages of higher priority are resent first.
+    /// The seq_ids of the messages found fully acknowledged during this tick are pushed to `delivered`.
+    pub fn next_tick(&mut self, now: Instant, socket: &UdpSocketWrapper, allowance: &mut usize, delivered: &mut Vec<u32>) -> bool {
+        let mut deferred = false;
+        self.retry_blocked_fragments(socket);
+        // nothing tells us whether they were received
+        let mut results: Vec<_> = self.untracked_callbacks.drain(..)
+            .map(|callback| (callback, DeliveryResult::Expired))
+            .collect();
         let mut entries_to_remove: Vec<_> = vec!();
-        for (seq_id, ref mut set) in &mut self.sets {
+        let mut seq_ids: Vec<u32> = self.sets.keys().copied().collect();
+        if *allowance != usize::MAX {
+            // not everything may be resent: the most urgent messages go first, then the oldest
+            let sets = &self.sets;
+            seq_ids.sort_unstable_by_key(|seq_id| (::std::cmp::Reverse(sets[seq_id].message_priority.rank()), *seq_id));
+        }
+        for seq_id in seq_ids {
+            let set = match self.sets.get_mut(&seq_id) {
+                Some(set) => set,
+                None => continue,
+            };
             if set.is_expired(now) {
-                entries_to_remove.push(*seq_id);
+                if let Some(callback) = set.callback.take() {
+                    let result = match set.expiration_type {
+                        PacketExpiration::TryReliable { .. } => DeliveryResult::GaveUp,
+                        _ => DeliveryResult::Expired,
+                    };
+                    results.push((callback, result));
+                }
+                entries_to_remove.push(seq_id);
                 continue;
             }
+            let was_complete = set.complete_since.is_some();
             if let Some(complete_time) = set.complete_since {
                 let delta = now - complete_time;
-                if delta >= SEQ_DATA_CLEANUP_DELAY {
-                    entries_to_remove.push(*seq_id);
+                if delta >= self.cleanup_delay {
+                    entries_to_remove.push(seq_id);
                 }
+            } else if !set.is_resent() {
+                set.complete_since = set.complete_ack_received();
             } else {
-                let ack_received = set.attempt_resend_packets(*seq_id, now, socket);
+                let last_sent_packet = set.last_sent_packet;
+                let ack_received = set.attempt_resend_packets(seq_id, now, socket, allowance, &mut deferred, self.congestion_factor);
                 if let Some(ack_received) = ack_received {
                     set.complete_since = Some(ack_received);
+                } else if set.last_sent_packet != last_sent_packet {
+                    self.resends_sampled += 1;
+                }
+            }
+            if !was_complete && set.complete_since.is_some() {
+                delivered.push(seq_id);
+                self.acks_sampled += 1;
+            }
+            if set.complete_since.is_some() {
+                if let Some(callback) = set.callback.take() {
+                    results.push((callback, DeliveryResult::Delivered));
                 }
             }
         }
+        self.sample_congestion();
         for seq_id in entries_to_remove {
             self.remove_seq_id(seq_id);
         }
+        for (callback, result) in results {
+            callback.call(result);
+        }
+        deferred
     }
 }
\ No newline at end of file
@@ -0,0 +1,63 @@
+use crate::rudp::SocketStatus;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// State and counters of a remote of a `RUdpServer`, taken at a given instant.
+///
+/// See `RUdpServer::snapshot`. With the `serde` feature, this can be serialized directly,
+/// for instance as JSON for an admin endpoint. `status` is then serialized as its name only.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct RemoteSnapshot {
+    pub addr: SocketAddr,
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_status"))]
+    pub status: SocketStatus,
+    /// Time since the connection was created
+    pub uptime: Duration,
+    /// Ping to the remote as ms, if it has been computed yet
+    pub ping: Option<u32>,
+    /// Time since we last received anything from the remote
+    pub silence: Duration,
+    /// Time left before the connection times out if the remote stays silent, None if it is finished
+    pub time_until_timeout: Option<Duration>,
+    /// Number of our messages not fully acknowledged by the remote yet
+    pub pending_sends: usize,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Rough estimate of the packet loss towards the remote, between 0 and 1: the ratio of
+    /// fragments we had to send again over all the packets sent.
+    ///
+    /// Like the bytes counters, it is computed since the last `reset_stats`.
+    pub loss_estimate: f32,
+}
+
+#[cfg(feature = "serde")]
+fn serialize_status<S: serde::Serializer>(status: &SocketStatus, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(status.name())
+}
+
+/// What a `RUdpServer` needs to know about its connections to carry on serving them, as returned by
+/// `RUdpServer::export_state`.
+///
+/// With the `serde` feature, this can be serialized and sent to a standby server, which takes over with
+/// `RUdpServer::import_state` if this one goes down.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ServerState {
+    pub remotes: Vec<RemoteState>,
+}
+
+/// State of a single connection of a `ServerState`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RemoteState {
+    pub addr: SocketAddr,
+    /// Token the remote was given when it connected, so that it can still `rebind` or `reconnect`
+    pub connection_token: Option<u64>,
+    /// seq_id of the next message sent to the remote, so that the remote doesn't mistake new messages for old ones
+    pub next_seq_id: u32,
+    /// See `RUdpSocket::set_max_payload_size`
+    pub max_payload_size: usize,
+    /// Largest payload the remote advertised during the handshake, if it did
+    pub remote_max_payload_size: Option<usize>,
+}
@@ -0,0 +1,177 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Traffic counters of a connection, since it was created or since the last `reset_stats`.
+///
+/// Bytes are counted at the UDP level: headers of this crate included, IP and UDP headers excluded.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionStats {
+    pub bytes_sent: u64,
+    pub packets_sent: u64,
+    pub bytes_received: u64,
+    pub packets_received: u64,
+    /// Fragments sent again because the remote did not acknowledge them in time
+    pub fragments_resent: u64,
+    /// Incoming messages given up on because some of their fragments never arrived
+    pub inbound_messages_dropped: u64,
+    /// Packets received from the remote which could not be read (bad CRC, invalid layout, ...)
+    pub malformed_packets: u64,
+    /// Acks received for messages we sent but stopped tracking: acknowledged a while ago, expired, or cancelled.
+    ///
+    /// A lot of them for acknowledged messages suggests `RUdpSocket::set_cleanup_delay` is too short for this path.
+    pub late_acks: u64,
+}
+
+impl ConnectionStats {
+    pub (crate) fn record_sent(&mut self, bytes: usize) {
+        self.bytes_sent += bytes as u64;
+        self.packets_sent += 1;
+    }
+
+    pub (crate) fn record_received(&mut self, bytes: usize) {
+        self.bytes_received += bytes as u64;
+        self.packets_received += 1;
+    }
+}
+
+/// Window over which `RateMeter` averages the traffic.
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Bytes sent and received during the last second, from the counters of `ConnectionStats` sampled every tick.
+#[derive(Debug, Default)]
+pub (crate) struct RateMeter {
+    /// (tick, bytes sent since the previous tick, bytes received since the previous tick), oldest first
+    deltas: VecDeque<(Instant, u64, u64)>,
+    /// Counters at the previous tick
+    last_sent: u64,
+    last_received: u64,
+}
+
+impl RateMeter {
+    pub (crate) fn record_tick(&mut self, now: Instant, stats: &ConnectionStats) {
+        // saturating: the counters may have been reset since the previous tick
+        let sent = stats.bytes_sent.saturating_sub(self.last_sent);
+        let received = stats.bytes_received.saturating_sub(self.last_received);
+        self.last_sent = stats.bytes_sent;
+        self.last_received = stats.bytes_received;
+        self.deltas.push_back((now, sent, received));
+        while self.deltas.front().is_some_and(|(tick, _, _)| now.saturating_duration_since(*tick) >= RATE_WINDOW) {
+            self.deltas.pop_front();
+        }
+    }
+
+    /// The counters were set back to 0.
+    pub (crate) fn reset_counters(&mut self) {
+        self.last_sent = 0;
+        self.last_received = 0;
+    }
+
+    /// Bytes per second
+    pub (crate) fn send_rate(&self) -> u64 {
+        self.deltas.iter().map(|(_, sent, _)| sent).sum::<u64>() * 1000 / RATE_WINDOW.as_millis() as u64
+    }
+
+    /// Bytes per second
+    pub (crate) fn recv_rate(&self) -> u64 {
+        self.deltas.iter().map(|(_, _, received)| received).sum::<u64>() * 1000 / RATE_WINDOW.as_millis() as u64
+    }
+}
+
+/// How many packets the server read from its sockets in a single tick, since it was created
+/// or since the last `reset_recv_burst_stats`.
+///
+/// A `max` much higher than what you expect to receive per tick means packets arrive in bursts,
+/// and that the receive buffer of the socket should be large enough to hold them.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct BurstStats {
+    pub max: usize,
+    pub avg: f64,
+    /// 99% of the ticks received at most this many packets
+    pub p99: usize,
+}
+
+/// Bursts larger than this are all counted in the last bucket of the histogram.
+const MAX_TRACKED_BURST: usize = 4096;
+
+/// Histogram of the number of packets received per tick.
+#[derive(Debug, Default, Clone)]
+pub (crate) struct BurstTracker {
+    /// `ticks_by_burst[n]` is the number of ticks where n packets were received
+    ticks_by_burst: Vec<u64>,
+    ticks: u64,
+    packets: u64,
+    max: usize,
+}
+
+impl BurstTracker {
+    pub (crate) fn record_tick(&mut self, packets: usize) {
+        let bucket = packets.min(MAX_TRACKED_BURST);
+        if self.ticks_by_burst.len() <= bucket {
+            self.ticks_by_burst.resize(bucket + 1, 0);
+        }
+        self.ticks_by_burst[bucket] += 1;
+        self.ticks += 1;
+        self.packets += packets as u64;
+        self.max = self.max.max(packets);
+    }
+
+    pub (crate) fn stats(&self) -> BurstStats {
+        if self.ticks == 0 {
+            return BurstStats::default();
+        }
+        // smallest burst size such that at least 99% of the ticks are at or below it
+        let threshold = (self.ticks * 99 + 99) / 100;
+        let mut seen = 0;
+        let mut p99 = self.max;
+        for (burst, ticks) in self.ticks_by_burst.iter().enumerate() {
+            seen += ticks;
+            if seen >= threshold {
+                p99 = burst;
+                break;
+            }
+        }
+        BurstStats {
+            max: self.max,
+            avg: self.packets as f64 / self.ticks as f64,
+            p99,
+        }
+    }
+}
+
+#[test]
+fn burst_tracker_stats() {
+    let mut tracker = BurstTracker::default();
+    assert_eq!(tracker.stats(), BurstStats::default());
+    for _ in 0..99 {
+        tracker.record_tick(1);
+    }
+    tracker.record_tick(101);
+    let stats = tracker.stats();
+    assert_eq!(stats.max, 101);
+    assert_eq!(stats.p99, 1);
+    assert!((stats.avg - 2.0).abs() < 1e-9);
+
+    tracker.record_tick(50);
+    assert_eq!(tracker.stats().p99, 50);
+}
+
+#[test]
+fn rate_meter_window() {
+    let now = Instant::now();
+    let mut meter = RateMeter::default();
+    let mut stats = ConnectionStats::default();
+    for tick in 0..10u64 {
+        stats.bytes_sent += 100;
+        stats.bytes_received += 10;
+        meter.record_tick(now + Duration::from_millis(tick * 200), &stats);
+    }
+    // only the last 5 ticks are within the last second
+    assert_eq!(meter.send_rate(), 500);
+    assert_eq!(meter.recv_rate(), 50);
+
+    stats = ConnectionStats::default();
+    meter.reset_counters();
+    stats.bytes_sent += 300;
+    meter.record_tick(now + Duration::from_millis(2000), &stats);
+    assert_eq!(meter.send_rate(), 300 + 400);
+}
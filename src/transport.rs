@@ -0,0 +1,35 @@
+use std::io::Result as IoResult;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+
+type SendCallback = dyn FnMut(&UdpSocket, &[u8], SocketAddr) -> IoResult<usize> + Send;
+
+/// How datagrams leave a socket: straight through its `UdpSocket`, unless tests put something in between
+/// to see how the socket copes with a full send buffer, a partial send or a small MTU.
+#[derive(Clone, Default)]
+pub (crate) struct Transport(Option<Arc<Mutex<SendCallback>>>);
+
+impl ::std::fmt::Debug for Transport {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "Transport({})", if self.0.is_some() { "custom" } else { "udp" })
+    }
+}
+
+impl Transport {
+    /// `f` is called instead of `UdpSocket::send_to`, and returns what it would.
+    #[cfg(test)]
+    pub (crate) fn new<F: FnMut(&UdpSocket, &[u8], SocketAddr) -> IoResult<usize> + Send + 'static>(f: F) -> Transport {
+        Transport(Some(Arc::new(Mutex::new(f))))
+    }
+
+    #[inline]
+    pub (crate) fn send_to(&self, udp_socket: &UdpSocket, bytes: &[u8], addr: SocketAddr) -> IoResult<usize> {
+        match &self.0 {
+            Some(f) => {
+                let mut f = f.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                f(udp_socket, bytes, addr)
+            },
+            None => udp_socket.send_to(bytes, addr),
+        }
+    }
+}
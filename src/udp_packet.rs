@@ -11,22 +11,58 @@ fn crc32_hash(bytes: &[u8]) -> u32 {
     h.finalize()
 }
 
+/// A packet of the protocol, decoded.
+///
+/// Turn it into bytes ready to be sent with `UdpPacket::from(&packet)`. Only reachable from outside of the crate
+/// with the `wire` feature, for tools which need to craft or read packets themselves: see `UdpPacket::decode`.
 #[derive(Debug, PartialEq)]
-pub (crate) enum Packet<P: AsRef<[u8]>> {
+pub enum Packet<P: AsRef<[u8]>> {
     Fragment(Fragment<P>),
     Ack(u32, P),
-    Syn,
-    SynAck,
+    /// Payload is the optional handshake payload of the application
+    Syn(P),
+    /// Payload is the connection token given by the server, or empty
+    SynAck(P),
     Heartbeat,
     End(u32),
-    Abort(u32)
+    Abort(u32),
+    /// Several whole packets, sent in a single datagram
+    Batch(P),
+    /// Payload is the connection token of the connection the sender wants to continue from a new address
+    Migrate(P),
+    /// Holds the id of the probe, which the remote must answer with a ProbeAck
+    Probe(u32),
+    ProbeAck(u32),
+    /// Same as Syn, but the payload starts with the connection token of a previous connection of the remote
+    Rejoin(P),
 }
 
 impl<P: AsRef<[u8]>> Packet<P> {
+    #[cfg(feature = "wire")]
+    pub (crate) fn map_payload<Q: AsRef<[u8]>, F: FnOnce(P) -> Q>(self, f: F) -> Packet<Q> {
+        match self {
+            Packet::Fragment(Fragment { seq_id, frag_id, frag_total, frag_meta, data }) =>
+                Packet::Fragment(Fragment { seq_id, frag_id, frag_total, frag_meta, data: f(data) }),
+            Packet::Ack(seq_id, data) => Packet::Ack(seq_id, f(data)),
+            Packet::Syn(data) => Packet::Syn(f(data)),
+            Packet::SynAck(data) => Packet::SynAck(f(data)),
+            Packet::Heartbeat => Packet::Heartbeat,
+            Packet::End(seq_id) => Packet::End(seq_id),
+            Packet::Abort(seq_id) => Packet::Abort(seq_id),
+            Packet::Batch(data) => Packet::Batch(f(data)),
+            Packet::Migrate(data) => Packet::Migrate(f(data)),
+            Packet::Probe(probe_id) => Packet::Probe(probe_id),
+            Packet::ProbeAck(probe_id) => Packet::ProbeAck(probe_id),
+            Packet::Rejoin(data) => Packet::Rejoin(f(data)),
+        }
+    }
+
     pub (crate) fn udp_packet_size(&self) -> usize {
         let data_size = match *self {
             Packet::Fragment(Fragment { ref data, .. }) => FRAG_ADD_HEADER_SIZE + data.as_ref().len(),
             Packet::Ack(_, ref data) => data.as_ref().len(),
+            Packet::Batch(ref data) => data.as_ref().len(),
+            Packet::Syn(ref data) | Packet::SynAck(ref data) | Packet::Migrate(ref data) | Packet::Rejoin(ref data) => data.as_ref().len(),
             _ => 0,
         };
         CRC32_SIZE + COMMON_HEADER_SIZE + data_size
@@ -37,11 +73,16 @@ impl<P: AsRef<[u8]>> Packet<P> {
         match *self {
             Packet::Fragment(Fragment { seq_id, frag_id, frag_total, .. }) => (seq_id, frag_id, frag_total),
             Packet::Ack(seq_id, _) => (seq_id, 255, 0),
-            Packet::Syn => (0, 255, 1),
-            Packet::SynAck => (0, 255, 2),
+            Packet::Syn(_) => (0, 255, 1),
+            Packet::SynAck(_) => (0, 255, 2),
             Packet::End(last_seq_id) => (last_seq_id, 255, 3),
             Packet::Abort(last_seq_id) => (last_seq_id, 255, 4),
             Packet::Heartbeat => (0, 255, 5),
+            Packet::Batch(_) => (0, 255, 6),
+            Packet::Migrate(_) => (0, 255, 7),
+            Packet::Probe(probe_id) => (probe_id, 255, 8),
+            Packet::ProbeAck(probe_id) => (probe_id, 255, 9),
+            Packet::Rejoin(_) => (0, 255, 10),
         }
     }
 
@@ -54,6 +95,8 @@ impl<P: AsRef<[u8]>> Packet<P> {
                 payload[1..].copy_from_slice(data.as_ref())
             },
             Packet::Ack(_, ref data) => payload.copy_from_slice(data.as_ref()),
+            Packet::Batch(ref data) => payload.copy_from_slice(data.as_ref()),
+            Packet::Syn(ref data) | Packet::SynAck(ref data) | Packet::Migrate(ref data) | Packet::Rejoin(ref data) => payload.copy_from_slice(data.as_ref()),
             _ => {/* don't write a payload for the other kinds */}
         }
     }
@@ -68,11 +111,16 @@ impl<P: AsRef<[u8]>> Packet<P> {
                 f1.seq_id == f2.seq_id && f1.frag_id == f2.frag_id && f1.frag_total == f2.frag_total
                 && f1.data.as_ref() == f2.data.as_ref(),
             (Ack(s1, ref d1), Ack(s2, ref d2)) => s1 == s2 && d1.as_ref() == d2.as_ref(),
-            (Syn, Syn) => true,
-            (SynAck, SynAck) => true,
+            (Syn(d1), Syn(d2)) => d1.as_ref() == d2.as_ref(),
+            (SynAck(d1), SynAck(d2)) => d1.as_ref() == d2.as_ref(),
             (End(s1), End(s2)) => s1 == s2,
             (Abort(s1), Abort(s2)) => s1 == s2,
             (Heartbeat, Heartbeat) => true,
+            (Batch(d1), Batch(d2)) => d1.as_ref() == d2.as_ref(),
+            (Migrate(d1), Migrate(d2)) => d1.as_ref() == d2.as_ref(),
+            (Probe(p1), Probe(p2)) => p1 == p2,
+            (ProbeAck(p1), ProbeAck(p2)) => p1 == p2,
+            (Rejoin(d1), Rejoin(d2)) => d1.as_ref() == d2.as_ref(),
             _ => false,
         }
     }
@@ -90,24 +138,58 @@ pub enum PacketMeta {
     Heartbeat,
     End(u32),
     Abort(u32),
+    /// Several whole packets, sent in a single datagram
+    Batch,
+    /// A remote continuing an existing connection from a new address
+    Migrate,
+    /// A probe with its id, see `RUdpSocket::probe`
+    Probe(u32),
+    /// The answer to a probe, with its id
+    ProbeAck(u32),
+    /// A Syn from a remote which was connected before, see `RUdpSocket::reconnect`
+    Rejoin,
 }
 
 impl PacketMeta {
+    /// Returns the seq_id carried by the packet, if this kind of packet has one.
+    ///
+    /// For `End` and `Abort`, this is the last seq_id sent by the remote.
+    pub fn seq_id(&self) -> Option<u32> {
+        match *self {
+            PacketMeta::Fragment(seq_id, ..) => Some(seq_id),
+            PacketMeta::Ack(seq_id) => Some(seq_id),
+            PacketMeta::End(last_seq_id) | PacketMeta::Abort(last_seq_id) => Some(last_seq_id),
+            PacketMeta::Syn | PacketMeta::SynAck | PacketMeta::Heartbeat | PacketMeta::Batch | PacketMeta::Migrate
+                | PacketMeta::Probe(_) | PacketMeta::ProbeAck(_) | PacketMeta::Rejoin => None,
+        }
+    }
+
     /// Takes as input the **stripped** data. The 10 first bytes of the packet **must**
     /// have been stripped before hand. This method cannot fail.
     pub (crate) fn build_packet_with<P: 'static + AsRef<[u8]>>(self, data: OwnedSlice<u8, P>) -> Packet<OwnedSlice<u8, P>> {
         match self {
-            PacketMeta::Fragment(seq_id, frag_id, frag_total, frag_meta) =>
+            PacketMeta::Fragment(seq_id, frag_id, frag_total, frag_meta) => {
+                // the padding has already been checked when computing the meta
+                let strip = match read_padding(data.as_ref()) {
+                    Some(padding) => FRAG_ADD_HEADER_SIZE + PADDING_HEADER_SIZE + padding,
+                    None => FRAG_ADD_HEADER_SIZE,
+                };
                 Packet::Fragment(Fragment {
-                    seq_id, frag_id, frag_total, data: data.with_added_strip(1), frag_meta,
-                }),
+                    seq_id, frag_id, frag_total, data: data.with_added_strip(strip), frag_meta,
+                })
+            },
             PacketMeta::Ack(seq_id) =>
                 Packet::Ack(seq_id, data),
-            PacketMeta::Syn => Packet::Syn,
-            PacketMeta::SynAck => Packet::SynAck,
+            PacketMeta::Syn => Packet::Syn(data),
+            PacketMeta::SynAck => Packet::SynAck(data),
             PacketMeta::Heartbeat => Packet::Heartbeat,
             PacketMeta::End(last_seq_id) => Packet::End(last_seq_id),
             PacketMeta::Abort(last_seq_id) => Packet::Abort(last_seq_id),
+            PacketMeta::Batch => Packet::Batch(data),
+            PacketMeta::Migrate => Packet::Migrate(data),
+            PacketMeta::Probe(probe_id) => Packet::Probe(probe_id),
+            PacketMeta::ProbeAck(probe_id) => Packet::ProbeAck(probe_id),
+            PacketMeta::Rejoin => Packet::Rejoin(data),
         }
     }
 }
@@ -119,8 +201,11 @@ impl PacketMeta {
 /// [4-7]:
 ///     * if type == Fragment, the sequence id
 ///     * if type == Ack, the sequence id of the acknowledged sequence
-///     * if type == Syn, type == SynAck, nothing (0s)
+///     * if type == Syn, type == SynAck or type == Rejoin, the most data the sender wants in a fragment
+///       (see `RUdpSocket::negotiated_payload_size`), or 0 if it doesn't say
+///     * if type == Migrate, nothing (0s)
 ///     * if type == End or type == Abort, the last SeqId sent
+///     * if type == Probe or type == ProbeAck, the id of the probe
 /// [8]: "Frag Id"
 /// [9] "Frag total"
 /// [10] "Frag meta": required ONLY if the type of the message is frag.
@@ -139,14 +224,25 @@ impl PacketMeta {
 /// * If Frag ID <= Frag Total, type = Fragment.
 /// * If Frag ID == 255, Frag Total == 0: type = Ack. Ack packet for a fragment/sequence element.
 /// * If Frag ID == 255, Frag Total == 1: type = Syn. This type is sent when trying to initiate
-/// a connection with a remote.
+///   a connection with a remote. The payload is an optional payload of the application.
 /// * If Frag ID == 255, Frag Total == 2: type = SynAck: confirm that a connection has been created.
+///   The payload is either empty, or the connection token as a BigEndian u64 (see Migrate).
 /// * If Frag ID == 255, Frag Total == 3: type = End. The other end has nothing else to send,
-/// and the connection is immediatly closed.
+///   and the connection is immediatly closed.
 /// * If Frag ID == 255, Frag Total == 4: type = Abort: Other program has been terminated
-/// unexpectedly and will not receive nor send packets anymore.
+///   unexpectedly and will not receive nor send packets anymore.
 /// * If Frag ID == 255, Frag Total == 5: type = Heartbeat: Message sent every few iterations
-/// to make sure the remote does not disconnect unexpectedly.
+///   to make sure the remote does not disconnect unexpectedly.
+/// * If Frag ID == 255, Frag Total == 6: type = Batch: several whole packets packed in a single
+///   datagram. See the Batch section below.
+/// * If Frag ID == 255, Frag Total == 7: type = Migrate: the remote continues an existing connection
+///   from a new address. The payload is the connection token it received in the SynAck, as a BigEndian u64.
+/// * If Frag ID == 255, Frag Total == 8: type = Probe: the remote must answer right away with a ProbeAck
+///   holding the same id.
+/// * If Frag ID == 255, Frag Total == 9: type = ProbeAck: the answer to a Probe.
+/// * If Frag ID == 255, Frag Total == 10: type = Rejoin: same as Syn, for a remote which was connected
+///   before from another address. The payload is the connection token it received in the SynAck
+///   of its previous connection as a BigEndian u64, followed by the payload of the application.
 /// * Other uses for Frag ID == 255 and Frag Total != 255 are reserved for other packets like these.
 ///
 /// # Fragment
@@ -168,6 +264,13 @@ impl PacketMeta {
 /// starts at the end of the CRC32 and ends at the end of the UDP Packet. Meaning,
 /// for TOTAL_SIZE=12, the CRC32 would be based on frag[4..12] of the packet.
 ///
+/// # Padding
+///
+/// To hide the size of small messages, fragments may be padded (see `RUdpSocket::set_min_payload_size`).
+/// A padded fragment has the highest bit of its "Frag meta" set. It is then followed by the length
+/// of the padding as a BigEndian u16, the padding itself (zeros), and finally the data of the fragment.
+/// The padding is covered by the CRC32 like the rest of the packet, and the receiver skips it.
+///
 /// # Ack
 ///
 /// Payload will contain additional data on top of the header, not defined by the user.
@@ -187,6 +290,11 @@ impl PacketMeta {
 ///
 /// The receiver will send 1 of these packets per iteration at *most*, unless the packet is totally received (all 1s to send),
 /// then the packet is sent once per iteration, for 10 iterations (to make sure the ack goes through).
+///
+/// # Batch
+///
+/// The payload is a list of whole packets (CRC included), each of them prefixed by its length
+/// as a BigEndian u16. A batch cannot contain another batch.
 pub struct UdpPacket<B: AsRef<[u8]>> {
    pub (crate) buffer: B
 }
@@ -206,8 +314,9 @@ impl<B: AsRef<[u8]>> ::std::fmt::Debug for UdpPacket<B> {
     }
 }
 
+/// Reason why some received bytes could not be read as a packet.
 #[derive(Copy, Clone, Debug, PartialEq)]
-pub (crate) enum UdpPacketError {
+pub enum UdpPacketError {
     /// Received data was not big enough to be a message readable by this crate.
     ///
     /// (It must be at least 10 bytes, 11 bytes for frags)
@@ -217,6 +326,73 @@ pub (crate) enum UdpPacketError {
     /// Frag Layout is incorrect (frag_id, frag_total)
     InvalidFragLayout(u8, u8),
     InvalidFragMeta,
+    /// The packet is a padded fragment, but it is too small to hold its padding
+    InvalidPadding,
+}
+
+/// Set in the "Frag meta" byte of fragments followed by some padding
+const PADDED_FRAG_META_FLAG: u8 = 0x80;
+/// Bits of the "Frag meta" byte holding the priority of the message, see `MessagePriority::wire_value`
+const FRAG_PRIORITY_MASK: u8 = 0x70;
+const FRAG_PRIORITY_SHIFT: u8 = 4;
+/// Size of the length of the padding, in padded fragments
+pub (crate) const PADDING_HEADER_SIZE: usize = 2;
+
+/// Returns the length of the padding of a fragment, if it is padded. `payload` starts with the "Frag meta" byte.
+///
+/// A padded fragment too small to hold the length of its padding is seen as padded with nothing,
+/// the caller must check the packet is large enough to hold the padding and its length anyway.
+fn read_padding(payload: &[u8]) -> Option<usize> {
+    match payload.first() {
+        Some(frag_meta) if frag_meta & PADDED_FRAG_META_FLAG != 0 =>
+            Some(payload.get(1..3).map(|len| BigEndian::read_u16(len) as usize).unwrap_or(0)),
+        _ => None,
+    }
+}
+
+impl UdpPacket<Box<[u8]>> {
+    /// Replaces the value of the seq_id field of the header, used as a parameter by some packets.
+    pub (crate) fn with_seq_id_field(mut self, value: u32) -> UdpPacket<Box<[u8]>> {
+        BigEndian::write_u32(&mut self.buffer[4..8], value);
+        let generated_crc: u32 = crc32_hash(&self.buffer[4..]);
+        BigEndian::write_u32(&mut self.buffer[0..4], generated_crc);
+        self
+    }
+
+    /// Stores the priority of the message in the "Frag meta" byte of a fragment. `priority` is a `MessagePriority::wire_value`.
+    ///
+    /// Must only be called on fragments.
+    pub (crate) fn with_frag_priority(mut self, priority: u8) -> UdpPacket<Box<[u8]>> {
+        if priority == 0 {
+            return self;
+        }
+        self.buffer[10] = (self.buffer[10] & !FRAG_PRIORITY_MASK) | ((priority << FRAG_PRIORITY_SHIFT) & FRAG_PRIORITY_MASK);
+        let generated_crc: u32 = crc32_hash(&self.buffer[4..]);
+        BigEndian::write_u32(&mut self.buffer[0..4], generated_crc);
+        self
+    }
+
+    /// Same as `UdpPacket::from(fragment)`, but if the data of the fragment is smaller than `min_payload_size`,
+    /// it is padded so that the packet is as large as a fragment with `min_payload_size` bytes of data.
+    ///
+    /// `min_payload_size` must not exceed `MAX_FRAGMENT_MESSAGE_SIZE - PADDING_HEADER_SIZE`.
+    pub (crate) fn padded_fragment<T: AsRef<[u8]>>(fragment: &Fragment<T>, min_payload_size: usize) -> UdpPacket<Box<[u8]>> {
+        let data = fragment.data.as_ref();
+        if data.len() >= min_payload_size {
+            return UdpPacket::from(fragment);
+        }
+        let padding = (min_payload_size - data.len()).saturating_sub(PADDING_HEADER_SIZE);
+        let mut bytes_mut = vec!(0; FRAG_DATA_START_BYTE + PADDING_HEADER_SIZE + padding + data.len());
+        BigEndian::write_u32(&mut bytes_mut[4..8], fragment.seq_id);
+        bytes_mut[8] = fragment.frag_id;
+        bytes_mut[9] = fragment.frag_total;
+        bytes_mut[10] = fragment.frag_meta as u8 | PADDED_FRAG_META_FLAG;
+        BigEndian::write_u16(&mut bytes_mut[11..13], padding as u16);
+        bytes_mut[FRAG_DATA_START_BYTE + PADDING_HEADER_SIZE + padding..].copy_from_slice(data);
+        let generated_crc: u32 = crc32_hash(&bytes_mut[4..]);
+        BigEndian::write_u32(&mut bytes_mut[0..4], generated_crc);
+        UdpPacket {buffer: bytes_mut.into_boxed_slice()}
+    }
 }
 
 impl<'a, T: AsRef<[u8]>> From<&'a Fragment<T>> for UdpPacket<Box<[u8]>> {
@@ -242,6 +418,24 @@ impl<'a, T: AsRef<[u8]>> From<&'a Packet<T>> for UdpPacket<Box<[u8]>> {
 }
 
 impl<B: AsRef<[u8]>> UdpPacket<B> {
+    /// Returns the value of the seq_id field of the header, or 0 if the packet is too small to hold one.
+    pub (crate) fn seq_id_field(&self) -> u32 {
+        match self.buffer.as_ref().get(4..8) {
+            Some(bytes) => BigEndian::read_u32(bytes),
+            None => 0,
+        }
+    }
+
+    /// Returns the priority stored in the "Frag meta" byte, as a `MessagePriority::wire_value`.
+    ///
+    /// Only meaningful for fragments, returns 0 if the packet is too small to be one.
+    pub (crate) fn frag_priority(&self) -> u8 {
+        match self.buffer.as_ref().get(10) {
+            Some(frag_meta) => (frag_meta & FRAG_PRIORITY_MASK) >> FRAG_PRIORITY_SHIFT,
+            None => 0,
+        }
+    }
+
     fn check_header_crc(udp_message: &[u8]) -> Result<(), UdpPacketError> {
         let buffer = udp_message;
         if buffer.len() < 10 {
@@ -256,7 +450,7 @@ impl<B: AsRef<[u8]>> UdpPacket<B> {
         }
     }
 
-    #[cfg(test)]
+    /// Wraps raw bytes received from the network. Nothing is checked until the packet is read.
     pub fn new(b: B) -> UdpPacket<B>{
         UdpPacket {buffer: b}
     }
@@ -267,32 +461,42 @@ impl<B: AsRef<[u8]>> UdpPacket<B> {
     /// it may be wise to set this udp socket as non-blocking  if you don't want to block
     /// your thread forever trying to read one message.
     pub fn from_udp_socket(udp_socket: &::std::net::UdpSocket) -> ::std::io::Result<(UdpPacket<Box<[u8]>>, ::std::net::SocketAddr)> {
-        let mut buffer = vec!(0; MAX_UDP_MESSAGE_SIZE);
-        let (message_size, socket_addr) = udp_socket.recv_from(buffer.as_mut_slice())?;
-        buffer.truncate(message_size);
-        let udp_message = UdpPacket {buffer: buffer.into_boxed_slice()};
+        let mut buffer = [0u8; MAX_RCV_UDP_DATA_SIZE];
+        let (message_size, socket_addr) = udp_socket.recv_from(&mut buffer)?;
+        let udp_message = UdpPacket {buffer: Box::from(&buffer[..message_size])};
         Ok((udp_message, socket_addr))
     }
 
+    /// Returns the raw bytes of this packet, as they were (or will be) sent on the network.
     #[inline]
-    pub (crate) fn as_bytes(&self) -> &[u8] {
+    pub fn as_bytes(&self) -> &[u8] {
         self.buffer.as_ref()
     }
+
+    /// Reads the type and seq_id of the packet without decoding its payload.
+    ///
+    /// The CRC is still checked, so a packet that passes this check can be safely forwarded
+    /// as-is. This does not consume the packet.
+    #[inline]
+    pub fn peek_meta(&self) -> Result<PacketMeta, UdpPacketError> {
+        self.compute_packet_meta()
+    }
     
     pub (crate) fn compute_packet_meta(&self) -> Result<PacketMeta, UdpPacketError> {
-        Self::check_header_crc(self.buffer.as_ref())?;
+        self.compute_packet_meta_with(true)
+    }
+
+    /// Same as `compute_packet_meta`, but the CRC is only checked if `verify_crc` is true.
+    pub (crate) fn compute_packet_meta_with(&self, verify_crc: bool) -> Result<PacketMeta, UdpPacketError> {
         let buffer = self.buffer.as_ref();
-        if buffer.len() < 10 {
+        if verify_crc {
+            Self::check_header_crc(buffer)?;
+        } else if buffer.len() < 10 {
             return Err(UdpPacketError::NotBigEnough);
         }
         let frag_total: u8 = buffer[9];
         let frag_id: u8 = buffer[8];
         let seq_id: u32 = BigEndian::read_u32(&buffer[4..8]);
-        let message_crc32: u32 = BigEndian::read_u32(&buffer[0..4]);
-        let computed_crc32 = crc32_hash(&buffer[4..]);
-        if computed_crc32 != message_crc32 {
-            return Err(UdpPacketError::InvalidCrc)
-        }
         match (frag_id, frag_total) {
             (255, 0) => Ok(PacketMeta::Ack(seq_id)),
             (255, 1) => Ok(PacketMeta::Syn),
@@ -300,6 +504,11 @@ impl<B: AsRef<[u8]>> UdpPacket<B> {
             (255, 3) => Ok(PacketMeta::End(seq_id)),
             (255, 4) => Ok(PacketMeta::Abort(seq_id)),
             (255, 5) => Ok(PacketMeta::Heartbeat),
+            (255, 6) => Ok(PacketMeta::Batch),
+            (255, 7) => Ok(PacketMeta::Migrate),
+            (255, 8) => Ok(PacketMeta::Probe(seq_id)),
+            (255, 9) => Ok(PacketMeta::ProbeAck(seq_id)),
+            (255, 10) => Ok(PacketMeta::Rejoin),
 
             // since frag_total is really +1, if frag_id == frag_total, it's actually the last fragment
             // that we received. if frag_id = frag_total = 0, the first and last fragment of a message was received.
@@ -309,11 +518,18 @@ impl<B: AsRef<[u8]>> UdpPacket<B> {
                     // we need another byte here for the "frag_meta" field.
                     return Err(UdpPacketError::NotBigEnough);
                 }
-                let frag_meta = buffer[10];
+                if let Some(padding) = read_padding(&buffer[PACKET_DATA_START_BYTE..]) {
+                    if buffer.len() < FRAG_DATA_START_BYTE + PADDING_HEADER_SIZE + padding {
+                        return Err(UdpPacketError::InvalidPadding);
+                    }
+                }
+                let frag_meta = buffer[10] & !(PADDED_FRAG_META_FLAG | FRAG_PRIORITY_MASK);
                 let frag_meta = match frag_meta {
                     0 => FragmentMeta::Forgettable,
                     1 => FragmentMeta::KeyExpirable,
                     2 => FragmentMeta::Key,
+                    3 => FragmentMeta::ForgettableAcked,
+                    4 => FragmentMeta::TryReliable,
                     _ => return Err(UdpPacketError::InvalidFragMeta),
                 };
                 Ok(PacketMeta::Fragment(seq_id, frag_id, frag_total, frag_meta))
@@ -323,11 +539,91 @@ impl<B: AsRef<[u8]>> UdpPacket<B> {
     }
 }
 
+/// Size of the length prefix of every packet inside of a batch
+pub (crate) const BATCH_ENTRY_HEADER_SIZE: usize = 2;
+
+/// Packs several packets into as few datagrams as possible.
+///
+/// Packets that can't fit with any other are sent as-is, without being wrapped in a batch.
+pub (crate) fn pack_batches(packets: Vec<Box<[u8]>>) -> Vec<UdpPacket<Box<[u8]>>> {
+    const MAX_BATCH_PAYLOAD_SIZE: usize = MAX_UDP_MESSAGE_SIZE - PACKET_DATA_START_BYTE;
+
+    fn flush(batch: &mut Vec<Box<[u8]>>, out: &mut Vec<UdpPacket<Box<[u8]>>>) {
+        if batch.len() == 1 {
+            out.push(UdpPacket { buffer: batch.pop().unwrap() });
+        } else if !batch.is_empty() {
+            let mut payload: Vec<u8> = Vec::new();
+            for packet in batch.drain(..) {
+                let mut len = [0u8; BATCH_ENTRY_HEADER_SIZE];
+                BigEndian::write_u16(&mut len, packet.len() as u16);
+                payload.extend_from_slice(&len);
+                payload.extend_from_slice(&packet);
+            }
+            out.push(UdpPacket::from(&Packet::Batch(payload)));
+        }
+    }
+
+    let mut out = Vec::new();
+    let mut batch: Vec<Box<[u8]>> = Vec::new();
+    let mut batch_size: usize = 0;
+    for packet in packets {
+        let entry_size = BATCH_ENTRY_HEADER_SIZE + packet.len();
+        if batch_size + entry_size > MAX_BATCH_PAYLOAD_SIZE {
+            flush(&mut batch, &mut out);
+            batch_size = 0;
+        }
+        batch_size += entry_size;
+        batch.push(packet);
+    }
+    flush(&mut batch, &mut out);
+    out
+}
+
+/// Splits the payload of a batch into the packets it holds.
+///
+/// Returns an error if the payload is truncated.
+pub (crate) fn unpack_batch(mut payload: &[u8]) -> Result<Vec<UdpPacket<Box<[u8]>>>, UdpPacketError> {
+    let mut packets = Vec::new();
+    while !payload.is_empty() {
+        if payload.len() < BATCH_ENTRY_HEADER_SIZE {
+            return Err(UdpPacketError::NotBigEnough);
+        }
+        let len = BigEndian::read_u16(&payload[0..BATCH_ENTRY_HEADER_SIZE]) as usize;
+        let entry = &payload[BATCH_ENTRY_HEADER_SIZE..];
+        if entry.len() < len {
+            return Err(UdpPacketError::NotBigEnough);
+        }
+        packets.push(UdpPacket { buffer: Box::from(&entry[..len]) });
+        payload = &entry[len..];
+    }
+    Ok(packets)
+}
+
+#[cfg(feature = "wire")]
+impl<B: AsRef<[u8]>> UdpPacket<B> {
+    /// Checks the CRC and decodes the packet, copying its payload.
+    ///
+    /// The payload of a padded fragment is returned without its padding. The payload of a `Batch` holds
+    /// several packets, each prefixed by its size as a big endian u16.
+    pub fn decode(&self) -> Result<Packet<Box<[u8]>>, UdpPacketError> {
+        let packet = UdpPacket::new(Box::<[u8]>::from(self.as_bytes())).compute_packet()?;
+        Ok(packet.map_payload(|payload| Box::from(payload.as_ref())))
+    }
+}
+
 impl<D: AsRef<[u8]> + 'static> UdpPacket<D> {
     pub (crate) fn compute_packet(self) -> Result<Packet<OwnedSlice<u8, D>>, UdpPacketError> {
         let packet_meta = self.compute_packet_meta()?;
         Ok(packet_meta.build_packet_with(OwnedSlice::new(self.buffer, PACKET_DATA_START_BYTE)))
     }
+
+    /// Same as `compute_packet`, but gives the buffer back on error. The CRC is only checked if `verify_crc` is true.
+    pub (crate) fn compute_packet_or_buffer(self, verify_crc: bool) -> Result<Packet<OwnedSlice<u8, D>>, (UdpPacketError, D)> {
+        match self.compute_packet_meta_with(verify_crc) {
+            Ok(packet_meta) => Ok(packet_meta.build_packet_with(OwnedSlice::new(self.buffer, PACKET_DATA_START_BYTE))),
+            Err(e) => Err((e, self.buffer)),
+        }
+    }
 }
 
 #[test]
@@ -389,7 +685,7 @@ fn udp_success_syn_parse() {
     let received_message_bytes: &'static [u8] = &[0x55, 0xE1, 0x6C, 0x47, 0, 0, 0, 0, 255, 1];
     let udp_message = UdpPacket::new(received_message_bytes);
     let packet = udp_message.compute_packet().unwrap();
-    if let Packet::Syn = packet {
+    if let Packet::Syn(_) = packet {
         // Ok
     } else {
         panic!("Received packet was not a fragment SYN");
@@ -401,7 +697,7 @@ fn udp_success_synack_parse() {
     let received_message_bytes: &'static [u8] = &[0xCC, 0xE8, 0x3D, 0xFD, 0, 0, 0, 0, 255, 2];
     let udp_message = UdpPacket::new(received_message_bytes);
     let packet = udp_message.compute_packet().unwrap();
-    if let Packet::SynAck = packet {
+    if let Packet::SynAck(_) = packet {
         // Ok
     } else {
         panic!("Received packet was not a fragment SYNACK");
@@ -420,8 +716,8 @@ fn udp_ser_de_ack() {
 
 #[test]
 fn udp_ser_de_syn_synack_others() {
-    let syn1: Packet<Box<[u8]>> = Packet::Syn;
-    let synack1: Packet<Box<[u8]>> = Packet::SynAck;
+    let syn1: Packet<Box<[u8]>> = Packet::Syn(Box::from(&b"hello"[..]));
+    let synack1: Packet<Box<[u8]>> = Packet::SynAck(Box::from(&[1u8, 2, 3, 4, 5, 6, 7, 8][..]));
     let end1: Packet<Box<[u8]>> = Packet::End(5);
     let abort1: Packet<Box<[u8]>> = Packet::Abort(10);
     let heartbeat1: Packet<Box<[u8]>> = Packet::Heartbeat;
@@ -475,4 +771,117 @@ fn udp_success_frag_conversions() {
     } else {
         panic!("Received message is not of fragment type!")
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn udp_peek_meta() {
+    let sent_fragment = Fragment {
+        seq_id: 12,
+        frag_id: 1,
+        frag_total: 3,
+        frag_meta: FragmentMeta::Key,
+        data: &[1u8, 2, 3, 4]
+    };
+    let udp_packet: UdpPacket<_> = UdpPacket::from(&sent_fragment);
+
+    match udp_packet.peek_meta().unwrap() {
+        PacketMeta::Fragment(12, 1, 3, FragmentMeta::Key) => {},
+        meta => panic!("unexpected packet meta {:?}", meta),
+    }
+    assert_eq!(udp_packet.peek_meta().unwrap().seq_id(), Some(12));
+    // the packet was not consumed and can still be fully decoded
+    assert!(udp_packet.compute_packet().is_ok());
+
+    let corrupted: &'static [u8] = &[0; 20];
+    assert_eq!(UdpPacket::new(corrupted).peek_meta().unwrap_err(), UdpPacketError::InvalidCrc);
+}
+
+#[test]
+fn udp_batch_pack_unpack() {
+    let small_packets: Vec<Box<[u8]>> = (0..10u32).map(|i| {
+        let p: Packet<Box<[u8]>> = Packet::End(i);
+        UdpPacket::from(&p).buffer
+    }).collect();
+    let batches = pack_batches(small_packets.clone());
+    assert_eq!(batches.len(), 1);
+    match batches.into_iter().next().unwrap().compute_packet().unwrap() {
+        Packet::Batch(payload) => {
+            let unpacked = unpack_batch(payload.as_ref()).unwrap();
+            assert_eq!(unpacked.len(), 10);
+            for (i, packet) in unpacked.into_iter().enumerate() {
+                assert_eq!(packet.as_bytes(), small_packets[i].as_ref());
+            }
+        },
+        p => panic!("expected a batch, got {:?}", p),
+    }
+
+    // a lone packet is not wrapped in a batch
+    let lone = pack_batches(vec!(small_packets[0].clone()));
+    assert_eq!(lone.len(), 1);
+    assert_eq!(lone[0].as_bytes(), small_packets[0].as_ref());
+
+    // fragments too big to be packed together are sent separately
+    let big_fragment = Fragment { seq_id: 1, frag_id: 0, frag_total: 0, frag_meta: FragmentMeta::Key, data: vec!(0u8; 1000) };
+    let big_packets: Vec<Box<[u8]>> = (0..3).map(|_| UdpPacket::from(&big_fragment).buffer).collect();
+    assert_eq!(pack_batches(big_packets).len(), 3);
+
+    assert_eq!(unpack_batch(&[0, 5, 1, 2]).unwrap_err(), UdpPacketError::NotBigEnough);
+}
+
+#[test]
+fn padded_fragment() {
+    let fragment = Fragment { seq_id: 4, frag_id: 0, frag_total: 0, frag_meta: FragmentMeta::KeyExpirable, data: vec![1u8, 2, 3] };
+    let udp_packet = UdpPacket::padded_fragment(&fragment, 100);
+    assert_eq!(udp_packet.as_bytes().len(), FRAG_DATA_START_BYTE + 100);
+    match udp_packet.compute_packet().unwrap() {
+        Packet::Fragment(received) => {
+            assert_eq!(received.frag_meta, FragmentMeta::KeyExpirable);
+            assert_eq!(received.data.as_ref(), &[1, 2, 3]);
+        },
+        _ => panic!("expected a fragment"),
+    }
+    // large enough already, nothing changes
+    assert_eq!(UdpPacket::padded_fragment(&fragment, 3).as_bytes(), UdpPacket::from(&fragment).as_bytes());
+
+    // a padding longer than the packet itself
+    let mut truncated = UdpPacket::padded_fragment(&fragment, 100).as_bytes()[..50].to_vec();
+    let crc = crc32_hash(&truncated[4..]);
+    BigEndian::write_u32(&mut truncated[0..4], crc);
+    assert_eq!(UdpPacket::new(truncated).compute_packet().unwrap_err(), UdpPacketError::InvalidPadding);
+}
+
+#[test]
+fn fragment_priority() {
+    let fragment = Fragment { seq_id: 4, frag_id: 0, frag_total: 0, frag_meta: FragmentMeta::TryReliable, data: vec![1u8, 2, 3] };
+    // Normal priority leaves the packet untouched
+    assert_eq!(UdpPacket::from(&fragment).with_frag_priority(0).as_bytes(), UdpPacket::from(&fragment).as_bytes());
+    for udp_packet in [UdpPacket::from(&fragment), UdpPacket::padded_fragment(&fragment, 100)] {
+        let udp_packet = udp_packet.with_frag_priority(6);
+        assert_eq!(udp_packet.frag_priority(), 6);
+        match udp_packet.compute_packet().unwrap() {
+            Packet::Fragment(received) => {
+                assert_eq!(received.frag_meta, FragmentMeta::TryReliable);
+                assert_eq!(received.data.as_ref(), &[1, 2, 3]);
+            },
+            _ => panic!("expected a fragment"),
+        }
+    }
+}
+
+#[cfg(feature = "wire")]
+#[test]
+fn decode_crafted_packets() {
+    let fragment = Fragment { seq_id: 12, frag_id: 1, frag_total: 2, frag_meta: FragmentMeta::Key, data: &b"abc"[..] };
+    let udp_packet = UdpPacket::from(&Packet::Fragment(fragment));
+    assert!(matches!(udp_packet.peek_meta(), Ok(PacketMeta::Fragment(12, 1, 2, FragmentMeta::Key))));
+    match udp_packet.decode().unwrap() {
+        Packet::Fragment(f) => assert_eq!((f.seq_id, f.frag_id, f.frag_total, f.data.as_ref()), (12, 1, 2, &b"abc"[..])),
+        p => panic!("unexpected packet {:?}", p),
+    }
+
+    let udp_packet = UdpPacket::from(&Packet::<&[u8]>::Abort(7));
+    assert_eq!(udp_packet.decode().unwrap(), Packet::Abort(7));
+    let mut bytes = udp_packet.as_bytes().to_vec();
+    bytes[5] ^= 1;
+    assert_eq!(UdpPacket::new(bytes).decode().unwrap_err(), UdpPacketError::InvalidCrc);
+}
@@ -4,16 +4,34 @@ use crate::misc::BoxedSlice;
 use std::collections::VecDeque;
 use crate::ack::Acks;
 use std::time::Instant;
+use crate::rudp::{ReceivedData, MessagePriority};
+use crate::discard::DiscardReason;
+use byteorder::{BigEndian, ByteOrder};
 
 #[derive(Debug)]
 pub (crate) enum ReceivedMessage {
     Ack(u32, BoxedSlice<u8>),
-    Data(u32, Box<[u8]>),
-    Syn,
-    SynAck,
+    Data(ReceivedData),
+    /// Holds the handshake payload of the application, which may be empty, and the payload size advertised by the remote
+    Syn(BoxedSlice<u8>, u32),
+    /// Holds the connection token, if the remote gave one, and the payload size advertised by the remote
+    SynAck(Option<u64>, u32),
     Heartbeat,
     End(u32),
     Abort(u32),
+    Probe(u32),
+    ProbeAck(u32),
+    /// An incomplete message was abandoned: (seq_id, received fragments, total fragments)
+    Dropped(u32, usize, usize),
+}
+
+/// Reads the connection token carried by the payload of SynAck and Migrate packets.
+pub (crate) fn read_token(payload: &[u8]) -> Option<u64> {
+    if payload.len() >= 8 {
+        Some(BigEndian::read_u64(&payload[0..8]))
+    } else {
+        None
+    }
 }
 
 #[derive(Debug)]
@@ -21,6 +39,9 @@ pub (crate) struct UdpPacketHandler {
     fragment_combiner: FragmentCombiner<BoxedSlice<u8>>,
     
     out_messages: VecDeque<ReceivedMessage>,
+
+    /// Packets with an invalid CRC are discarded when true
+    verify_crc: bool,
 }
 
 impl UdpPacketHandler {
@@ -28,16 +49,24 @@ impl UdpPacketHandler {
         UdpPacketHandler {
             fragment_combiner: FragmentCombiner::new(),
             out_messages: VecDeque::with_capacity(32),
+            verify_crc: true,
         }
     }
 
-    pub (crate) fn add_received_packet(&mut self, udp_packet: UdpPacket<Box<[u8]>>, now: Instant) {
-        match udp_packet.compute_packet() {
+    /// `on_discard` is called with the reason and the bytes of every packet we can't make sense of.
+    pub (crate) fn add_received_packet(&mut self, udp_packet: UdpPacket<Box<[u8]>>, now: Instant, on_discard: &mut dyn FnMut(DiscardReason, &[u8])) {
+        // only meaningful for the handshake packets
+        let advertised_payload_size = udp_packet.seq_id_field();
+        // only meaningful for fragments
+        let priority = MessagePriority::from_wire_value(udp_packet.frag_priority());
+        match udp_packet.compute_packet_or_buffer(self.verify_crc) {
             Ok(Packet::Fragment(f)) => {
                 log::trace!("received fragment {:?}", f);
                 self.fragment_combiner.push(f, now);
-                if let Some((seq_id, data)) = self.fragment_combiner.next_out_message() {
-                    self.out_messages.push_back(ReceivedMessage::Data(seq_id, data));
+                if let Some(mut received_data) = self.fragment_combiner.next_out_message() {
+                    // every fragment of a message carries the same priority
+                    received_data.priority = priority;
+                    self.out_messages.push_back(ReceivedMessage::Data(received_data));
                 }
             },
             Ok(Packet::Ack(seq_id, data)) => {
@@ -48,13 +77,24 @@ impl UdpPacketHandler {
                 log::trace!("received heartbeat");
                 self.out_messages.push_back(ReceivedMessage::Heartbeat);
             },
-            Ok(Packet::Syn) => {
+            Ok(Packet::Syn(payload)) => {
                 log::trace!("received Syn");
-                self.out_messages.push_back(ReceivedMessage::Syn);
+                self.out_messages.push_back(ReceivedMessage::Syn(payload, advertised_payload_size));
+            },
+            Ok(Packet::Rejoin(payload)) => {
+                // the token only matters to the server when creating the connection, past that it's a regular Syn
+                log::trace!("received Rejoin");
+                let token_len = ::std::cmp::min(8, payload.as_ref().len());
+                self.out_messages.push_back(ReceivedMessage::Syn(payload.with_added_strip(token_len), advertised_payload_size));
             },
-            Ok(Packet::SynAck) => {
+            Ok(Packet::SynAck(payload)) => {
                 log::trace!("received SynAck");
-                self.out_messages.push_back(ReceivedMessage::SynAck);
+                self.out_messages.push_back(ReceivedMessage::SynAck(read_token(payload.as_ref()), advertised_payload_size));
+            },
+            Ok(Packet::Migrate(_)) => {
+                // migrations are handled by the server before reaching an existing connection,
+                // this is most likely a duplicate of a Migrate already processed
+                log::trace!("ignoring Migrate for an existing connection");
             },
             Ok(Packet::End(last_seq_id)) => {
                 log::trace!("received End({})", last_seq_id);
@@ -64,17 +104,101 @@ impl UdpPacketHandler {
                 log::trace!("received Abort({})", last_seq_id);
                 self.out_messages.push_back(ReceivedMessage::Abort(last_seq_id));
             },
-            Err(_) => { /* ignore errors */ }
+            Ok(Packet::Probe(probe_id)) => {
+                log::trace!("received Probe({})", probe_id);
+                self.out_messages.push_back(ReceivedMessage::Probe(probe_id));
+            },
+            Ok(Packet::ProbeAck(probe_id)) => {
+                log::trace!("received ProbeAck({})", probe_id);
+                self.out_messages.push_back(ReceivedMessage::ProbeAck(probe_id));
+            },
+            Ok(Packet::Batch(payload)) => {
+                log::trace!("received batch {:?}", payload);
+                match unpack_batch(payload.as_ref()) {
+                    Ok(packets) => for packet in packets {
+                        if let Ok(PacketMeta::Batch) = packet.compute_packet_meta_with(self.verify_crc) {
+                            // a batch inside of a batch is never sent by us, ignore it
+                            log::trace!("ignoring nested batch");
+                            continue;
+                        }
+                        self.add_received_packet(packet, now, on_discard);
+                    },
+                    Err(e) => {
+                        log::trace!("received malformed batch: {:?}", e);
+                        on_discard(DiscardReason::MalformedBatch(e), payload.as_ref());
+                    },
+                }
+            },
+            Err((e, buffer)) => on_discard(DiscardReason::InvalidPacket(e), &buffer),
         };
     }
 
     /// Should be called every "tick", whatever you choose your tick to be.
     #[inline]
     pub (crate) fn tick(&mut self, now: Instant) -> Acks<Box<[u8]>> {
-        self.fragment_combiner.tick(now)
+        let acks = self.fragment_combiner.tick(now);
+        while let Some((seq_id, received, frag_total)) = self.fragment_combiner.next_dropped_set() {
+            self.out_messages.push_back(ReceivedMessage::Dropped(seq_id, received, frag_total));
+        }
+        acks
     }
     
     pub (crate) fn next_received_message(&mut self) -> Option<ReceivedMessage> {
         self.out_messages.pop_front()
     }
+
+    #[inline]
+    pub (crate) fn missing_fragment_count(&self, seq_id: u32) -> Option<usize> {
+        self.fragment_combiner.missing_fragment_count(seq_id)
+    }
+
+    #[inline]
+    pub (crate) fn is_receiving(&self, seq_id: u32) -> bool {
+        self.fragment_combiner.is_receiving(seq_id)
+    }
+
+    #[inline]
+    pub (crate) fn corrupted_sets(&self) -> CorruptedSetsStats {
+        self.fragment_combiner.corrupted_sets
+    }
+
+    #[inline]
+    pub (crate) fn ack_mode(&self) -> AckMode {
+        self.fragment_combiner.ack_mode
+    }
+
+    #[inline]
+    pub (crate) fn reassembly_limit(&self) -> Option<usize> {
+        self.fragment_combiner.reassembly_limit
+    }
+
+    #[inline]
+    pub (crate) fn reassembly_buffered_bytes(&self) -> usize {
+        self.fragment_combiner.buffered_bytes
+    }
+
+    #[inline]
+    pub (crate) fn verify_crc(&self) -> bool {
+        self.verify_crc
+    }
+
+    #[inline]
+    pub (crate) fn set_verify_crc(&mut self, verify_crc: bool) {
+        self.verify_crc = verify_crc;
+    }
+
+    #[inline]
+    pub (crate) fn set_ack_mode(&mut self, ack_mode: AckMode) {
+        self.fragment_combiner.ack_mode = ack_mode;
+    }
+
+    #[inline]
+    pub (crate) fn set_reassembly_limit(&mut self, reassembly_limit: Option<usize>) {
+        self.fragment_combiner.reassembly_limit = reassembly_limit;
+    }
+
+    #[inline]
+    pub (crate) fn reset_corrupted_sets(&mut self) {
+        self.fragment_combiner.corrupted_sets = CorruptedSetsStats::default();
+    }
 }
\ No newline at end of file